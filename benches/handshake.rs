@@ -0,0 +1,237 @@
+//! Benchmarks of the proxy handshakes driven against
+//! an in-process mock proxy server, measuring both
+//! handshakes per second and allocations per handshake
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use async_proxy::clients::socks4::Socks4NoIdent;
+use async_proxy::clients::socks5::{Destination, TcpNoAuth};
+use async_proxy::proxy::ProxyConstructor;
+use common::allocations::{allocations, CountingAllocator};
+use common::{timeouts, Exchange, MockServer, SOCKS4_SUCCESS, SOCKS5_SUCCESS};
+use criterion::measurement::{Measurement, ValueFormatter, WallTime};
+use criterion::{criterion_group, criterion_main, BenchmarkGroup, Criterion, Throughput};
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::mpsc;
+use std::thread;
+use tokio::net::TcpStream;
+use tokio::runtime::{Builder, Runtime};
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// The criterion measurement of
+/// allocations made by the current thread
+struct Allocations;
+
+impl Measurement for Allocations {
+    type Intermediate = usize;
+    type Value = usize;
+
+    fn start(&self) -> usize {
+        allocations()
+    }
+
+    fn end(&self, start: usize) -> usize {
+        allocations() - start
+    }
+
+    fn add(&self, v1: &usize, v2: &usize) -> usize {
+        v1 + v2
+    }
+
+    fn zero(&self) -> usize {
+        0
+    }
+
+    fn to_f64(&self, value: &usize) -> f64 {
+        *value as f64
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter {
+        &AllocationsFormatter
+    }
+}
+
+struct AllocationsFormatter;
+
+impl ValueFormatter for AllocationsFormatter {
+    fn scale_values(&self, _: f64, _: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+
+    fn scale_throughputs(&self, _: f64, _: &Throughput, _: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+
+    fn scale_for_machines(&self, _: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+}
+
+/// Creates a single-threaded runtime
+fn runtime() -> Runtime {
+    Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+/// Starts a mock server on its own thread,
+/// playing the script over every connection
+fn spawn_server(script: Vec<Exchange>) -> SocketAddr {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        runtime().block_on(async move {
+            let addr = MockServer::spawn_repeating(script).await;
+            sender.send(addr).unwrap();
+            std::future::pending::<()>().await
+        })
+    });
+
+    receiver.recv().unwrap()
+}
+
+/// Measures the handshake of a proxy constructor
+/// with the given measurement, leaving connecting
+/// to the mock server out of the measurement
+fn bench_handshake<M, C>(
+    group: &mut BenchmarkGroup<M>,
+    measurement: &M,
+    name: &str,
+    server: SocketAddr,
+    proxy: &C,
+) where
+    M: Measurement,
+    C: ProxyConstructor<Stream = TcpStream>,
+    C::ErrorKind: std::fmt::Debug,
+{
+    bench_connect(group, measurement, name, server, |stream| {
+        proxy.connect(stream)
+    });
+}
+
+/// Measures the handshake performed by `connect`
+/// (for instance, an unboxed one, not going through
+/// the trait) just as `bench_handshake` does
+fn bench_connect<M, F, Fut, T, E>(
+    group: &mut BenchmarkGroup<M>,
+    measurement: &M,
+    name: &str,
+    server: SocketAddr,
+    connect: F,
+) where
+    M: Measurement,
+    F: Fn(TcpStream) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: std::fmt::Debug,
+{
+    let mut runtime = runtime();
+
+    group.bench_function(name, |b| {
+        b.iter_custom(|iters| {
+            let mut total = measurement.zero();
+
+            for _ in 0..iters {
+                let stream = runtime.block_on(TcpStream::connect(server)).unwrap();
+
+                let start = measurement.start();
+                let result = runtime.block_on(connect(stream));
+                total = measurement.add(&total, &measurement.end(start));
+
+                result.unwrap();
+            }
+
+            total
+        })
+    });
+}
+
+fn handshakes(c: &mut Criterion) {
+    let socks4_server = spawn_server(vec![Exchange::new(9, &SOCKS4_SUCCESS)]);
+    let socks5_server = spawn_server(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ]);
+
+    let socks4 = Socks4NoIdent::new(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80), timeouts());
+    let socks5 = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+
+    let mut group = c.benchmark_group("handshakes");
+    group.throughput(Throughput::Elements(1));
+    bench_handshake(
+        &mut group,
+        &WallTime,
+        "socks4-no-ident",
+        socks4_server,
+        &socks4,
+    );
+    bench_handshake(
+        &mut group,
+        &WallTime,
+        "socks5-no-auth",
+        socks5_server,
+        &socks5,
+    );
+    group.finish();
+}
+
+fn allocations_per_handshake(c: &mut Criterion<Allocations>) {
+    let socks4_server = spawn_server(vec![Exchange::new(9, &SOCKS4_SUCCESS)]);
+    let socks5_server = spawn_server(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ]);
+
+    let socks4 = Socks4NoIdent::new(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80), timeouts());
+    let socks5 = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+
+    let mut group = c.benchmark_group("allocations");
+    bench_handshake(
+        &mut group,
+        &Allocations,
+        "socks4-no-ident",
+        socks4_server,
+        &socks4,
+    );
+    bench_handshake(
+        &mut group,
+        &Allocations,
+        "socks5-no-auth",
+        socks5_server,
+        &socks5,
+    );
+    // The hottest case (an IPv4 destination with no
+    // authentication) allocates nothing but the entries
+    // of the timers of the timeouts (one per read and
+    // write) once the trait is not gone through
+    bench_connect(
+        &mut group,
+        &Allocations,
+        "socks5-no-auth-unboxed",
+        socks5_server,
+        |stream| socks5.connect_unboxed(stream),
+    );
+    group.finish();
+}
+
+criterion_group!(time, handshakes);
+
+/// The group of allocation benchmarks, written out
+/// by hand since the number of allocations per handshake
+/// is constant, which the plots cannot be drawn for, and
+/// `criterion_group!` re-enables them from the arguments
+fn allocs() {
+    let mut criterion = Criterion::default()
+        .with_measurement(Allocations)
+        .configure_from_args()
+        .without_plots();
+
+    allocations_per_handshake(&mut criterion);
+}
+
+criterion_main!(time, allocs);
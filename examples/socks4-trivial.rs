@@ -1,7 +1,6 @@
 use async_proxy::clients::socks4::no_ident::Socks4NoIdent;
+use async_proxy::clients::socks4::ErrorKind;
 use async_proxy::general::ConnectionTimeouts;
-use async_proxy::proxy::ProxyConstructor;
-use tokio::net::TcpStream;
 use tokio::time::timeout;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use std::net::{SocketAddr, SocketAddrV4};
@@ -33,33 +32,31 @@ async fn main() {
     // Creating the socks4 constructor,
     // using which we will establish a connection
     // through proxy
-    let mut socks4_proxy = Socks4NoIdent::new(dest_addr, timeouts);
+    let socks4_proxy = Socks4NoIdent::new(dest_addr, timeouts);
     // Or this way:
-    //  let mut socks4_proxy: Socks4NoIdent = "52.20.16.20:30000 8000:8000:8000".parse().unwrap();
+    //  let socks4_proxy: Socks4NoIdent = "52.20.16.20:30000 8000:8000:8000".parse().unwrap();
 
     // Printing out information that we are starting
     // a connection to the Socks4 proxy server
     println!("Starting connection to the Socks4 proxy server `{}`", proxy_addr);
 
-    // Connecting to the stream and getting the readable and
-    // writable stream, or terminating the script if it is
-    // unable to connect
-    let stream = TcpStream::connect(proxy_addr)
-                           .await
-                           .expect("Unable to connect to the proxy server");
-
-
     // Printing out information that we are starting
     // a connection to the service through the proxy client
     println!("Starting connection to the destination `{}` throught socks4 proxy `{}`",
               dest_addr, proxy_addr);
 
-    // Connecting to the service through proxy
-    let mut stream = match socks4_proxy.connect(stream).await {
+    // Connecting to the proxy server and then to the service
+    // through it, getting the readable and writable stream,
+    // or terminating the script if it is unable to connect
+    let mut stream = match socks4_proxy.establish(proxy_addr).await {
         Ok(stream) => {
             println!("Successfully connected to the service through the proxy");
             stream
         },
+        Err(ErrorKind::ProxyUnreachable(e)) => {
+            println!("Cannot connect to the proxy server: {}", e);
+            exit(1);
+        },
         Err(e) => {
             println!("Cannot connect to the service: {}", e);
             exit(1);
@@ -0,0 +1,118 @@
+use async_proxy::clients::http::connect::HttpConnectStream;
+use async_proxy::clients::http::HttpConnect;
+use async_proxy::clients::socks5::{Destination, TcpNoAuth};
+use async_proxy::general::ConnectionTimeouts;
+use async_proxy::proxy::ProxyConstructor;
+use std::net::SocketAddr;
+use std::process::exit;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+#[tokio::main]
+async fn main() {
+    // The address of the HTTP proxy server,
+    // which is the only way out of the network
+    let http_proxy_addr: SocketAddr = "203.0.113.10:3128".parse().unwrap();
+
+    // The address of the Socks5 proxy server
+    // reached through the HTTP proxy server
+    let socks5_proxy_addr: SocketAddr = "198.51.100.20:1080".parse().unwrap();
+
+    // Setting up timeouts
+    let timeouts = ConnectionTimeouts::new(
+        // Connecting timeout
+        Duration::from_secs(8),
+        // Write timeout
+        Duration::from_secs(8),
+        // Read timeout
+        Duration::from_secs(8),
+    );
+
+    // The address of the destination service
+    // that we will be connecting to through proxy.
+    // (We used a tcp echo server from `http://tcpbin.org/`)
+    let dest_addr: SocketAddr = "52.20.16.20:30000".parse().unwrap();
+
+    // Creating the HTTP constructor, which
+    // tunnels to the Socks5 proxy server
+    let (destination, port) = Destination::from_socket_addr(socks5_proxy_addr);
+    let http_proxy = HttpConnect::new(destination, port, timeouts.clone());
+
+    // Creating the socks5 constructor, which speaks
+    // Socks5 over the tunnel of the HTTP proxy server
+    let (destination, port) = Destination::from_socket_addr(dest_addr);
+    let socks5_proxy: TcpNoAuth<HttpConnectStream> = TcpNoAuth::new(destination, port, timeouts);
+
+    // Printing out information that we are starting
+    // a connection to the HTTP proxy server
+    println!(
+        "Starting connection to the HTTP proxy server `{}`",
+        http_proxy_addr
+    );
+
+    // Connecting to the HTTP proxy server over tcp
+    let stream = TcpStream::connect(http_proxy_addr)
+        .await
+        .expect("Unable to connect to the HTTP proxy server");
+
+    // Tunneling to the Socks5 proxy server
+    let stream = match http_proxy.connect(stream).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            println!("Cannot tunnel to the socks5 proxy server: {}", e);
+            exit(1);
+        }
+    };
+
+    // Printing out information that we are starting
+    // a connection to the service through the proxy client
+    println!(
+        "Starting connection to the destination `{}` throught socks5 proxy `{}`",
+        dest_addr, socks5_proxy_addr
+    );
+
+    // Connecting to the service through
+    // the Socks5 proxy, inside the tunnel
+    let mut stream = match socks5_proxy.connect(stream).await {
+        Ok(stream) => {
+            println!("Successfully connected to the service through the proxies");
+            stream
+        }
+        Err(e) => {
+            println!("Cannot connect to the service: {}", e);
+            exit(1);
+        }
+    };
+
+    // Getting a message that will be sent to the service
+    println!("Please inter a message to be sent. Message: ");
+
+    let mut input = String::new();
+    std::io::stdin()
+        .read_line(&mut input)
+        .expect("Unable to read a line from stdin");
+
+    // Sending the message to the service
+    // with the timeout of 8 seconds
+    let future = stream.write_all(input.as_bytes());
+    let future = timeout(Duration::from_secs(8), future);
+    future
+        .await
+        .expect("Timeout of 8 seconds reached")
+        .expect("Unable to send the message");
+
+    // Receiving a message from the service
+    // with the timeout of 8 seconds
+    let future = stream.read_to_string(&mut input);
+    let future = timeout(Duration::from_secs(8), future);
+    future
+        .await
+        .expect("Timeout of 8 seconds reached")
+        .expect("Unable to receive a string from the service");
+
+    // Successfully received a message.
+    // Printing it out
+    println!("Received message from the service: {}", input);
+}
@@ -0,0 +1,123 @@
+use async_proxy::clients::socks5::{Destination, TlsNoAuth};
+use async_proxy::general::ConnectionTimeouts;
+use async_proxy::proxy::ProxyConstructor;
+use std::net::SocketAddr;
+use std::process::exit;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_rustls::rustls::ClientConfig;
+use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::TlsConnector;
+
+#[tokio::main]
+async fn main() {
+    // The address of the proxy server that requires
+    // a TLS connection to be established before speaking Socks5
+    let proxy_addr: SocketAddr = "203.0.113.10:1080".parse().unwrap();
+
+    // The host name of the proxy server, which
+    // its TLS certificate is verified against
+    const PROXY_HOST: &str = "socks.example.com";
+
+    // Setting up timeouts
+    let timeouts = ConnectionTimeouts::new(
+        // Connecting timeout
+        Duration::from_secs(8),
+        // Write timeout
+        Duration::from_secs(8),
+        // Read timeout
+        Duration::from_secs(8),
+    );
+
+    // The address of the destination service
+    // that we will be connecting to through proxy.
+    // (We used a tcp echo server from `http://tcpbin.org/`)
+    let dest_addr: SocketAddr = "52.20.16.20:30000".parse().unwrap();
+
+    // Setting up the TLS client configuration
+    // with the Mozilla's root certificates
+    let mut config = ClientConfig::new();
+    config
+        .root_store
+        .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    let connector = TlsConnector::from(Arc::new(config));
+
+    // Creating the socks5 constructor,
+    // which speaks Socks5 over a TLS stream
+    let (destination, port) = Destination::from_socket_addr(dest_addr);
+    let socks5_proxy: TlsNoAuth =
+        TlsNoAuth::new(destination, port, timeouts);
+
+    // Printing out information that we are starting
+    // a connection to the Socks5 proxy server
+    println!(
+        "Starting connection to the socks5 proxy server `{}`",
+        proxy_addr
+    );
+
+    // Connecting to the proxy server over tcp
+    let stream = TcpStream::connect(proxy_addr)
+        .await
+        .expect("Unable to connect to the proxy server");
+
+    // Wrapping the connection to the proxy server
+    // in TLS, so that the Socks5 protocol data
+    // is sent over an encrypted channel
+    let proxy_name = DNSNameRef::try_from_ascii_str(PROXY_HOST).expect("Invalid proxy host name");
+    let stream = connector
+        .connect(proxy_name, stream)
+        .await
+        .expect("Unable to establish a TLS connection to the proxy server");
+
+    // Printing out information that we are starting
+    // a connection to the service through the proxy client
+    println!(
+        "Starting connection to the destination `{}` throught socks5 proxy over TLS",
+        dest_addr
+    );
+
+    // Connecting to the service through proxy
+    let mut stream = match socks5_proxy.connect(stream).await {
+        Ok(stream) => {
+            println!("Successfully connected to the service through the proxy");
+            stream
+        }
+        Err(e) => {
+            println!("Cannot connect to the service: {}", e);
+            exit(1);
+        }
+    };
+
+    // Getting a message that will be sent to the service
+    println!("Please inter a message to be sent. Message: ");
+
+    let mut input = String::new();
+    std::io::stdin()
+        .read_line(&mut input)
+        .expect("Unable to read a line from stdin");
+
+    // Sending the message to the service
+    // with the timeout of 8 seconds
+    let future = stream.write_all(input.as_bytes());
+    let future = timeout(Duration::from_secs(8), future);
+    future
+        .await
+        .expect("Timeout of 8 seconds reached")
+        .expect("Unable to send the message");
+
+    // Receiving a message from the service
+    // with the timeout of 8 seconds
+    let future = stream.read_to_string(&mut input);
+    let future = timeout(Duration::from_secs(8), future);
+    future
+        .await
+        .expect("Timeout of 8 seconds reached")
+        .expect("Unable to receive a string from the service");
+
+    // Successfully received a message.
+    // Printing it out
+    println!("Received message from the service: {}", input);
+}
@@ -0,0 +1,98 @@
+use async_proxy::clients::socks5::{Destination, TcpNoAuth};
+use async_proxy::general::ConnectionTimeouts;
+use async_proxy::proxy::ProxyConstructor;
+use std::net::SocketAddr;
+use std::process::exit;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::time::timeout;
+
+#[tokio::main]
+async fn main() {
+    // The path of the Unix domain socket
+    // that the proxy server listens on
+    const PROXY_PATH: &str = "/var/run/socks.sock";
+
+    // Setting up timeouts
+    let timeouts = ConnectionTimeouts::new(
+        // Connecting timeout
+        Duration::from_secs(8),
+        // Write timeout
+        Duration::from_secs(8),
+        // Read timeout
+        Duration::from_secs(8),
+    );
+
+    // The address of the destination service
+    // that we will be connecting to through proxy.
+    // (We used a tcp echo server from `http://tcpbin.org/`)
+    let dest_addr: SocketAddr = "52.20.16.20:30000".parse().unwrap();
+
+    // Creating the socks5 constructor,
+    // which speaks Socks5 over a Unix stream
+    let (destination, port) = Destination::from_socket_addr(dest_addr);
+    let socks5_proxy: TcpNoAuth<UnixStream> = TcpNoAuth::new(destination, port, timeouts);
+
+    // Printing out information that we are starting
+    // a connection to the Socks5 proxy server
+    println!(
+        "Starting connection to the socks5 proxy server `{}`",
+        PROXY_PATH
+    );
+
+    // Connecting to the proxy server over
+    // the Unix domain socket
+    let stream = UnixStream::connect(PROXY_PATH)
+        .await
+        .expect("Unable to connect to the proxy server");
+
+    // Printing out information that we are starting
+    // a connection to the service through the proxy client
+    println!(
+        "Starting connection to the destination `{}` throught socks5 proxy `{}`",
+        dest_addr, PROXY_PATH
+    );
+
+    // Connecting to the service through proxy
+    let mut stream = match socks5_proxy.connect(stream).await {
+        Ok(stream) => {
+            println!("Successfully connected to the service through the proxy");
+            stream
+        }
+        Err(e) => {
+            println!("Cannot connect to the service: {}", e);
+            exit(1);
+        }
+    };
+
+    // Getting a message that will be sent to the service
+    println!("Please inter a message to be sent. Message: ");
+
+    let mut input = String::new();
+    std::io::stdin()
+        .read_line(&mut input)
+        .expect("Unable to read a line from stdin");
+
+    // Sending the message to the service
+    // with the timeout of 8 seconds
+    let future = stream.write_all(input.as_bytes());
+    let future = timeout(Duration::from_secs(8), future);
+    future
+        .await
+        .expect("Timeout of 8 seconds reached")
+        .expect("Unable to send the message");
+
+    // Receiving a message from the service
+    // with the timeout of 8 seconds
+    let future = stream.read_to_string(&mut input);
+    let future = timeout(Duration::from_secs(8), future);
+    future
+        .await
+        .expect("Timeout of 8 seconds reached")
+        .expect("Unable to receive a string from the service");
+
+    // Successfully received a message.
+    // Printing it out
+    println!("Received message from the service: {}", input);
+}
@@ -1,7 +1,7 @@
 use async_proxy::clients::socks5::{no_auth::TcpNoAuth, Destination};
 use async_proxy::general::ConnectionTimeouts;
 use async_proxy::proxy::ProxyConstructor;
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::SocketAddr;
 use std::process::exit;
 use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -28,15 +28,13 @@ async fn main() {
     // The address of the destination service
     // that we will be connecting to through proxy.
     // (We used a tcp echo server from `http://tcpbin.org/`)
-    let dest_ipaddr: Ipv4Addr = Ipv4Addr::new(52, 20, 16, 20);
-
-    // The port of the destination service
-    const DEST_PORT: u16 = 30_000;
+    let dest_addr: SocketAddr = "52.20.16.20:30000".parse().unwrap();
 
     // Creating the socks5 constructor,
     // using which we will establish a connection
     // through proxy
-    let mut socks5_proxy = TcpNoAuth::new(Destination::Ipv4Addr(dest_ipaddr), DEST_PORT, timeouts);
+    let (destination, port) = Destination::from_socket_addr(dest_addr);
+    let socks5_proxy = TcpNoAuth::new(destination, port, timeouts);
 
     // You can use socks5_proxy.with_authentication() for select `Username/Password` auth method 
     // socks5_proxy.with_authentication(username, password);
@@ -58,8 +56,8 @@ async fn main() {
     // Printing out information that we are starting
     // a connection to the service through the proxy client
     println!(
-        "Starting connection to the destination `{}:{}` throught socks5 proxy `{}`",
-        dest_ipaddr, DEST_PORT, proxy_addr
+        "Starting connection to the destination `{}` throught socks5 proxy `{}`",
+        dest_addr, proxy_addr
     );
 
     // Connecting to the service through proxy
@@ -4,7 +4,7 @@
 /// 
 /// # Example
 /// 
-/// ```
+/// ```no_run
 /// use async_proxy::clients::socks4::no_ident::Socks4NoIdent;
 /// use async_proxy::general::ConnectionTimeouts;
 /// use async_proxy::proxy::ProxyConstructor;
@@ -68,7 +68,7 @@ pub mod socks4;
 /// 
 /// # Example
 /// 
-/// ```
+/// ```no_run
 /// use async_proxy::clients::socks5::{
 ///     Destination, no_auth::TcpNoAuth
 /// };
@@ -109,8 +109,8 @@ pub mod socks4;
 ///     // Creating the socks5 constructor,
 ///     // using which we will establish a connection
 ///     // through proxy
-///     let mut socks5_proxy = TcpNoAuth::new(Destination::Ipv4Addr(dest_ipaddr),
-///                                           DEST_PORT, timeouts);
+///     let socks5_proxy = TcpNoAuth::new(Destination::Ipv4Addr(dest_ipaddr),
+///                                       DEST_PORT, timeouts);
 /// 
 ///     // Connecting to the stream and getting the readable and
 ///     // writable stream, or terminating the script if it is
@@ -134,5 +134,15 @@ pub mod socks4;
 ///     // -- using `stream` -- //
 /// }
 /// ```
+pub mod socks5;
+
+/// Module contains implementations
+/// of the HTTP proxy protocols
+/// (HTTP CONNECT tunneling)
+pub mod http;
 
-pub mod socks5;
\ No newline at end of file
+/// Module contains helpers establishing
+/// a TLS connection to a destination
+/// through an already connected proxy stream
+#[cfg(feature = "tls")]
+pub mod tls;
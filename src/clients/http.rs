@@ -0,0 +1,86 @@
+use crate::general::{ErrorCategory, TimeoutPhase};
+use std::fmt;
+
+/// Holds implementation of the HTTP CONNECT
+/// proxy protocol (tunneling through an HTTP proxy)
+pub mod connect;
+
+pub use connect::HttpConnect;
+
+/// Represents an HTTP proxy protocol
+/// error that can occur when connecting
+/// to a destination
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// Indicates that an error occured
+    /// during a native I/O operation,
+    /// such as writing to or reading from
+    /// a stream
+    IOError(std::io::Error),
+    /// Indicates that a bad (not readable as
+    /// an HTTP response) buffer is received
+    BadBuffer,
+    /// Indicates that the headers of the response
+    /// are longer than the client is ready to read
+    HeadersTooLong,
+    /// Indicates that the server looks like
+    /// a server of another proxy protocol
+    /// (for instance, a Socks server)
+    WrongProxyType,
+    /// Indicates that the server requires
+    /// an authentication (the status 407),
+    /// or has not accepted the credentials
+    ProxyAuthenticationRequired,
+    /// Indicates that the server has replied
+    /// with a status other than a successful one
+    RequestFailed(u16),
+    /// Indicates that a timeouts has been reached
+    /// when connecting to a service, in the phase carried
+    OperationTimeoutReached(TimeoutPhase)
+}
+
+impl ErrorKind {
+    /// Returns the category of the error, telling
+    /// a refused request from a broken server.
+    ///
+    /// A client error status (4xx) and the statuses
+    /// telling that the destination cannot be reached
+    /// (502 and 504) mean a denied request, while any other
+    /// server error status (5xx) means a failure of the server
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            ErrorKind::RequestFailed(400..=499) | ErrorKind::RequestFailed(502)
+            | ErrorKind::RequestFailed(504) => ErrorCategory::PolicyDenied,
+            ErrorKind::IOError(_) | ErrorKind::RequestFailed(500..=599)
+                => ErrorCategory::NetworkError,
+            ErrorKind::BadBuffer | ErrorKind::HeadersTooLong | ErrorKind::WrongProxyType
+            | ErrorKind::RequestFailed(_) => ErrorCategory::ProtocolError,
+            ErrorKind::OperationTimeoutReached(_) => ErrorCategory::Timeout,
+            ErrorKind::ProxyAuthenticationRequired => ErrorCategory::AuthFailure
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ErrorKind::IOError(e) 
+                => f.write_str(&format!("I/O error: {}", e)),
+            ErrorKind::BadBuffer => f.write_str("bad buffer has been received"),
+            ErrorKind::HeadersTooLong => f.write_str("response headers are too long"),
+            ErrorKind::WrongProxyType
+                => f.write_str("the server does not look like an HTTP proxy server"),
+            ErrorKind::ProxyAuthenticationRequired
+                => f.write_str("proxy authentication required"),
+            ErrorKind::RequestFailed(status)
+                => f.write_str(&format!("request failed with the status {}", status)),
+            ErrorKind::OperationTimeoutReached(phase) => f.write_str(&phase.to_string())
+        }
+    }
+}
+
+impl From<std::io::Error> for ErrorKind {
+    fn from(e: std::io::Error) -> ErrorKind {
+        ErrorKind::IOError(e)
+    }
+}
@@ -0,0 +1,663 @@
+use crate::clients::http::ErrorKind;
+use crate::clients::socks5::{Destination, Port};
+use crate::general::{
+    self, observed, optional_timeout, record_error, trace_bytes, trace_redacted_bytes,
+    ConnectionTimeouts, IOStream, Observer, TimeoutPhase,
+};
+use crate::proxy::{self, ProxyConstructor, ReadHalf, WriteHalf};
+use bytes::{Buf, BufMut};
+use core::task::{Context, Poll};
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ops::Range;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, RawSocket};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// The maximum length of the response
+/// headers the client is ready to read
+const MAX_HEADERS_LEN: usize = 8 * 1024;
+
+/// The terminator of the response headers
+const HEADERS_TERMINATOR: &[u8] = b"\r\n\r\n";
+
+/// The number of bytes the tunnel is
+/// refilled with when read as buffered
+const FILL_LEN: usize = 8 * 1024;
+
+/// Represents the proxy constructor
+/// that creates a `HttpConnectStream`
+/// proxy stream when connected.
+///
+/// Just as the Socks5 one, the constructor
+/// is generic over the stream `S` it operates on,
+/// which is the plain `TcpStream` by default
+pub struct HttpConnect<'a, S = TcpStream> {
+    /// Represents an address of
+    /// a service to what user
+    /// wants to connect through a proxy
+    destination: Destination,
+    /// The port of the destination service
+    port: u16,
+    /// The timeout set
+    timeouts: ConnectionTimeouts,
+    /// The credentials for the `Basic`
+    /// authentication, if required
+    credentials: Option<(Cow<'a, str>, Cow<'a, str>)>,
+    /// The observer of the connection
+    /// attempts, by default there is none
+    observer: Option<Arc<dyn Observer<ErrorKind>>>,
+    /// Marks the type of a stream
+    /// the constructor operates on
+    _stream: PhantomData<fn() -> S>,
+}
+
+/// The actual type that represents
+/// the HTTP CONNECT tunnel.
+/// Contains a stream that operates on
+/// and the bytes of the tunnel read along
+/// with the response headers, if any
+pub struct HttpConnectStream<S = TcpStream> {
+    /// The stream on which
+    /// the client operates on
+    wrapped_stream: S,
+    /// The bytes that followed the
+    /// response headers in the same read
+    buffered: Vec<u8>,
+    /// The number of the buffered
+    /// bytes that have been read
+    position: usize,
+    /// The timeouts of the constructor,
+    /// used by `read_timed` and `write_timed`
+    timeouts: ConnectionTimeouts,
+    /// The kind of the last I/O error
+    /// of the tunnel, if any
+    last_error: Option<io::ErrorKind>,
+}
+
+impl<'a, S> HttpConnect<'a, S> {
+    pub fn new(
+        destination: Destination,
+        port: impl Into<Port>,
+        timeouts: ConnectionTimeouts,
+    ) -> HttpConnect<'a, S> {
+        HttpConnect {
+            destination,
+            port: port.into().0,
+            timeouts,
+            credentials: None,
+            observer: None,
+            _stream: PhantomData,
+        }
+    }
+
+    /// Uses the `Basic` authentication
+    /// (the `Proxy-Authorization` header)
+    pub fn with_authentication(&mut self, username: &'a str, password: &'a str) {
+        self.credentials = Some((Cow::Borrowed(username), Cow::Borrowed(password)));
+    }
+
+    /// Sets the observer of the connection attempts,
+    /// which is told when a handshake starts and
+    /// how it ends, along with the time it has taken
+    pub fn with_observer(&mut self, observer: Arc<dyn Observer<ErrorKind>>) {
+        self.observer = Some(observer);
+    }
+
+    /// Returns the destination of a service
+    /// we are connecting to through proxy
+    pub fn destination(&self) -> &Destination {
+        &self.destination
+    }
+
+    /// Returns the port of the destination service
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Returns the timeouts set
+    pub fn timeouts(&self) -> &ConnectionTimeouts {
+        &self.timeouts
+    }
+
+    /// Builds the CONNECT request, returning it
+    /// along with the range of the encoded credentials
+    /// in it (empty if there are none)
+    fn request(&self) -> (Vec<u8>, Range<usize>) {
+        let authority = format!("{}:{}", self.destination, self.port);
+        let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+        let mut credentials_range = 0..0;
+
+        if let Some((username, password)) = &self.credentials {
+            let credentials = format!("{}:{}", username, password);
+            request.push_str("Proxy-Authorization: Basic ");
+            let start = request.len();
+            request.push_str(&base64(credentials.as_bytes()));
+            credentials_range = start..request.len();
+            request.push_str("\r\n");
+        }
+
+        request.push_str("\r\n");
+        (request.into_bytes(), credentials_range)
+    }
+}
+
+impl<'a, S> HttpConnect<'a, S>
+where
+    S: IOStream + Send,
+{
+    /// Reads the response headers and returns
+    /// the bytes that followed them in the same read
+    async fn read_headers(&self, stream: &mut S) -> Result<Vec<u8>, ErrorKind> {
+        let mut response = Vec::new();
+        let mut chunk = [0; 1024];
+
+        loop {
+            // Reading the next part of the response
+            let future = stream.read(&mut chunk);
+            let future = optional_timeout(self.timeouts.read_timeout, future);
+            let read_bytes = future
+                .await
+                .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Read))??;
+            trace_bytes("received", &chunk[..read_bytes]);
+
+            // The server has closed the connection
+            // before the end of the headers
+            if read_bytes == 0 {
+                return Err(ErrorKind::BadBuffer);
+            }
+
+            // The terminator may be split
+            // between the previous part and this one
+            let search_from = response.len().saturating_sub(HEADERS_TERMINATOR.len() - 1);
+            response.extend_from_slice(&chunk[..read_bytes]);
+
+            // A Socks4 reply starts with 0x00, while
+            // a Socks5 one starts with 0x05, so that
+            // we are talking to a wrong proxy server
+            if response[0] == 0x00 || response[0] == 0x05 {
+                return Err(ErrorKind::WrongProxyType);
+            }
+
+            if let Some(end) = response[search_from..]
+                .windows(HEADERS_TERMINATOR.len())
+                .position(|window| window == HEADERS_TERMINATOR)
+            {
+                // Parsing the status and keeping
+                // the rest of the response
+                let end = search_from + end + HEADERS_TERMINATOR.len();
+                let rest = response.split_off(end);
+                check_status(&response)?;
+
+                return Ok(rest);
+            }
+
+            if response.len() > MAX_HEADERS_LEN {
+                return Err(ErrorKind::HeadersTooLong);
+            }
+        }
+    }
+}
+
+/// Parses the status line of the response
+/// and returns an error if the status is not
+/// a successful (2xx) one
+fn check_status(headers: &[u8]) -> Result<(), ErrorKind> {
+    // The status line looks like:
+    //   HTTP/1.1 200 Connection established
+    let mut parts = headers.splitn(3, |byte| *byte == b' ');
+
+    let version = parts.next().ok_or(ErrorKind::BadBuffer)?;
+    if !version.starts_with(b"HTTP/1.") {
+        return Err(ErrorKind::BadBuffer);
+    }
+
+    let status = parts
+        .next()
+        .and_then(|status| std::str::from_utf8(status).ok())
+        .and_then(|status| status.trim_end().parse::<u16>().ok())
+        .ok_or(ErrorKind::BadBuffer)?;
+
+    match status {
+        200..=299 => Ok(()),
+        407 => Err(ErrorKind::ProxyAuthenticationRequired),
+        status => Err(ErrorKind::RequestFailed(status)),
+    }
+}
+
+/// Encodes the input with the standard
+/// base64 alphabet (with padding)
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    // `usize::div_ceil` is too recent
+    // for the older toolchains supported
+    #[allow(clippy::manual_div_ceil)]
+    let mut output = String::with_capacity((input.len() + 2) / 3 * 4);
+
+    for group in input.chunks(3) {
+        // Packing up to 3 bytes into 24 bits
+        let bits = group.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+            bits | (*byte as u32) << (16 - 8 * i)
+        });
+
+        // Each 6 bits make up a character, while the
+        // characters missing in the last group are padded
+        for i in 0..4 {
+            if i <= group.len() {
+                output.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+
+    output
+}
+
+impl<'a> HttpConnect<'a, TcpStream> {
+    /// Establishes a tunnel on the tcp stream just
+    /// as `connect` does, but with `TCP_NODELAY` set
+    /// during the handshake (a latency-sensitive request
+    /// and response), restoring the prior setting once
+    /// the tunnel has been established
+    pub async fn connect_with_nodelay(
+        &self,
+        stream: TcpStream,
+    ) -> Result<HttpConnectStream<TcpStream>, ErrorKind> {
+        let nodelay = stream.nodelay()?;
+        stream.set_nodelay(true)?;
+
+        let stream = self.connect(stream).await?;
+        stream.get_ref().set_nodelay(nodelay)?;
+
+        Ok(stream)
+    }
+}
+
+impl<'a, S> HttpConnect<'a, S>
+where
+    S: IOStream + Send,
+{
+    /// Establishes a tunnel on the stream just as
+    /// `connect` does and then writes the initial
+    /// payload (for instance, an HTTP request) to it,
+    /// saving a separate call for protocols where
+    /// the client speaks first.
+    ///
+    /// Note that it is not a real TCP Fast Open: the
+    /// payload is written right after the response,
+    /// within the write timeout set
+    pub async fn connect_with_initial_data(
+        &self,
+        stream: S,
+        initial: &[u8],
+    ) -> Result<HttpConnectStream<S>, ErrorKind> {
+        let mut stream = self.connect(stream).await?;
+
+        // Writing the initial payload to the tunnel
+        let future = stream.wrapped_stream.write_all(initial);
+        let future = optional_timeout(self.timeouts.write_timeout, future);
+        future
+            .await
+            .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Write))??;
+
+        Ok(stream)
+    }
+
+    /// Establishes a tunnel on the stream
+    /// just as `connect` does, but returns the future
+    /// itself instead of a boxed one (which the trait
+    /// methods return), saving an allocation per
+    /// connection where the trait is not needed
+    pub async fn connect_unboxed(&self, stream: S) -> Result<HttpConnectStream<S>, ErrorKind> {
+        observed(self.observer.as_deref(), self.handshake(stream)).await
+    }
+
+    /// Performs the handshake over the stream,
+    /// which is what `connect` observes
+    async fn handshake(&self, mut stream: S) -> Result<HttpConnectStream<S>, ErrorKind> {
+        // Sending the CONNECT request
+        let (request, credentials_range) = self.request();
+        let future = stream.write_all(&request);
+        let future = optional_timeout(self.timeouts.write_timeout, future);
+        future
+            .await
+            .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Write))??;
+        trace_redacted_bytes("sent", &request, credentials_range);
+
+        // Reading and analyzing the response
+        // and returning an HTTP proxy client
+        // instance if everything was successful
+        let buffered = self.read_headers(&mut stream).await?;
+
+        Ok(HttpConnectStream {
+            wrapped_stream: stream,
+            buffered,
+            position: 0,
+            timeouts: self.timeouts.clone(),
+            last_error: None,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, S> ProxyConstructor for HttpConnect<'a, S>
+where
+    S: IOStream + Send,
+{
+    type Stream = S;
+    type ProxyStream = HttpConnectStream<S>;
+    type ErrorKind = ErrorKind;
+
+    async fn connect(&self, stream: Self::Stream) -> Result<Self::ProxyStream, Self::ErrorKind> {
+        self.connect_unboxed(stream).await
+    }
+
+    async fn send_payload(
+        &self,
+        buf: &mut Vec<u8>,
+        stream: &mut Self::Stream,
+    ) -> Result<usize, Self::ErrorKind> {
+        // Writing the payload to the server
+        let future = stream.write_all(buf);
+        let future = optional_timeout(self.timeouts.write_timeout, future);
+        future
+            .await
+            .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Write))??;
+        trace_bytes("sent", buf);
+
+        // Reading a reply from the server
+        let future = stream.read(buf);
+        let future = optional_timeout(self.timeouts.read_timeout, future);
+        let read_bytes = future
+            .await
+            .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Read))??;
+        trace_bytes("received", &buf[..read_bytes]);
+
+        Ok(read_bytes)
+    }
+}
+
+impl<S> HttpConnectStream<S> {
+    /// Returns a shared reference
+    /// to the underlying stream
+    pub fn get_ref(&self) -> &S {
+        &self.wrapped_stream
+    }
+
+    /// Returns a mutable reference
+    /// to the underlying stream.
+    ///
+    /// Note that reading from it directly skips
+    /// the buffered bytes of the tunnel, if any
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.wrapped_stream
+    }
+
+    /// Consumes the proxy stream and returns
+    /// the underlying stream along with the bytes
+    /// of the tunnel read with the response headers,
+    /// which have not been returned yet, so that
+    /// reclaiming the stream loses no data
+    pub fn into_inner_with_buffered(mut self) -> (S, Vec<u8>) {
+        let buffered = self.buffered.split_off(self.position);
+        (self.wrapped_stream, buffered)
+    }
+}
+
+impl<S: IOStream> HttpConnectStream<S> {
+    /// Shuts down the tunnel within the given timeout,
+    /// just as `general::shutdown_with_timeout` does
+    pub async fn shutdown_with_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        general::shutdown_with_timeout(self, timeout).await
+    }
+
+    /// Reads what the destination sends first,
+    /// just as `general::read_initial` does
+    pub async fn read_initial(&mut self, max: usize) -> io::Result<Vec<u8>> {
+        general::read_initial(self, max).await
+    }
+
+    /// Returns the timeouts of the constructor
+    /// the stream has been established with
+    pub fn timeouts(&self) -> &ConnectionTimeouts {
+        &self.timeouts
+    }
+
+    /// Returns the kind of the last I/O error the
+    /// tunnel has failed with, if any, just as
+    /// `TcpNoAuthStream::last_error_kind` does
+    pub fn last_error_kind(&self) -> Option<io::ErrorKind> {
+        self.last_error
+    }
+
+    /// Reads from the tunnel within the read timeout
+    /// of the constructor (with no limit if there is
+    /// none), so that the timeouts bound the whole
+    /// lifetime of the connection and not just the
+    /// handshake. Fails with `io::ErrorKind::TimedOut`
+    /// if the timeout is reached
+    pub async fn read_timed(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_timeout = self.timeouts.read_timeout;
+        general::read_timed(self, buf, read_timeout).await
+    }
+
+    /// Writes to the tunnel within the write timeout
+    /// of the constructor, just as `read_timed` reads,
+    /// returning the number of bytes written.
+    /// Fails with `io::ErrorKind::TimedOut`
+    /// if the timeout is reached
+    pub async fn write_timed(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let write_timeout = self.timeouts.write_timeout;
+        general::write_timed(self, buf, write_timeout).await
+    }
+}
+
+impl<S: IOStream> AsyncRead for HttpConnectStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = Pin::into_inner(self);
+
+        // Handing the buffered bytes first,
+        // since they precede anything the
+        // stream can return
+        if this.position < this.buffered.len() {
+            let buffered = &this.buffered[this.position..];
+            let len = buffered.len().min(buf.len());
+            buf[..len].copy_from_slice(&buffered[..len]);
+            this.position += len;
+
+            return Poll::Ready(Ok(len));
+        }
+
+        let poll = Pin::new(&mut this.wrapped_stream).poll_read(cx, buf);
+
+        record_error(&mut this.last_error, poll)
+    }
+
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [MaybeUninit<u8>]) -> bool {
+        // The buffered bytes are only ever copied
+        // into the buffer, never read from it
+        self.wrapped_stream.prepare_uninitialized_buffer(buf)
+    }
+
+    fn poll_read_buf<B: BufMut>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut B,
+    ) -> Poll<io::Result<usize>>
+    where
+        Self: Sized,
+    {
+        let this = Pin::into_inner(self);
+
+        // Handing the buffered bytes first,
+        // just as `poll_read` does
+        if this.position < this.buffered.len() {
+            let buffered = &this.buffered[this.position..];
+            let len = buffered.len().min(buf.remaining_mut());
+            buf.put_slice(&buffered[..len]);
+            this.position += len;
+
+            return Poll::Ready(Ok(len));
+        }
+
+        let poll = Pin::new(&mut this.wrapped_stream).poll_read_buf(cx, buf);
+
+        record_error(&mut this.last_error, poll)
+    }
+}
+
+/// The tunnel is a buffered reader itself, so that
+/// line-oriented protocols (`read_line`, `read_until`)
+/// are spoken through it with no `BufReader`, starting
+/// with the bytes read along with the response headers
+impl<S: IOStream> AsyncBufRead for HttpConnectStream<S> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = Pin::into_inner(self);
+
+        // Refilling the buffer from the stream
+        // once all the buffered bytes are consumed
+        if this.position >= this.buffered.len() {
+            this.buffered.resize(FILL_LEN, 0);
+            this.position = 0;
+
+            match Pin::new(&mut this.wrapped_stream).poll_read(cx, &mut this.buffered) {
+                Poll::Ready(Ok(read)) => this.buffered.truncate(read),
+                other => {
+                    this.buffered.clear();
+                    let other = record_error(&mut this.last_error, other);
+                    return other.map_ok(|_| &[][..]);
+                }
+            }
+        }
+
+        Poll::Ready(Ok(&this.buffered[this.position..]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = Pin::into_inner(self);
+        this.position = (this.position + amt).min(this.buffered.len());
+    }
+}
+
+impl<S: IOStream> AsyncWrite for HttpConnectStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_write(cx, buf);
+        record_error(&mut this.last_error, poll)
+    }
+
+    fn poll_write_buf<B: Buf>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut B,
+    ) -> Poll<Result<usize, io::Error>>
+    where
+        Self: Sized,
+    {
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_write_buf(cx, buf);
+        record_error(&mut this.last_error, poll)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_flush(cx);
+        record_error(&mut this.last_error, poll)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_shutdown(cx);
+        record_error(&mut this.last_error, poll)
+    }
+}
+
+impl HttpConnectStream<TcpStream> {
+    /// Splits the proxy stream into the owned
+    /// read and write halves, which can be used
+    /// concurrently (for instance, from two tasks).
+    ///
+    /// The bytes of the tunnel read along with
+    /// the response headers and not returned yet
+    /// are returned by the read half first
+    pub fn into_split(self) -> (ReadHalf, WriteHalf) {
+        proxy::split(self.wrapped_stream, self.buffered, self.position)
+    }
+
+    /// Checks whether the tunnel is still alive (for
+    /// instance, before reusing a pooled connection)
+    /// without blocking and without consuming any data.
+    /// The proxy having closed the connection
+    /// (or an error) means the tunnel is dead,
+    /// unless there are buffered bytes to be read
+    pub fn is_alive(&mut self) -> bool {
+        self.position < self.buffered.len() || general::is_alive(&mut self.wrapped_stream)
+    }
+
+    /// Sets the `SO_LINGER` option of the underlying
+    /// tcp stream, bounding how long closing it waits
+    /// for the unsent data to be transmitted
+    pub fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
+        self.wrapped_stream.set_linger(linger)
+    }
+
+    /// Sets the `SO_KEEPALIVE` option of the underlying
+    /// tcp stream along with the idle time before the first
+    /// probe (`None` disables it), so that an idle tunnel is
+    /// not dropped by a NAT forgetting idle connections.
+    /// The probes are tcp ones, since any bytes written
+    /// into the tunnel would reach the destination
+    pub fn set_keepalive(&self, keepalive: Option<Duration>) -> io::Result<()> {
+        self.wrapped_stream.set_keepalive(keepalive)
+    }
+}
+
+/// Gives the raw file descriptor of the
+/// underlying stream, for instance, to set
+/// a socket option the crate does not expose
+#[cfg(unix)]
+impl<S: AsRawFd> AsRawFd for HttpConnectStream<S> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.wrapped_stream.as_raw_fd()
+    }
+}
+
+/// Gives the raw socket of the
+/// underlying stream, for instance, to set
+/// a socket option the crate does not expose
+#[cfg(windows)]
+impl<S: AsRawSocket> AsRawSocket for HttpConnectStream<S> {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.wrapped_stream.as_raw_socket()
+    }
+}
+
+/// Prints the peer address of the underlying
+/// tcp stream only, never the transferred data
+impl fmt::Debug for HttpConnectStream<TcpStream> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HttpConnectStream")
+            .field("peer_addr", &self.wrapped_stream.peer_addr().ok())
+            .finish()
+    }
+}
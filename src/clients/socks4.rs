@@ -1,3 +1,4 @@
+use crate::general::{ErrorCategory, TimeoutPhase};
 use std::fmt;
 
 /// Holds implementation of the actual socks4 protocol
@@ -8,10 +9,16 @@ pub mod general;
 /// connection
 pub mod no_ident;
 
+/// Holds implementation of the tcp port
+/// binding (BIND) request of the socks4 protocol
+pub mod bind;
+
 pub use general::Socks4General;
 pub use no_ident::Socks4NoIdent;
+pub use bind::Socks4Bind;
 
 /// Represents a Socks4 protocol command
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[repr(u8)]
 pub enum Command {
     TcpConnectionEstablishment = 1,
@@ -42,8 +49,49 @@ pub enum ErrorKind {
     /// a connection request
     BadIdent,
     /// Indicates that a timeouts has been reached
-    /// when connecting to a service
-    OperationTimeoutReached
+    /// when connecting to a service, in the phase carried
+    OperationTimeoutReached(TimeoutPhase),
+    /// Indicates that the server looks like
+    /// a server of another proxy protocol
+    /// (for instance, a Socks5 or an HTTP one)
+    WrongProxyType,
+    /// Indicates that the ident is longer than
+    /// the maximum length set (255 bytes by default),
+    /// so that a server may be unable to read it
+    IdentTooLong,
+    /// Indicates that the ident contains a NULL
+    /// byte (0x00), which would terminate
+    /// the ident field prematurely
+    IdentContainsNul,
+    /// Indicates that the proxy server itself cannot
+    /// be connected to (the connection is refused, the
+    /// server is unreachable or the connecting timeout
+    /// has been reached), so that no handshake
+    /// has been attempted at all
+    ProxyUnreachable(std::io::Error),
+    /// Indicates that the operation is not supported
+    /// on the stream (for instance, `S4BindStream::accept`
+    /// called once the second reply has been awaited)
+    NotSupported
+}
+
+impl ErrorKind {
+    /// Returns the category of the error, telling
+    /// a refused request from a broken server
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            ErrorKind::RequestDenied => ErrorCategory::PolicyDenied,
+            ErrorKind::IOError(_) | ErrorKind::ProxyUnreachable(_) => ErrorCategory::NetworkError,
+            ErrorKind::BadBuffer
+            | ErrorKind::WrongProxyType
+            | ErrorKind::NotSupported => ErrorCategory::ProtocolError,
+            ErrorKind::OperationTimeoutReached(_) => ErrorCategory::Timeout,
+            ErrorKind::IdentIsUnavailable
+            | ErrorKind::BadIdent
+            | ErrorKind::IdentTooLong
+            | ErrorKind::IdentContainsNul => ErrorCategory::AuthFailure
+        }
+    }
 }
 
 impl fmt::Display for ErrorKind {
@@ -55,7 +103,20 @@ impl fmt::Display for ErrorKind {
             ErrorKind::RequestDenied => f.write_str("request denied"),
             ErrorKind::IdentIsUnavailable => f.write_str("ident is unavailable"),
             ErrorKind::BadIdent => f.write_str("bad ident"),
-            ErrorKind::OperationTimeoutReached => f.write_str("operation timeout reached")
+            ErrorKind::OperationTimeoutReached(phase) => f.write_str(&phase.to_string()),
+            ErrorKind::WrongProxyType
+                => f.write_str("the server does not look like a Socks4 server"),
+            ErrorKind::IdentTooLong => f.write_str("ident is too long"),
+            ErrorKind::IdentContainsNul => f.write_str("ident contains a NULL byte"),
+            ErrorKind::ProxyUnreachable(e)
+                => f.write_str(&format!("proxy server is unreachable: {}", e)),
+            ErrorKind::NotSupported => f.write_str("operation is not supported on the stream")
         }
     }
+}
+
+impl From<std::io::Error> for ErrorKind {
+    fn from(e: std::io::Error) -> ErrorKind {
+        ErrorKind::IOError(e)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,349 @@
+use crate::clients::socks4::general::{parse_reply, S4GeneralStream, Socks4General};
+use crate::clients::socks4::{Command, ErrorKind};
+use crate::general::{
+    optional_timeout, read_initial, trace_bytes, ConnectionTimeouts, Observer, TimeoutPhase,
+};
+use crate::proxy::{BoundEndpoint, ProxyConstructor};
+use bytes::{Buf, BufMut};
+use core::task::{Context, Poll};
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::mem::MaybeUninit;
+use std::net::SocketAddrV4;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, RawSocket};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// Represents the proxy constructor that sends
+/// the tcp port binding (BIND) request, asking the
+/// server to listen for an inbound connection from
+/// the destination (for instance, the data connection
+/// of an active FTP session).
+///
+/// The proxy stream reports the endpoint the server
+/// listens on, and `S4BindStream::accept` waits for
+/// the destination to connect to it, just as the
+/// Socks5 BIND request does
+pub struct Socks4Bind {
+    /// The constructor the handshake is performed with,
+    /// which command is the tcp port binding one
+    inner: Socks4General,
+    /// The timeout of the wait for the destination
+    /// connecting to the endpoint bound by the
+    /// server, by default it is the read timeout
+    accept_timeout: Option<Duration>,
+}
+
+/// The actual type that represents
+/// the Socks4 BIND proxy stream, which
+/// is the tunnel to the destination
+/// once it has been accepted
+pub struct S4BindStream {
+    /// The proxy stream of the
+    /// handshake performed
+    inner: S4GeneralStream,
+    /// The accept timeout of the
+    /// constructor, used by `accept`
+    accept_timeout: Option<Duration>,
+    /// Whether `accept` has been called, after
+    /// which the stream is no longer awaiting
+    /// the second reply
+    accepted: bool,
+}
+
+impl Socks4Bind {
+    /// Creates a constructor asking the server to
+    /// accept an inbound connection from `dest_addr`,
+    /// which is the address of the service expected
+    /// to connect (the server may check that the
+    /// connection comes from it)
+    pub fn new(
+        dest_addr: SocketAddrV4,
+        ident: Cow<'static, str>,
+        timeouts: ConnectionTimeouts,
+    ) -> Socks4Bind {
+        let mut inner = Socks4General::new(dest_addr, ident, timeouts);
+        inner.with_command(Command::TcpPortBinding);
+
+        Socks4Bind {
+            inner,
+            accept_timeout: None,
+        }
+    }
+
+    /// Sets the observer of the connection attempts,
+    /// which is told when a handshake starts and
+    /// how it ends, along with the time it has taken
+    pub fn with_observer(&mut self, observer: Arc<dyn Observer<ErrorKind>>) {
+        self.inner.with_observer(observer);
+    }
+
+    /// Sets the timeout of the wait for the
+    /// destination connecting to the endpoint
+    /// bound by the server (see `S4BindStream::accept`),
+    /// which is the read timeout unless set
+    pub fn with_accept_timeout(&mut self, timeout: Duration) {
+        self.accept_timeout = Some(timeout);
+    }
+
+    /// Returns the address of a service
+    /// expected to connect through proxy
+    pub fn dest_addr(&self) -> SocketAddrV4 {
+        self.inner.dest_addr()
+    }
+
+    /// Returns the ident passed
+    /// when sending the request
+    pub fn ident(&self) -> Cow<'_, str> {
+        self.inner.ident()
+    }
+
+    /// Returns the timeouts set
+    pub fn timeouts(&self) -> &ConnectionTimeouts {
+        self.inner.timeouts()
+    }
+
+    /// Returns the timeout of the wait for the
+    /// destination connecting to the endpoint bound
+    /// by the server, falling back on the read timeout
+    pub fn accept_timeout(&self) -> Option<Duration> {
+        self.accept_timeout.or(self.inner.timeouts().read_timeout)
+    }
+
+    /// Sends the BIND request just as `connect` does,
+    /// but returns the future itself instead of a boxed
+    /// one (which the trait methods return), saving an
+    /// allocation per connection where the trait is not needed
+    pub async fn connect_unboxed(&self, stream: TcpStream) -> Result<S4BindStream, ErrorKind> {
+        let inner = self.inner.connect_unboxed(stream).await?;
+
+        Ok(S4BindStream {
+            inner,
+            accept_timeout: self.accept_timeout(),
+            accepted: false,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl ProxyConstructor for Socks4Bind {
+    type ProxyStream = S4BindStream;
+    type Stream = TcpStream;
+    type ErrorKind = ErrorKind;
+
+    /// Sends the BIND request and returns the proxy
+    /// stream once the first reply (carrying the endpoint
+    /// the server listens on) is received
+    async fn connect(&self, stream: Self::Stream) -> Result<Self::ProxyStream, Self::ErrorKind> {
+        self.connect_unboxed(stream).await
+    }
+
+    async fn send_payload(
+        &self,
+        buf: &mut Vec<u8>,
+        stream: &mut Self::Stream,
+    ) -> Result<usize, Self::ErrorKind> {
+        self.inner.send_payload(buf, stream).await
+    }
+}
+
+impl S4BindStream {
+    /// Returns the endpoint the server listens on
+    /// for the inbound connection, as reported in
+    /// its first reply. An unspecified address
+    /// (0.0.0.0) means the address of the server itself
+    /// (see `reachable_bound_endpoint`)
+    pub fn bound_endpoint(&self) -> &BoundEndpoint {
+        self.inner.bound_endpoint()
+    }
+
+    /// Returns the endpoint the server listens on with
+    /// an unspecified address substituted with the address
+    /// of the server, which is the endpoint the destination
+    /// is told to connect to, just as
+    /// `S4GeneralStream::reachable_bound_endpoint` does
+    pub fn reachable_bound_endpoint(&self) -> io::Result<BoundEndpoint> {
+        self.inner.reachable_bound_endpoint()
+    }
+
+    /// Waits for the second reply to the BIND request,
+    /// which the server sends once the destination has
+    /// connected to the bound endpoint, and returns the
+    /// endpoint reported in it. From then on, the stream
+    /// is the tunnel to the destination.
+    ///
+    /// The reply is awaited within the accept timeout
+    /// of the constructor (the read timeout unless set),
+    /// failing with `ErrorKind::OperationTimeoutReached(TimeoutPhase::Handshake)`
+    /// if the destination does not connect in time, and with
+    /// `ErrorKind::NotSupported` if `accept` has already been called
+    /// (even if it has failed or timed out, since part of the reply
+    /// may have been consumed)
+    pub async fn accept(&mut self) -> Result<BoundEndpoint, ErrorKind> {
+        if self.accepted {
+            return Err(ErrorKind::NotSupported);
+        }
+        self.accepted = true;
+
+        // Reading through the proxy stream, so that
+        // an I/O error is kept in `last_error_kind`
+        let mut buf = [0; 8];
+        let future = self.inner.read_exact(&mut buf);
+        let future = optional_timeout(self.accept_timeout, future);
+        future
+            .await
+            .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Handshake))??;
+        trace_bytes("received", &buf);
+
+        parse_reply(&buf)
+    }
+
+    /// Reads what the destination sends first,
+    /// just as `general::read_initial` does
+    pub async fn read_initial(&mut self, max: usize) -> io::Result<Vec<u8>> {
+        read_initial(self, max).await
+    }
+
+    /// Returns the timeouts of the constructor
+    /// the stream has been established with
+    pub fn timeouts(&self) -> &ConnectionTimeouts {
+        self.inner.timeouts()
+    }
+
+    /// Returns the kind of the last I/O error the
+    /// tunnel has failed with, if any, just as
+    /// `TcpNoAuthStream::last_error_kind` does
+    pub fn last_error_kind(&self) -> Option<io::ErrorKind> {
+        self.inner.last_error_kind()
+    }
+
+    /// Reads from the tunnel within the read timeout
+    /// of the constructor (with no limit if there is
+    /// none), just as `S4GeneralStream::read_timed` does
+    pub async fn read_timed(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read_timed(buf).await
+    }
+
+    /// Writes to the tunnel within the write timeout
+    /// of the constructor (with no limit if there is
+    /// none), just as `S4GeneralStream::write_timed` does
+    pub async fn write_timed(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write_timed(buf).await
+    }
+
+    /// Returns a shared reference
+    /// to the underlying tcp stream
+    pub fn get_ref(&self) -> &TcpStream {
+        self.inner.get_ref()
+    }
+
+    /// Returns a mutable reference
+    /// to the underlying tcp stream
+    pub fn get_mut(&mut self) -> &mut TcpStream {
+        self.inner.get_mut()
+    }
+}
+
+impl AsyncRead for S4BindStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let pinned = &mut Pin::into_inner(self).inner;
+        Pin::new(pinned).poll_read(cx, buf)
+    }
+
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [MaybeUninit<u8>]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+
+    fn poll_read_buf<B: BufMut>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut B,
+    ) -> Poll<io::Result<usize>>
+    where
+        Self: Sized,
+    {
+        let stream = &mut Pin::into_inner(self).inner;
+        Pin::new(stream).poll_read_buf(cx, buf)
+    }
+}
+
+impl AsyncWrite for S4BindStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let stream = &mut Pin::into_inner(self).inner;
+        Pin::new(stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_buf<B: Buf>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut B,
+    ) -> Poll<Result<usize, io::Error>>
+    where
+        Self: Sized,
+    {
+        let stream = &mut Pin::into_inner(self).inner;
+        Pin::new(stream).poll_write_buf(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let stream = &mut Pin::into_inner(self).inner;
+        Pin::new(stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let stream = &mut Pin::into_inner(self).inner;
+        Pin::new(stream).poll_shutdown(cx)
+    }
+}
+
+impl From<S4BindStream> for TcpStream {
+    fn from(stream: S4BindStream) -> TcpStream {
+        stream.inner.into()
+    }
+}
+
+/// Gives the raw file descriptor of the
+/// underlying stream, for instance, to set
+/// a socket option the crate does not expose
+#[cfg(unix)]
+impl AsRawFd for S4BindStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+/// Gives the raw socket of the
+/// underlying stream, for instance, to set
+/// a socket option the crate does not expose
+#[cfg(windows)]
+impl AsRawSocket for S4BindStream {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.inner.as_raw_socket()
+    }
+}
+
+/// Prints the peer address of the underlying
+/// tcp stream only, never the transferred data
+impl fmt::Debug for S4BindStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("S4BindStream")
+            .field("peer_addr", &self.inner.get_ref().peer_addr().ok())
+            .finish()
+    }
+}
@@ -1,17 +1,29 @@
 use crate::clients::socks4::{Command, ErrorKind};
-use crate::general::ConnectionTimeouts;
-use crate::proxy::ProxyConstructor;
+use crate::general::{
+    self, observed, optional_timeout, record_error, trace_bytes, ConnectionTimeouts, Observer,
+    TimeoutPhase,
+};
+use crate::proxy::{self, BoundAddr, BoundEndpoint, ProxyConstructor, ReadHalf, WriteHalf};
 use byteorder::{BigEndian, ByteOrder};
+use bytes::{Buf, BufMut};
 use core::task::{Context, Poll};
 use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt;
 use std::io;
-use std::net::SocketAddrV4;
+use std::mem::MaybeUninit;
+use std::net::{Ipv4Addr, SocketAddrV4};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, RawSocket};
 use std::pin::Pin;
 use std::str::FromStr;
-use tokio::io::{AsyncRead, AsyncWrite};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, BufReader};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
-use tokio::time::timeout;
 
 /// Represents the proxy constructor
 /// that creates a `S4GeneralStream`
@@ -21,12 +33,29 @@ pub struct Socks4General {
     /// we are connecting through proxy
     dest_addr: SocketAddrV4,
     /// An ident (see Socks4 protocol wiki
-    ///  for more information)
-    ident: Cow<'static, str>,
+    ///  for more information), which is raw
+    /// bytes rather than necessarily UTF-8
+    ident: Cow<'static, [u8]>,
     /// The timeout set
     timeouts: ConnectionTimeouts,
+    /// The maximum length of the ident
+    /// sent to a server (in bytes)
+    max_ident_len: usize,
+    /// The observer of the connection
+    /// attempts, by default there is none
+    observer: Option<Arc<dyn Observer<ErrorKind>>>,
+    /// The command sent in the request,
+    /// by default it is the tcp connection
+    /// establishment (CONNECT)
+    command: Command,
 }
 
+/// The default maximum length of an ident
+/// (in bytes). Socks4 itself does not limit
+/// the ident, but servers usually read
+/// no more than that
+pub const DEFAULT_MAX_IDENT_LEN: usize = 255;
+
 /// Represents an error that
 /// can occur during `from_str`
 /// parsing
@@ -51,6 +80,15 @@ pub struct S4GeneralStream {
     /// The tcp stream on which
     /// the client operates on
     wrapped_stream: TcpStream,
+    /// The endpoint reported
+    /// in the server's reply
+    bound: BoundEndpoint,
+    /// The timeouts of the constructor,
+    /// used by `read_timed` and `write_timed`
+    timeouts: ConnectionTimeouts,
+    /// The kind of the last I/O error
+    /// of the tunnel, if any
+    last_error: Option<io::ErrorKind>,
 }
 
 impl Socks4General {
@@ -58,26 +96,114 @@ impl Socks4General {
         dest_addr: SocketAddrV4,
         ident: Cow<'static, str>,
         timeouts: ConnectionTimeouts,
+    ) -> Socks4General {
+        let ident = match ident {
+            Cow::Borrowed(ident) => Cow::Borrowed(ident.as_bytes()),
+            Cow::Owned(ident) => Cow::Owned(ident.into_bytes()),
+        };
+
+        Socks4General::with_ident_bytes(dest_addr, ident, timeouts)
+    }
+
+    /// Creates a constructor with an ident of raw
+    /// bytes, which are not necessarily UTF-8 (some
+    /// legacy servers key on binary idents).
+    /// The ident is still checked for a NULL byte
+    /// inside of it before anything is sent
+    pub fn with_ident_bytes(
+        dest_addr: SocketAddrV4,
+        ident: Cow<'static, [u8]>,
+        timeouts: ConnectionTimeouts,
     ) -> Socks4General {
         Socks4General {
             dest_addr,
             ident,
             timeouts,
+            max_ident_len: DEFAULT_MAX_IDENT_LEN,
+            observer: None,
+            command: Command::TcpConnectionEstablishment,
+        }
+    }
+
+    /// Sets the maximum length of the ident
+    /// (in bytes), which is checked before
+    /// anything is sent to a server
+    pub fn with_max_ident_len(&mut self, max_ident_len: usize) {
+        self.max_ident_len = max_ident_len;
+    }
+
+    /// Sets the observer of the connection attempts,
+    /// which is told when a handshake starts and
+    /// how it ends, along with the time it has taken
+    pub fn with_observer(&mut self, observer: Arc<dyn Observer<ErrorKind>>) {
+        self.observer = Some(observer);
+    }
+
+    /// Sets the command sent in the request,
+    /// which `Socks4Bind` sets to BIND
+    pub(crate) fn with_command(&mut self, command: Command) {
+        self.command = command;
+    }
+
+    /// Returns the maximum length of the ident
+    pub fn max_ident_len(&self) -> usize {
+        self.max_ident_len
+    }
+
+    /// Checks that the ident can be sent
+    /// as the NULL-terminated ident field
+    fn validate_ident(&self) -> Result<(), ErrorKind> {
+        if self.ident.len() > self.max_ident_len {
+            return Err(ErrorKind::IdentTooLong);
         }
+
+        // A NULL byte inside of the ident would be
+        // read by a server as the end of the field,
+        // and the rest of the ident as a garbage
+        if self.ident.contains(&0) {
+            return Err(ErrorKind::IdentContainsNul);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the address of a service
+    /// we are connecting to through proxy
+    pub fn dest_addr(&self) -> SocketAddrV4 {
+        self.dest_addr
+    }
+
+    /// Returns the ident passed
+    /// when establishing a connection,
+    /// with the bytes that are not UTF-8
+    /// replaced (see `ident_bytes`)
+    pub fn ident(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.ident)
+    }
+
+    /// Returns the raw bytes of the ident
+    /// passed when establishing a connection
+    pub fn ident_bytes(&self) -> &[u8] {
+        &self.ident
+    }
+
+    /// Returns the timeouts set
+    pub fn timeouts(&self) -> &ConnectionTimeouts {
+        &self.timeouts
     }
 }
 
 /// Impl for parsing a `Socks4General`
 /// from a string
-impl FromStr for Socks4General {
-    type Err = StrParsingError;
+impl TryFrom<&str> for Socks4General {
+    type Error = StrParsingError;
 
     /// Parses a `Socks4General` from a
     /// string in format:
     ///   ipv4:port ident timeouts
-    fn from_str(s: &str) -> Result<Socks4General, Self::Err> {
+    fn try_from(s: &str) -> Result<Socks4General, Self::Error> {
         // Splitting the string on spaces
-        let mut s = s.split(" ");
+        let mut s = s.split(' ');
 
         // Parsing an address and timeouts
         let (address, ident, timeouts) = (
@@ -100,16 +226,132 @@ impl FromStr for Socks4General {
     }
 }
 
-#[async_trait::async_trait]
-impl ProxyConstructor for Socks4General {
-    type ProxyStream = S4GeneralStream;
-    type Stream = TcpStream;
-    type ErrorKind = ErrorKind;
+impl FromStr for Socks4General {
+    type Err = StrParsingError;
+
+    /// Parses a `Socks4General` from a
+    /// string just as `try_from` does
+    fn from_str(s: &str) -> Result<Socks4General, Self::Err> {
+        Socks4General::try_from(s)
+    }
+}
+
+impl fmt::Display for StrParsingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            StrParsingError::SyntaxError => f.write_str("expected `ipv4:port ident timeouts`"),
+            StrParsingError::InvalidAddr => f.write_str("invalid destination address"),
+            StrParsingError::InvalidTimeouts => f.write_str("invalid timeouts"),
+        }
+    }
+}
+
+impl std::error::Error for StrParsingError {}
+
+impl Socks4General {
+    /// Establishes a proxied connection on the stream
+    /// just as `connect` does and then writes the initial
+    /// payload (for instance, an HTTP request) to the tunnel,
+    /// saving a separate call for protocols where
+    /// the client speaks first.
+    ///
+    /// Note that it is not a real TCP Fast Open: the
+    /// payload is written right after the server's reply,
+    /// within the write timeout set
+    pub async fn connect_with_initial_data(
+        &self,
+        stream: TcpStream,
+        initial: &[u8],
+    ) -> Result<S4GeneralStream, ErrorKind> {
+        let mut stream = self.connect(stream).await?;
+
+        // Writing the initial payload to the tunnel
+        let future = stream.wrapped_stream.write_all(initial);
+        let future = optional_timeout(self.timeouts.write_timeout, future);
+        future
+            .await
+            .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Write))??;
+
+        Ok(stream)
+    }
+
+    /// Establishes a proxied connection on the tcp
+    /// stream just as `connect` does, but with `TCP_NODELAY`
+    /// set during the handshake (made of small requests and
+    /// replies, so that it is latency-sensitive), restoring
+    /// the prior setting once the handshake has succeeded
+    pub async fn connect_with_nodelay(
+        &self,
+        stream: TcpStream,
+    ) -> Result<S4GeneralStream, ErrorKind> {
+        let nodelay = stream.nodelay()?;
+        stream.set_nodelay(true)?;
+
+        let stream = self.connect(stream).await?;
+        stream.get_ref().set_nodelay(nodelay)?;
+
+        Ok(stream)
+    }
+
+    /// Establishes a proxied connection on the stream
+    /// just as `connect` does, but returns the future
+    /// itself instead of a boxed one (which the trait
+    /// methods return), saving an allocation per
+    /// connection where the trait is not needed
+    pub async fn connect_unboxed(&self, stream: TcpStream) -> Result<S4GeneralStream, ErrorKind> {
+        observed(self.observer.as_deref(), self.handshake(stream)).await
+    }
+
+    /// Writes the payload to the server and
+    /// reads its reply into the same buffer,
+    /// returning the number of bytes read
+    async fn exchange_payload(
+        &self,
+        buf: &mut [u8],
+        stream: &mut TcpStream,
+    ) -> Result<usize, ErrorKind> {
+        self.write_payload(buf, stream).await?;
+        self.read_payload(buf, stream).await
+    }
+
+    /// Writes the payload to the server
+    /// within the write timeout
+    async fn write_payload(&self, buf: &[u8], stream: &mut TcpStream) -> Result<(), ErrorKind> {
+        let future = stream.write_all(buf);
+        let future = optional_timeout(self.timeouts.write_timeout, future);
+        future
+            .await
+            .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Write))??;
+        trace_bytes("sent", buf);
+
+        Ok(())
+    }
+
+    /// Reads from the server into the buffer within
+    /// the read timeout, returning the number of bytes
+    /// read, which is never more than the buffer length
+    async fn read_payload(
+        &self,
+        buf: &mut [u8],
+        stream: &mut TcpStream,
+    ) -> Result<usize, ErrorKind> {
+        let future = stream.read(buf);
+        let future = optional_timeout(self.timeouts.read_timeout, future);
+        let read_bytes = future
+            .await
+            .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Read))??;
+        trace_bytes("received", &buf[..read_bytes]);
+
+        Ok(read_bytes)
+    }
+
+    /// Performs the handshake over the stream,
+    /// which is what `connect` observes
+    async fn handshake(&self, mut stream: TcpStream) -> Result<S4GeneralStream, ErrorKind> {
+        // Validating the ident before
+        // anything is sent to the server
+        self.validate_ident()?;
 
-    async fn connect(
-        &mut self,
-        mut stream: Self::Stream,
-    ) -> Result<Self::ProxyStream, Self::ErrorKind> {
         // Computing the Socks4 buffer length.
         // The buffer length is computed this way:
         //  (+1) for the number of the version of the socks protocol (4 in this case)
@@ -126,8 +368,9 @@ impl ProxyConstructor for Socks4General {
         // being used in the payload buffer
         buf.push(4);
 
-        // Pusing the tcp connection establishment command
-        buf.push(Command::TcpConnectionEstablishment as u8);
+        // Pusing the command (the tcp connection
+        // establishment one, unless set otherwise)
+        buf.push(self.command as u8);
 
         // Filling the port buffer with zeroes
         // due to that fact that it is permitted
@@ -149,64 +392,293 @@ impl ProxyConstructor for Socks4General {
         // Writing the IPv4 in the buffer
         BigEndian::write_u32(&mut buf[4..8], (*self.dest_addr.ip()).into());
 
+        // Writing the ident (USERID)
+        buf.extend_from_slice(&self.ident);
+
         // And, finally, pushing the
         // NULL-termination (0x00) byte
         buf.push(0);
 
         // Sending our generated payload
         // to the Socks4 server
-        let read_bytes = self.send_payload(&mut buf, &mut stream).await.unwrap();
-     
+        self.write_payload(&buf, &mut stream).await?;
 
-        // We should receive exatly 8 bytes from the server,
-        // unless there is something wrong with the
-        // received reply
-        if read_bytes != 8 {
-            return Err(ErrorKind::BadBuffer);
+        // Reading the reply into a buffer of its own,
+        // so that no byte past the 8 of it is read
+        // (the data the tunnel starts with is
+        // left to the caller of the proxy stream)
+        let mut reply = [0; 8];
+        let read_bytes = self.read_payload(&mut reply, &mut stream).await?;
+
+        // A Socks5 reply starts with 0x05, while an HTTP
+        // one starts with `H` (as in `HTTP/1.1`), so that
+        // we are talking to a wrong proxy server
+        if read_bytes > 0 && (reply[0] == 0x05 || reply[0] == b'H') {
+            return Err(ErrorKind::WrongProxyType);
         }
 
+        // We should receive exatly 8 bytes from the server,
+        // which may arrive in parts, unless there is
+        // something wrong with the received reply
+        read_rest_of_reply(
+            &mut stream,
+            &mut reply,
+            read_bytes,
+            self.timeouts.read_timeout,
+        )
+        .await?;
+
         // Analyzing the received reply
         // and returning a socks4 general proxy client
         // instance if everything was successful
-        match buf[1] {
-            // Means that request accepted
-            0x5a => Ok(S4GeneralStream {
-                wrapped_stream: stream,
-            }),
-            // Means that our request was denied
-            0x5b => Err(ErrorKind::RequestDenied),
-            // Means that ident is currently unavailable
-            0x5c => Err(ErrorKind::IdentIsUnavailable),
-            // Means that the user passed a wrong ident string
-            0x5d => Err(ErrorKind::BadIdent),
-            // Does not match anything, means that
-            // we got a bad buffer
-            _ => Err(ErrorKind::BadBuffer),
+        let bound = parse_reply(&reply)?;
+
+        Ok(S4GeneralStream {
+            wrapped_stream: stream,
+            bound,
+            timeouts: self.timeouts.clone(),
+            last_error: None,
+        })
+    }
+}
+
+/// Reads the rest of the 8-byte reply to a Socks4 request,
+/// of which `read_bytes` have already been read into `buf`,
+/// since a single read may return just a part of it.
+/// A reply cut short by the server hanging up,
+/// as well as an empty one, is a bad buffer
+pub(crate) async fn read_rest_of_reply(
+    stream: &mut TcpStream,
+    buf: &mut [u8; 8],
+    read_bytes: usize,
+    read_timeout: Option<Duration>,
+) -> Result<(), ErrorKind> {
+    if read_bytes == 0 {
+        return Err(ErrorKind::BadBuffer);
+    }
+
+    if read_bytes < 8 {
+        let future = stream.read_exact(&mut buf[read_bytes..8]);
+        let future = optional_timeout(read_timeout, future);
+        match future
+            .await
+            .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Read))?
+        {
+            Ok(_) => trace_bytes("received", &buf[read_bytes..8]),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Err(ErrorKind::BadBuffer),
+            Err(e) => return Err(e.into()),
         }
     }
 
+    Ok(())
+}
+
+/// Parses a reply of 8 bytes to a Socks4 request
+/// and returns the endpoint it carries,
+/// unless the request has not been granted
+pub(crate) fn parse_reply(buf: &[u8]) -> Result<BoundEndpoint, ErrorKind> {
+    // The reply version (VN) must be 0x00,
+    // otherwise we are probably talking to
+    // something that is not a Socks4 server
+    if buf[0] != 0x00 {
+        return Err(ErrorKind::BadBuffer);
+    }
+
+    match buf[1] {
+        // Means that request accepted.
+        // The reply carries the port (in the network
+        // byte order) and the IPv4 address bound by the server
+        0x5a => Ok(BoundEndpoint::new(
+            BoundAddr::Ipv4(Ipv4Addr::from(BigEndian::read_u32(&buf[4..8]))),
+            BigEndian::read_u16(&buf[2..4]),
+        )),
+        // Means that our request was denied
+        0x5b => Err(ErrorKind::RequestDenied),
+        // Means that ident is currently unavailable
+        0x5c => Err(ErrorKind::IdentIsUnavailable),
+        // Means that the user passed a wrong ident string
+        0x5d => Err(ErrorKind::BadIdent),
+        // Does not match anything, means that
+        // we got a bad buffer
+        _ => Err(ErrorKind::BadBuffer),
+    }
+}
+
+#[async_trait::async_trait]
+impl ProxyConstructor for Socks4General {
+    type ProxyStream = S4GeneralStream;
+    type Stream = TcpStream;
+    type ErrorKind = ErrorKind;
+
+    async fn connect(&self, stream: Self::Stream) -> Result<Self::ProxyStream, Self::ErrorKind> {
+        self.connect_unboxed(stream).await
+    }
+
     async fn send_payload(
         &self,
         buf: &mut Vec<u8>,
         stream: &mut Self::Stream,
     ) -> Result<usize, Self::ErrorKind> {
-        // Writing the initial payload to the server
-        let future = stream.write_all(&buf);
-        let future = timeout(self.timeouts.write_timeout, future);
-        let _ = future
-            .await
-            .map_err(|_| ErrorKind::OperationTimeoutReached)?
-            .map_err(|e| ErrorKind::IOError(e))?;
+        self.exchange_payload(buf, stream).await
+    }
+}
 
-        // Reading a reply from the server
-        let future = stream.read(buf);
-        let future = timeout(self.timeouts.read_timeout, future);
-        let read_bytes = future
-            .await
-            .map_err(|_| ErrorKind::OperationTimeoutReached)?
-            .map_err(|e| ErrorKind::IOError(e))?;
+impl S4GeneralStream {
+    /// Wraps the tunnel in a `BufReader`, so that
+    /// line-oriented protocols (`read_line`, `read_until`)
+    /// are spoken through it
+    pub fn buffered(self) -> BufReader<Self> {
+        BufReader::new(self)
+    }
 
-        Ok(read_bytes)
+    /// Returns the endpoint that the server
+    /// has bound, as reported in its reply
+    pub fn bound_endpoint(&self) -> &BoundEndpoint {
+        &self.bound
+    }
+
+    /// Returns the endpoint that the server has
+    /// bound, with an unspecified address substituted
+    /// with the address of the server (see
+    /// `BoundEndpoint::substitute_unspecified`), which is
+    /// the endpoint to be used for subsequent operations
+    pub fn reachable_bound_endpoint(&self) -> io::Result<BoundEndpoint> {
+        let server = self.wrapped_stream.peer_addr()?.ip();
+        Ok(self.bound.clone().substitute_unspecified(server))
+    }
+
+    /// Returns the port (DSTPORT) reported
+    /// in the server's reply, for instance,
+    /// the port the server has bound for us
+    pub fn bound_port(&self) -> u16 {
+        self.bound.port
+    }
+
+    /// Returns the IPv4 address (DSTIP)
+    /// reported in the server's reply
+    pub fn bound_ip(&self) -> Ipv4Addr {
+        match self.bound.addr {
+            BoundAddr::Ipv4(ip) => ip,
+            // A Socks4 reply carries nothing but
+            // an IPv4 address, so that the endpoint
+            // is always created from one
+            _ => unreachable!("a Socks4 bound address is always IPv4"),
+        }
+    }
+
+    /// Returns a shared reference
+    /// to the underlying tcp stream
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.wrapped_stream
+    }
+
+    /// Returns a mutable reference
+    /// to the underlying tcp stream
+    pub fn get_mut(&mut self) -> &mut TcpStream {
+        &mut self.wrapped_stream
+    }
+
+    /// Consumes the proxy stream and returns
+    /// the underlying tcp stream along with the bytes
+    /// read past the reply of the server, which
+    /// have not been returned yet. The reply is read
+    /// exactly, never past its end, so that there are
+    /// none and the data following the reply (even if
+    /// sent along with it) is read from the tcp stream
+    pub fn into_inner_with_buffered(self) -> (TcpStream, Vec<u8>) {
+        (self.wrapped_stream, Vec::new())
+    }
+
+    /// Receives data from the underlying tcp stream
+    /// without removing it from the queue, so that
+    /// the next read returns the same data
+    pub async fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.wrapped_stream.peek(buf).await
+    }
+
+    /// Attempts to receive data from the underlying
+    /// tcp stream without removing it from the queue
+    pub fn poll_peek(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.wrapped_stream.poll_peek(cx, buf)
+    }
+
+    /// Splits the proxy stream into the owned
+    /// read and write halves, which can be used
+    /// concurrently (for instance, from two tasks)
+    pub fn into_split(self) -> (ReadHalf, WriteHalf) {
+        proxy::split(self.wrapped_stream, Vec::new(), 0)
+    }
+
+    /// Checks whether the tunnel is still alive (for
+    /// instance, before reusing a pooled connection)
+    /// without blocking and without consuming any data.
+    /// The proxy having closed the connection
+    /// (or an error) means the tunnel is dead
+    pub fn is_alive(&mut self) -> bool {
+        general::is_alive(&mut self.wrapped_stream)
+    }
+
+    /// Shuts down the tunnel within the given timeout,
+    /// just as `general::shutdown_with_timeout` does
+    pub async fn shutdown_with_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        general::shutdown_with_timeout(self, timeout).await
+    }
+
+    /// Reads what the destination sends first,
+    /// just as `general::read_initial` does
+    pub async fn read_initial(&mut self, max: usize) -> io::Result<Vec<u8>> {
+        general::read_initial(self, max).await
+    }
+
+    /// Returns the timeouts of the constructor
+    /// the stream has been established with
+    pub fn timeouts(&self) -> &ConnectionTimeouts {
+        &self.timeouts
+    }
+
+    /// Returns the kind of the last I/O error the
+    /// tunnel has failed with, if any, just as
+    /// `TcpNoAuthStream::last_error_kind` does
+    pub fn last_error_kind(&self) -> Option<io::ErrorKind> {
+        self.last_error
+    }
+
+    /// Reads from the tunnel within the read timeout
+    /// of the constructor (with no limit if there is
+    /// none), so that the timeouts bound the whole
+    /// lifetime of the connection and not just the
+    /// handshake. Fails with `io::ErrorKind::TimedOut`
+    /// if the timeout is reached
+    pub async fn read_timed(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_timeout = self.timeouts.read_timeout;
+        general::read_timed(self, buf, read_timeout).await
+    }
+
+    /// Writes to the tunnel within the write timeout
+    /// of the constructor, just as `read_timed` reads,
+    /// returning the number of bytes written.
+    /// Fails with `io::ErrorKind::TimedOut`
+    /// if the timeout is reached
+    pub async fn write_timed(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let write_timeout = self.timeouts.write_timeout;
+        general::write_timed(self, buf, write_timeout).await
+    }
+
+    /// Sets the `SO_LINGER` option of the underlying
+    /// tcp stream, bounding how long closing it waits
+    /// for the unsent data to be transmitted
+    pub fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
+        self.wrapped_stream.set_linger(linger)
+    }
+
+    /// Sets the `SO_KEEPALIVE` option of the underlying
+    /// tcp stream along with the idle time before the first
+    /// probe (`None` disables it), so that an idle tunnel is
+    /// not dropped by a NAT forgetting idle connections.
+    /// The probes are tcp ones, since any bytes written
+    /// into the tunnel would reach the destination
+    pub fn set_keepalive(&self, keepalive: Option<Duration>) -> io::Result<()> {
+        self.wrapped_stream.set_keepalive(keepalive)
     }
 }
 
@@ -216,8 +688,26 @@ impl AsyncRead for S4GeneralStream {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<io::Result<usize>> {
-        let pinned = &mut Pin::into_inner(self).wrapped_stream;
-        Pin::new(pinned).poll_read(cx, buf)
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_read(cx, buf);
+        record_error(&mut this.last_error, poll)
+    }
+
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [MaybeUninit<u8>]) -> bool {
+        self.wrapped_stream.prepare_uninitialized_buffer(buf)
+    }
+
+    fn poll_read_buf<B: BufMut>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut B,
+    ) -> Poll<io::Result<usize>>
+    where
+        Self: Sized,
+    {
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_read_buf(cx, buf);
+        record_error(&mut this.last_error, poll)
     }
 }
 
@@ -227,23 +717,69 @@ impl AsyncWrite for S4GeneralStream {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize, io::Error>> {
-        let stream = &mut Pin::into_inner(self).wrapped_stream;
-        Pin::new(stream).poll_write(cx, buf)
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_write(cx, buf);
+        record_error(&mut this.last_error, poll)
+    }
+
+    fn poll_write_buf<B: Buf>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut B,
+    ) -> Poll<Result<usize, io::Error>>
+    where
+        Self: Sized,
+    {
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_write_buf(cx, buf);
+        record_error(&mut this.last_error, poll)
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
-        let stream = &mut Pin::into_inner(self).wrapped_stream;
-        Pin::new(stream).poll_flush(cx)
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_flush(cx);
+        record_error(&mut this.last_error, poll)
     }
 
     fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
-        let stream = &mut Pin::into_inner(self).wrapped_stream;
-        Pin::new(stream).poll_shutdown(cx)
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_shutdown(cx);
+        record_error(&mut this.last_error, poll)
+    }
+}
+
+impl From<S4GeneralStream> for TcpStream {
+    fn from(stream: S4GeneralStream) -> TcpStream {
+        stream.wrapped_stream
+    }
+}
+
+/// Gives the raw file descriptor of the
+/// underlying stream, for instance, to set
+/// a socket option the crate does not expose
+#[cfg(unix)]
+impl AsRawFd for S4GeneralStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.wrapped_stream.as_raw_fd()
+    }
+}
+
+/// Gives the raw socket of the
+/// underlying stream, for instance, to set
+/// a socket option the crate does not expose
+#[cfg(windows)]
+impl AsRawSocket for S4GeneralStream {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.wrapped_stream.as_raw_socket()
     }
 }
 
-impl Into<TcpStream> for S4GeneralStream {
-    fn into(self) -> TcpStream {
-        self.wrapped_stream
+/// Prints the peer address of the underlying
+/// tcp stream only, never the transferred data
+impl fmt::Debug for S4GeneralStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("S4GeneralStream")
+            .field("peer_addr", &self.wrapped_stream.peer_addr().ok())
+            .finish()
     }
 }
@@ -1,16 +1,27 @@
-use crate::general::ConnectionTimeouts;
+use crate::general::{self, observed, optional_timeout, record_error, trace_bytes, ConnectionTimeouts,
+                     Observer, TimeoutPhase};
 use crate::clients::socks4::{ErrorKind, Command};
-use crate::proxy::ProxyConstructor;
+use crate::clients::socks4::general::{parse_reply, read_rest_of_reply};
+use crate::proxy::{self, BoundAddr, BoundEndpoint, ProxyConstructor, ReadHalf, WriteHalf};
 use byteorder::{ByteOrder, BigEndian};
-use tokio::net::TcpStream;
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::io::{AsyncRead, AsyncWrite, BufReader};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::time::timeout;
 use std::pin::Pin;
+use std::mem::MaybeUninit;
+use bytes::{Buf, BufMut};
 use core::task::{Poll, Context};
-use std::net::SocketAddrV4;
+use std::net::{Ipv4Addr, SocketAddrV4};
 use std::str::FromStr;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::Duration;
 use std::io;
+use std::fmt;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, RawSocket};
 
 /// Parameters required by this Socks4
 /// proxy client protocol implementation
@@ -19,7 +30,10 @@ pub struct Socks4NoIdent {
     /// we are connecting through proxy
     dest_addr: SocketAddrV4,
     /// The timeout set
-    timeouts: ConnectionTimeouts
+    timeouts: ConnectionTimeouts,
+    /// The observer of the connection
+    /// attempts, by default there is none
+    observer: Option<Arc<dyn Observer<ErrorKind>>>
 }
 
 /// Represents an error that
@@ -45,28 +59,55 @@ pub enum StrParsingError {
 pub struct S4NoIdentStream {
     /// The tcp stream on which
     /// the client operates on
-    wrapped_stream: TcpStream
+    wrapped_stream: TcpStream,
+    /// The endpoint reported
+    /// in the server's reply
+    bound: BoundEndpoint,
+    /// The timeouts of the constructor,
+    /// used by `read_timed` and `write_timed`
+    timeouts: ConnectionTimeouts,
+    /// The kind of the last I/O error
+    /// of the tunnel, if any
+    last_error: Option<io::ErrorKind>
 }
 
 impl Socks4NoIdent {
     pub fn new(dest_addr: SocketAddrV4, timeouts: ConnectionTimeouts)
         -> Socks4NoIdent
     {
-        Socks4NoIdent { dest_addr, timeouts }
+        Socks4NoIdent { dest_addr, timeouts, observer: None }
+    }
+
+    /// Sets the observer of the connection attempts,
+    /// which is told when a handshake starts and
+    /// how it ends, along with the time it has taken
+    pub fn with_observer(&mut self, observer: Arc<dyn Observer<ErrorKind>>) {
+        self.observer = Some(observer);
+    }
+
+    /// Returns the address of a service
+    /// we are connecting to through proxy
+    pub fn dest_addr(&self) -> SocketAddrV4 {
+        self.dest_addr
+    }
+
+    /// Returns the timeouts set
+    pub fn timeouts(&self) -> &ConnectionTimeouts {
+        &self.timeouts
     }
 }
 
-/// Impl for parsing a `Socks4General`
+/// Impl for parsing a `Socks4NoIdent`
 /// from a string
-impl FromStr for Socks4NoIdent {
-    type Err = StrParsingError;
+impl TryFrom<&str> for Socks4NoIdent {
+    type Error = StrParsingError;
 
-    /// Parses a `Socks4General` from a
+    /// Parses a `Socks4NoIdent` from a
     /// string in format:
     ///   ipv4:port timeouts 
-    fn from_str(s: &str) -> Result<Socks4NoIdent, Self::Err> {
+    fn try_from(s: &str) -> Result<Socks4NoIdent, Self::Error> {
         // Splitting the string on spaces
-        let mut s = s.split(" ");
+        let mut s = s.split(' ');
 
         // Parsing an address and timeouts
         let (address, timeouts) = (s.next()
@@ -82,14 +123,147 @@ impl FromStr for Socks4NoIdent {
     }
 }
 
-#[async_trait::async_trait]
-impl ProxyConstructor for Socks4NoIdent {
-    type ProxyStream = S4NoIdentStream;
-    type Stream = TcpStream;
-    type ErrorKind = ErrorKind;
+impl FromStr for Socks4NoIdent {
+    type Err = StrParsingError;
 
-    async fn connect(&mut self, mut stream: Self::Stream)
-        -> Result<Self::ProxyStream, Self::ErrorKind>
+    /// Parses a `Socks4NoIdent` from a
+    /// string just as `try_from` does
+    fn from_str(s: &str) -> Result<Socks4NoIdent, Self::Err> {
+        Socks4NoIdent::try_from(s)
+    }
+}
+
+impl fmt::Display for StrParsingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            StrParsingError::SyntaxError => f.write_str("expected `ipv4:port timeouts`"),
+            StrParsingError::InvalidAddr => f.write_str("invalid destination address"),
+            StrParsingError::InvalidTimeouts => f.write_str("invalid timeouts")
+        }
+    }
+}
+
+impl std::error::Error for StrParsingError {}
+
+impl Socks4NoIdent {
+    /// Establishes a proxied connection on the stream
+    /// just as `connect` does and then writes the initial
+    /// payload (for instance, an HTTP request) to the tunnel,
+    /// saving a separate call for protocols where
+    /// the client speaks first.
+    ///
+    /// Note that it is not a real TCP Fast Open: the
+    /// payload is written right after the server's reply,
+    /// within the write timeout set
+    pub async fn connect_with_initial_data(&self, stream: TcpStream, initial: &[u8])
+        -> Result<S4NoIdentStream, ErrorKind>
+    {
+        let mut stream = self.connect(stream).await?;
+
+        // Writing the initial payload to the tunnel
+        let future = stream.wrapped_stream.write_all(initial);
+        let future = optional_timeout(self.timeouts.write_timeout, future);
+        future.await
+              .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Write))??;
+
+        Ok(stream)
+    }
+
+    /// Establishes a proxied connection on the tcp
+    /// stream just as `connect` does, but with `TCP_NODELAY`
+    /// set during the handshake (made of small requests and
+    /// replies, so that it is latency-sensitive), restoring
+    /// the prior setting once the handshake has succeeded
+    pub async fn connect_with_nodelay(&self, stream: TcpStream)
+        -> Result<S4NoIdentStream, ErrorKind>
+    {
+        let nodelay = stream.nodelay()?;
+        stream.set_nodelay(true)?;
+
+        let stream = self.connect(stream).await?;
+        stream.get_ref().set_nodelay(nodelay)?;
+
+        Ok(stream)
+    }
+
+    /// Connects to the proxy server at `proxy_addr`
+    /// (bounded by the connecting timeout) and establishes
+    /// a proxied connection on the stream just as `connect` does.
+    /// While the destination must be an IPv4 one, the proxy
+    /// server itself may be reached over IPv6 as well
+    /// (for instance, at `"[::1]:1080"`).
+    ///
+    /// A failure to reach the proxy server itself is
+    /// `ErrorKind::ProxyUnreachable`, while any other error
+    /// means that the handshake has been attempted
+    pub async fn establish<A: ToSocketAddrs>(&self, proxy_addr: A)
+        -> Result<S4NoIdentStream, ErrorKind>
+    {
+        // Connecting to the proxy server, where
+        // reaching the connecting timeout is reported
+        // as an I/O error, just as a refused connection
+        let future = TcpStream::connect(proxy_addr);
+        let future = optional_timeout(self.timeouts.connecting_timeout, future);
+        let stream = future.await
+                           .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut,
+                                                                  "connecting timeout reached")))
+                           .map_err(ErrorKind::ProxyUnreachable)?;
+
+        self.connect(stream).await
+    }
+
+    /// Establishes a proxied connection on the stream
+    /// just as `connect` does, but returns the future
+    /// itself instead of a boxed one (which the trait
+    /// methods return), saving an allocation per
+    /// connection where the trait is not needed
+    pub async fn connect_unboxed(&self, stream: TcpStream)
+        -> Result<S4NoIdentStream, ErrorKind>
+    {
+        observed(self.observer.as_deref(), self.handshake(stream)).await
+    }
+
+    /// Writes the payload to the server and
+    /// reads its reply into the same buffer,
+    /// returning the number of bytes read
+    async fn exchange_payload(&self, buf: &mut [u8], stream: &mut TcpStream)
+        -> Result<usize, ErrorKind>
+    {
+        self.write_payload(buf, stream).await?;
+        self.read_payload(buf, stream).await
+    }
+
+    /// Writes the payload to the server
+    /// within the write timeout
+    async fn write_payload(&self, buf: &[u8], stream: &mut TcpStream)
+        -> Result<(), ErrorKind>
+    {
+        let future = stream.write_all(buf);
+        let future = optional_timeout(self.timeouts.write_timeout, future);
+        future.await.map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Write))??;
+        trace_bytes("sent", buf);
+
+        Ok(())
+    }
+
+    /// Reads from the server into the buffer within
+    /// the read timeout, returning the number of bytes
+    /// read, which is never more than the buffer length
+    async fn read_payload(&self, buf: &mut [u8], stream: &mut TcpStream)
+        -> Result<usize, ErrorKind>
+    {
+        let future = stream.read(buf);
+        let future = optional_timeout(self.timeouts.read_timeout, future);
+        let read_bytes = future.await.map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Read))??;
+        trace_bytes("received", &buf[..read_bytes]);
+
+        Ok(read_bytes)
+    }
+
+    /// Performs the handshake over the stream,
+    /// which is what `connect` observes
+    async fn handshake(&self, mut stream: TcpStream)
+        -> Result<S4NoIdentStream, ErrorKind>
     {
         // Computing the Socks4 buffer length.
         // The buffer length is computed this way:
@@ -136,48 +310,217 @@ impl ProxyConstructor for Socks4NoIdent {
 
         // Sending our generated payload
         // to the Socks4 server
-        let future = stream.write_all(&buf);
-        let future = timeout(self.timeouts.write_timeout, future);
-        let _ = future.await.map_err(|_| ErrorKind::OperationTimeoutReached)?
-                            .map_err(|e| ErrorKind::IOError(e))?;
+        self.write_payload(&buf, &mut stream).await?;
 
-        // Reading a reply from the server
-        let future = stream.read(&mut buf);
-        let future = timeout(self.timeouts.read_timeout, future);
-        let read_bytes = future.await.map_err(|_| ErrorKind::OperationTimeoutReached)?
-                                      .map_err(|e| ErrorKind::IOError(e))?;
+        // Reading the reply into a buffer of its own,
+        // so that no byte past the 8 of it is read
+        // (the data the tunnel starts with is
+        // left to the caller of the proxy stream)
+        let mut buf = [0; 8];
+        let read_bytes = self.read_payload(&mut buf, &mut stream).await?;
 
-        // We should receive exatly 8 bytes from the server,
-        // unless there is something wrong with the
-        // received reply
-        if read_bytes != 8 {
-            return Err(ErrorKind::BadBuffer)
+        // A Socks5 reply starts with 0x05, while an HTTP
+        // one starts with `H` (as in `HTTP/1.1`), so that
+        // we are talking to a wrong proxy server
+        if read_bytes > 0 && (buf[0] == 0x05 || buf[0] == b'H') {
+            return Err(ErrorKind::WrongProxyType)
         }
 
-        // Analyzing the received reply
-        // and returning a socks4 general proxy client
-        // instance if everything was successful
-        match buf[1] {
-            // Means that request accepted
-            0x5a => Ok(S4NoIdentStream { wrapped_stream: stream }),
-            // Means that our request was denied
-            0x5b => Err(ErrorKind::RequestDenied),
-            // Means that ident is currently unavailable
-            0x5c => Err(ErrorKind::IdentIsUnavailable),
-            // Means that the user passed a wrong ident string
-            0x5d => Err(ErrorKind::BadIdent),
-            // Does not match anything, means that
-            // we got a bad buffer
-            _ => Err(ErrorKind::BadBuffer)
+        // We should receive exatly 8 bytes from the server,
+        // which may arrive in parts, unless there is
+        // something wrong with the received reply
+        read_rest_of_reply(&mut stream, &mut buf, read_bytes,
+                           self.timeouts.read_timeout).await?;
+
+        // Analyzing the received reply just as
+        // the general client does and returning a socks4
+        // proxy client instance if everything was successful
+        let bound = parse_reply(&buf)?;
+
+        Ok(S4NoIdentStream {
+            wrapped_stream: stream,
+            bound,
+            timeouts: self.timeouts.clone(),
+            last_error: None
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl ProxyConstructor for Socks4NoIdent {
+    type ProxyStream = S4NoIdentStream;
+    type Stream = TcpStream;
+    type ErrorKind = ErrorKind;
+
+    async fn connect(&self, stream: Self::Stream)
+        -> Result<Self::ProxyStream, Self::ErrorKind>
+    {
+        self.connect_unboxed(stream).await
+    }
+
+    async fn send_payload(&self, buf: &mut Vec<u8>, stream: &mut Self::Stream)
+        -> Result<usize, Self::ErrorKind>
+    {
+        self.exchange_payload(buf, stream).await
+    }
+}
+
+impl S4NoIdentStream {
+    /// Wraps the tunnel in a `BufReader`, so that
+    /// line-oriented protocols (`read_line`, `read_until`)
+    /// are spoken through it
+    pub fn buffered(self) -> BufReader<Self> {
+        BufReader::new(self)
+    }
+
+    /// Returns the endpoint that the server
+    /// has bound, as reported in its reply
+    pub fn bound_endpoint(&self) -> &BoundEndpoint {
+        &self.bound
+    }
+
+    /// Returns the endpoint that the server has
+    /// bound, with an unspecified address substituted
+    /// with the address of the server (see
+    /// `BoundEndpoint::substitute_unspecified`), which is
+    /// the endpoint to be used for subsequent operations
+    pub fn reachable_bound_endpoint(&self) -> io::Result<BoundEndpoint> {
+        let server = self.wrapped_stream.peer_addr()?.ip();
+        Ok(self.bound.clone().substitute_unspecified(server))
+    }
+
+    /// Returns the port (DSTPORT) reported
+    /// in the server's reply, for instance,
+    /// the port the server has bound for us
+    pub fn bound_port(&self) -> u16 {
+        self.bound.port
+    }
+
+    /// Returns the IPv4 address (DSTIP)
+    /// reported in the server's reply
+    pub fn bound_ip(&self) -> Ipv4Addr {
+        match self.bound.addr {
+            BoundAddr::Ipv4(ip) => ip,
+            // A Socks4 reply carries nothing but
+            // an IPv4 address, so that the endpoint
+            // is always created from one
+            _ => unreachable!("a Socks4 bound address is always IPv4")
         }
     }
 
-    async fn send_payload(
-        &self,
-        buf: &mut Vec<u8>,
-        stream: &mut Self::Stream,
-    ) -> Result<usize, Self::ErrorKind> {
-        todo!()
+    /// Returns a shared reference
+    /// to the underlying tcp stream
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.wrapped_stream
+    }
+
+    /// Returns a mutable reference
+    /// to the underlying tcp stream
+    pub fn get_mut(&mut self) -> &mut TcpStream {
+        &mut self.wrapped_stream
+    }
+
+    /// Consumes the proxy stream and returns
+    /// the underlying tcp stream along with the bytes
+    /// read past the reply of the server, which
+    /// have not been returned yet. The reply is read
+    /// exactly, never past its end, so that there are
+    /// none and the data following the reply (even if
+    /// sent along with it) is read from the tcp stream
+    pub fn into_inner_with_buffered(self) -> (TcpStream, Vec<u8>) {
+        (self.wrapped_stream, Vec::new())
+    }
+
+    /// Receives data from the underlying tcp stream
+    /// without removing it from the queue, so that
+    /// the next read returns the same data
+    pub async fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.wrapped_stream.peek(buf).await
+    }
+
+    /// Attempts to receive data from the underlying
+    /// tcp stream without removing it from the queue
+    pub fn poll_peek(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.wrapped_stream.poll_peek(cx, buf)
+    }
+
+    /// Splits the proxy stream into the owned
+    /// read and write halves, which can be used
+    /// concurrently (for instance, from two tasks)
+    pub fn into_split(self) -> (ReadHalf, WriteHalf) {
+        proxy::split(self.wrapped_stream, Vec::new(), 0)
+    }
+
+    /// Checks whether the tunnel is still alive (for
+    /// instance, before reusing a pooled connection)
+    /// without blocking and without consuming any data.
+    /// The proxy having closed the connection
+    /// (or an error) means the tunnel is dead
+    pub fn is_alive(&mut self) -> bool {
+        general::is_alive(&mut self.wrapped_stream)
+    }
+
+    /// Shuts down the tunnel within the given timeout,
+    /// just as `general::shutdown_with_timeout` does
+    pub async fn shutdown_with_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        general::shutdown_with_timeout(self, timeout).await
+    }
+
+    /// Reads what the destination sends first,
+    /// just as `general::read_initial` does
+    pub async fn read_initial(&mut self, max: usize) -> io::Result<Vec<u8>> {
+        general::read_initial(self, max).await
+    }
+
+    /// Returns the timeouts of the constructor
+    /// the stream has been established with
+    pub fn timeouts(&self) -> &ConnectionTimeouts {
+        &self.timeouts
+    }
+
+    /// Returns the kind of the last I/O error the
+    /// tunnel has failed with, if any, just as
+    /// `TcpNoAuthStream::last_error_kind` does
+    pub fn last_error_kind(&self) -> Option<io::ErrorKind> {
+        self.last_error
+    }
+
+    /// Reads from the tunnel within the read timeout
+    /// of the constructor (with no limit if there is
+    /// none), so that the timeouts bound the whole
+    /// lifetime of the connection and not just the
+    /// handshake. Fails with `io::ErrorKind::TimedOut`
+    /// if the timeout is reached
+    pub async fn read_timed(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_timeout = self.timeouts.read_timeout;
+        general::read_timed(self, buf, read_timeout).await
+    }
+
+    /// Writes to the tunnel within the write timeout
+    /// of the constructor, just as `read_timed` reads,
+    /// returning the number of bytes written.
+    /// Fails with `io::ErrorKind::TimedOut`
+    /// if the timeout is reached
+    pub async fn write_timed(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let write_timeout = self.timeouts.write_timeout;
+        general::write_timed(self, buf, write_timeout).await
+    }
+
+    /// Sets the `SO_LINGER` option of the underlying
+    /// tcp stream, bounding how long closing it waits
+    /// for the unsent data to be transmitted
+    pub fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
+        self.wrapped_stream.set_linger(linger)
+    }
+
+    /// Sets the `SO_KEEPALIVE` option of the underlying
+    /// tcp stream along with the idle time before the first
+    /// probe (`None` disables it), so that an idle tunnel is
+    /// not dropped by a NAT forgetting idle connections.
+    /// The probes are tcp ones, since any bytes written
+    /// into the tunnel would reach the destination
+    pub fn set_keepalive(&self, keepalive: Option<Duration>) -> io::Result<()> {
+        self.wrapped_stream.set_keepalive(keepalive)
     }
 }
 
@@ -185,8 +528,23 @@ impl AsyncRead for S4NoIdentStream {
     fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8])
         -> Poll<io::Result<usize>>
     {
-        let pinned = &mut Pin::into_inner(self).wrapped_stream;
-        Pin::new(pinned).poll_read(cx, buf)
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_read(cx, buf);
+        record_error(&mut this.last_error, poll)
+    }
+
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [MaybeUninit<u8>]) -> bool {
+        self.wrapped_stream.prepare_uninitialized_buffer(buf)
+    }
+
+    fn poll_read_buf<B: BufMut>(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut B)
+        -> Poll<io::Result<usize>>
+    where
+        Self: Sized
+    {
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_read_buf(cx, buf);
+        record_error(&mut this.last_error, poll)
     }
 }
 
@@ -194,27 +552,70 @@ impl AsyncWrite for S4NoIdentStream {
     fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8])
         -> Poll<Result<usize, io::Error>>
     { 
-        let stream = &mut Pin::into_inner(self).wrapped_stream;
-        Pin::new(stream).poll_write(cx, buf)
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_write(cx, buf);
+        record_error(&mut this.last_error, poll)
+    }
+
+    fn poll_write_buf<B: Buf>(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut B)
+        -> Poll<Result<usize, io::Error>>
+    where
+        Self: Sized
+    {
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_write_buf(cx, buf);
+        record_error(&mut this.last_error, poll)
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>)
         -> Poll<Result<(), io::Error>>
     {
-        let stream = &mut Pin::into_inner(self).wrapped_stream;
-        Pin::new(stream).poll_flush(cx)
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_flush(cx);
+        record_error(&mut this.last_error, poll)
     }
 
     fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>)
         -> Poll<Result<(), io::Error>>
     {
-        let stream = &mut Pin::into_inner(self).wrapped_stream;
-        Pin::new(stream).poll_shutdown(cx)
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_shutdown(cx);
+        record_error(&mut this.last_error, poll)
+    }
+}
+
+impl From<S4NoIdentStream> for TcpStream {
+    fn from(stream: S4NoIdentStream) -> TcpStream {
+        stream.wrapped_stream
+    }
+}
+
+/// Gives the raw file descriptor of the
+/// underlying stream, for instance, to set
+/// a socket option the crate does not expose
+#[cfg(unix)]
+impl AsRawFd for S4NoIdentStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.wrapped_stream.as_raw_fd()
     }
 }
 
-impl Into<TcpStream> for S4NoIdentStream {
-    fn into(self) -> TcpStream {
-        self.wrapped_stream
+/// Gives the raw socket of the
+/// underlying stream, for instance, to set
+/// a socket option the crate does not expose
+#[cfg(windows)]
+impl AsRawSocket for S4NoIdentStream {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.wrapped_stream.as_raw_socket()
     }
-}
\ No newline at end of file
+}
+
+/// Prints the peer address of the underlying
+/// tcp stream only, never the transferred data
+impl fmt::Debug for S4NoIdentStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("S4NoIdentStream")
+         .field("peer_addr", &self.wrapped_stream.peer_addr().ok())
+         .finish()
+    }
+}
@@ -1,5 +1,6 @@
 use byteorder::{ByteOrder, BigEndian};
 use std::borrow::Cow;
+use std::fmt;
 use std::str::FromStr;
 use std::net;
 
@@ -10,14 +11,65 @@ use std::net;
 /// between a client and a socks5 server
 pub mod no_auth;
 
-pub use no_auth::TcpNoAuth;
+/// Module contains the authentication
+/// methods of the socks5 protocol, selected
+/// at compile time by `Socks5<A>`
+pub mod auth;
+
+/// Module contains the Socks5 UDP
+/// datagram header handling
+/// (see UDP ASSOCIATE in RFC 1928)
+pub mod udp;
+
+/// Module contains the Tor extension resolving
+/// a domain name through the proxy server
+/// (the RESOLVE command)
+#[cfg(feature = "tor-extensions")]
+pub mod resolve;
+
+/// Module contains the Socks5 handshake
+/// as a state machine owning no I/O,
+/// which the proxy constructors drive
+pub mod handshake;
+
+/// Module contains the parsing of the
+/// destination literals at compile time,
+/// which `destination!` expands to
+#[doc(hidden)]
+pub mod literal;
+
+pub use no_auth::{Socks5, TcpNoAuth, TcpNoAuthBuilder};
+#[cfg(feature = "tls")]
+pub use no_auth::TlsNoAuth;
 
 /// The Socks5 protocol command representation
 #[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Command {
     TcpConnectionEstablishment = 1,
     TcpPortBinding,
-    UdpPortBinding
+    UdpPortBinding,
+    /// The RESOLVE command of Tor (not a part
+    /// of the standard Socks5), see `resolve::Resolve`
+    #[cfg(feature = "tor-extensions")]
+    Resolve = 0xF0
+}
+
+/// Represents a policy of resolving
+/// a domain name destination
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResolutionPolicy {
+    /// The domain name is resolved locally
+    /// and the resolved address is sent
+    /// to the proxy server instead of it
+    /// (useful behind DNS-filtering proxies)
+    Local,
+    /// The domain name is sent to the proxy
+    /// server as it is, so that the server
+    /// resolves it (no local DNS leaks).
+    /// It is the default policy
+    #[default]
+    Remote,
 }
 
 /// Represents a destination address of
@@ -26,6 +78,7 @@ pub enum Command {
 /// It is a good solution, but not
 /// the fastest, so it will be rewritten in the
 /// future in preference to a dispatch mechanism
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Destination {
     /// Represents an IPv4 address
     Ipv4Addr(std::net::Ipv4Addr),
@@ -45,81 +98,287 @@ impl Destination {
             Destination::Ipv6Addr(_) => 16 + 1
         }
     }
+}
+
+/// Appends the fields of a Socks5 payload to
+/// a buffer one after another, so that the length
+/// of the payload is made by construction rather
+/// than computed beforehand, and no field is ever
+/// written at an offset of a pre-sized buffer
+pub(crate) struct PayloadWriter<'b, B: PayloadBuf = Vec<u8>> {
+    /// The buffer the fields are appended to
+    buf: &'b mut B
+}
+
+/// A buffer the fields of a payload are
+/// appended to by a `PayloadWriter`, which is
+/// a vector unless the payload is kept inline
+pub(crate) trait PayloadBuf {
+    /// Appends the bytes to the end of the buffer
+    fn extend_from_slice(&mut self, bytes: &[u8]);
+
+    /// Returns the number of bytes in the buffer
+    fn position(&self) -> usize;
+}
+
+impl PayloadBuf for Vec<u8> {
+    fn extend_from_slice(&mut self, bytes: &[u8]) {
+        Vec::extend_from_slice(self, bytes)
+    }
+
+    fn position(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<'b, B: PayloadBuf> PayloadWriter<'b, B> {
+    /// Creates a writer appending
+    /// to the end of the buffer `buf`
+    pub(crate) fn new(buf: &'b mut B) -> PayloadWriter<'b, B> {
+        PayloadWriter { buf }
+    }
+
+    /// Returns the number of bytes in the buffer,
+    /// which is the position the next field is appended at
+    pub(crate) fn position(&self) -> usize {
+        self.buf.position()
+    }
+
+    /// Appends a single byte field
+    /// (for instance, the version or the command)
+    pub(crate) fn byte(&mut self, byte: u8) -> &mut Self {
+        self.buf.extend_from_slice(&[byte]);
+        self
+    }
+
+    /// Appends raw bytes
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.buf.extend_from_slice(bytes);
+        self
+    }
+
+    /// Appends a port (in the network byte order)
+    pub(crate) fn port(&mut self, port: u16) -> &mut Self {
+        self.bytes(&port.to_be_bytes())
+    }
+
+    /// Appends bytes prefixed with their length
+    /// as a single byte (for instance, a domain name
+    /// or a username), failing with nothing appended
+    /// if they are longer than 255 bytes
+    pub(crate) fn short_bytes(&mut self, bytes: &[u8])
+        -> Result<&mut Self, ()>
+    {
+        if bytes.len() > 255 {
+            return Err(())
+        }
+
+        self.byte(bytes.len() as u8);
+        Ok(self.bytes(bytes))
+    }
 
-    /// Extends buffer with a buffer
-    /// representation of a Destination
+    /// Appends the buffer representation of a destination:
+    /// the address type followed by the address itself
     /// (See the Socks5 wiki for more information).
-    ///
-    /// Note:
-    ///     I wanted to make this function generic, such as
-    ///     it would have the signature like that:
-    ///     ```
-    ///     fn extend_buffer(buf: impl AsMut<u8>)
-    ///     ```
-    ///     but it was preffered to don't to it, because the sense of this
-    ///     flexibility will lead to longer compilation time, and that
-    ///     is totally okay in most of cases, but the function is not even
-    ///     `pub(crate)`, so the choice is obvious
-    ///
-    fn extend_buffer(&mut self, buf: &mut [u8])
-        -> Result<(), ()>
+    /// Fails with nothing appended if a domain name
+    /// is longer than 255 bytes
+    pub(crate) fn destination(&mut self, destination: &Destination)
+        -> Result<&mut Self, ()>
     {
-        match self {
+        match destination {
             Destination::Ipv4Addr(addr) => {
                 // If the destination is an IPv4 address, then
-                // the first byte of the buffer will
-                // contain `0x01`
-                buf[0] = 0x01;
-
-                // Then we need represent the IPv4
-                // address as a buffer (in the network byte order)
-                // and copy it to our buffer `buf`
-                BigEndian::write_u32(&mut buf[1..5], addr.clone().into());
+                // the address type is `0x01`, followed by the
+                // address (in the network byte order)
+                Ok(self.byte(0x01).bytes(&addr.octets()))
             },
             Destination::DomainName(name) => {
                 // If the destination is a domain name, then
-                // the first byte of the buffer will
-                // contain `0x03`
-                buf[0] = 0x03;
-
-                // Then we need to compute the length
-                // of the domain name and store it
-                // as a next byte in the buffer.
-                // The length cannot be larger than
-                // the maxumim value of a byte (0xFF or 255),
-                // so we need to make sure of it
-
+                // the address type is `0x03`, followed by the
+                // length of the domain name and the name itself.
+                // The length cannot be larger than the maximum
+                // value of a byte (0xFF or 255), which is checked
+                // before anything is appended
                 if name.len() > 255 {
                     return Err(())
                 }
 
-                // Storing the length
-                buf[1] = name.len() as u8;
-
-                // Then the socks5 protocol requires us to 
-                // represent the domain name address as
-                // a buffer and copy it to our buffer `buf`
-                buf[2..].clone_from_slice(name.as_bytes());
+                self.byte(0x03).short_bytes(name.as_bytes())
             },
             Destination::Ipv6Addr(addr) => {
                 // If the destination is an IPv6 address, then
-                // the first byte of the buffer will
-                // contain `0x04`
-                buf[0] = 0x04;
-
-                // Then we need represent the IPv4
-                // address as a buffer (in the network byte order)
-                // and copy it to our buffer `buf`
-                BigEndian::write_u128(&mut buf[1..17], addr.clone().into());
+                // the address type is `0x04`, followed by the
+                // address (in the network byte order)
+                Ok(self.byte(0x04).bytes(&addr.octets()))
+            }
+        }
+    }
+}
+
+impl Destination {
+    /// Splits a socket address into
+    /// an IP address destination and a port
+    pub fn from_socket_addr(addr: net::SocketAddr) -> (Destination, u16) {
+        match addr {
+            net::SocketAddr::V4(addr) => (Destination::Ipv4Addr(*addr.ip()), addr.port()),
+            net::SocketAddr::V6(addr) => (Destination::Ipv6Addr(*addr.ip()), addr.port())
+        }
+    }
+
+    /// Parses a buffer representation
+    /// of a Destination (the address type byte
+    /// followed by the address) from the beginning
+    /// of the buffer and returns the destination
+    /// with the number of bytes it takes up
+    pub(crate) fn from_buffer(buf: &[u8])
+        -> Result<(Destination, usize), ()>
+    {
+        match buf.first() {
+            // An IPv4 address, which takes up 4 bytes
+            // after the address type byte
+            Some(0x01) if buf.len() >= 5 => {
+                let addr = BigEndian::read_u32(&buf[1..5]);
+                Ok((Destination::Ipv4Addr(addr.into()), 1 + 4))
+            },
+            // A domain name, the first byte
+            // of which is the name length
+            Some(0x03) if buf.len() >= 2 && buf.len() >= 2 + buf[1] as usize => {
+                let len = buf[1] as usize;
+                let name = std::str::from_utf8(&buf[2..2 + len])
+                                    .map_err(|_| ())?;
+
+                Ok((Destination::DomainName(Cow::Owned(name.to_owned())), 2 + len))
+            },
+            // An IPv6 address, which takes up 16 bytes
+            // after the address type byte
+            Some(0x04) if buf.len() >= 17 => {
+                let addr = BigEndian::read_u128(&buf[1..17]);
+                Ok((Destination::Ipv6Addr(addr.into()), 1 + 16))
+            },
+            // Either an unknown address
+            // type or a truncated buffer
+            _ => Err(())
+        }
+    }
+}
+
+/// Formats the destination as the host it represents
+/// (a dotted-quad IPv4 address, an IPv6 address enclosed
+/// in brackets or a domain name), which `FromStr` parses
+/// back into the same destination
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Destination::Ipv4Addr(addr) => write!(f, "{}", addr),
+            Destination::DomainName(name) => f.write_str(name),
+            Destination::Ipv6Addr(addr) => write!(f, "[{}]", addr)
+        }
+    }
+}
+
+/// Represents the port of a destination,
+/// so that it is never confused with another
+/// numeric argument of a constructor.
+/// The constructors accept `impl Into<Port>`,
+/// so that a bare `u16` is accepted as well
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Port(pub u16);
+
+impl From<u16> for Port {
+    fn from(port: u16) -> Port {
+        Port(port)
+    }
+}
+
+impl From<Port> for u16 {
+    fn from(port: Port) -> u16 {
+        port.0
+    }
+}
+
+/// Parses a port from its decimal representation
+impl FromStr for Port {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Port, Self::Err> {
+        s.parse().map(Port)
+    }
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Represents an error that can occur
+/// when creating a destination from a string
+#[derive(Debug, PartialEq, Eq)]
+pub enum DestinationError {
+    /// Indicates that the string is neither
+    /// an IP address, nor a valid domain name
+    InvalidDomainName,
+    /// Indicates that an internationalized
+    /// domain name cannot be converted to its
+    /// ASCII form (the `idna` feature only)
+    IdnaFailure
+}
+
+impl Destination {
+    /// Creates a domain name destination,
+    /// which is validated before being created.
+    ///
+    /// With the `idna` feature enabled, an
+    /// internationalized domain name (for instance,
+    /// `münchen.de`) is converted to its ASCII form
+    /// (`xn--mnchen-3ya.de`), since Socks5 domain
+    /// names are sent as ASCII.
+    ///
+    /// The name is validated with `webpki` (the
+    /// `dns-validation` feature, on by default),
+    /// or else by its length and characters only
+    pub fn hostname(name: &str) -> Result<Destination, DestinationError> {
+        // Converting the domain
+        // name to its ASCII form
+        #[cfg(feature = "idna")]
+        let name = &idna::domain_to_ascii(name)
+                         .map_err(|_| DestinationError::IdnaFailure)?;
+
+        // Validating the domain name
+        #[cfg(feature = "dns-validation")]
+        webpki::DNSNameRef::try_from_ascii_str(name)
+                           .map_err(|_| DestinationError::InvalidDomainName)?;
+        #[cfg(not(feature = "dns-validation"))]
+        {
+            if !is_valid_hostname(name) {
+                return Err(DestinationError::InvalidDomainName)
             }
         }
 
-        Ok(())
+        Ok(Destination::DomainName(Cow::Owned(name.to_owned())))
     }
 }
 
+/// Checks a domain name by its length and
+/// characters only, in place of `webpki` (without
+/// the `dns-validation` feature): the name (with an
+/// optional trailing dot) is at most 253 bytes long
+/// and consists of labels of 1 to 63 letters, digits,
+/// hyphens and underscores, neither starting nor
+/// ending with a hyphen
+#[cfg(not(feature = "dns-validation"))]
+fn is_valid_hostname(name: &str) -> bool {
+    let name = name.strip_suffix('.').unwrap_or(name);
+
+    !name.is_empty() && name.len() <= 253 && name.split('.').all(|label| {
+        !label.is_empty() && label.len() <= 63
+            && !label.starts_with('-') && !label.ends_with('-')
+            && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    })
+}
+
 impl FromStr for Destination {
-    type Err = ();
+    type Err = DestinationError;
 
     /// Parses a socks5 destination.
     /// The parsing algorithm is simpler than
@@ -128,30 +387,43 @@ impl FromStr for Destination {
     ///     from the string.
     ///     If succeed, returns an IPv4 destination representation,
     ///     If not, it tries to parse an IPv6
+    ///     (optionally enclosed in brackets and
+    ///     optionally with a `%zone`, which is dropped)
     ///     from the string.
     ///     Then, if succeed, returns an IPv6 destination representation.
     ///     Finally, if not, it tries to parse a domain name
-    ///     from the string and returns a domain name destination
+    ///     from the string (see `Destination::hostname`)
+    ///     and returns a domain name destination
     ///     if succeed, unless `Err`
     fn from_str(s: &str) -> Result<Destination, Self::Err> {
         // Trying to parse an ipv4 address from the string
         // (Actually, not the best code, but better that
         //  multiple calls of `.map` or nested matched for ex.)
-        let result = s.parse::<net::Ipv4Addr>();
-        if result.is_ok() { 
-            return Ok(Destination::Ipv4Addr(result.unwrap()))
+        if let Ok(addr) = s.parse::<net::Ipv4Addr>() {
+            return Ok(Destination::Ipv4Addr(addr))
         }
-       
-        // Trying to parse an IPv6 address from the string
-        let result = s.parse::<net::Ipv6Addr>();
-        if result.is_ok() { 
-            return Ok(Destination::Ipv6Addr(result.unwrap()))
+
+        // Trying to parse an IPv6 address from the string,
+        // which may be enclosed in brackets (just as it is
+        // displayed), so that a displayed destination
+        // is parsed back into the same destination
+        let unbracketed = s.strip_prefix('[')
+                           .and_then(|s| s.strip_suffix(']'))
+                           .unwrap_or(s);
+
+        // A scoped IPv6 address (for instance, `fe80::1%eth0`)
+        // carries a zone that only makes sense on the local
+        // host and cannot be sent in a Socks5 request,
+        // so that the zone is dropped
+        let unscoped = match unbracketed.split_once('%') {
+            Some((addr, zone)) if !zone.is_empty() => addr,
+            _ => unbracketed
+        };
+        if let Ok(addr) = unscoped.parse::<net::Ipv6Addr>() {
+            return Ok(Destination::Ipv6Addr(addr))
         }
 
         // Trying to parse a domain name
-        webpki::DNSNameRef::try_from_ascii_str(s)
-                           .map_err(|_| ())?;
-
-        Ok(Destination::DomainName(Cow::Owned(s.to_owned())))
+        Destination::hostname(s)
     }
 }
\ No newline at end of file
@@ -0,0 +1,375 @@
+use crate::clients::socks5::no_auth::{AuthenticationKind, ErrorKind, NotSupportedMethod};
+use crate::clients::socks5::PayloadWriter;
+use crate::general::{
+    optional_timeout, trace_bytes, trace_redacted_bytes, ConnectionTimeouts, IOStream, TimeoutPhase,
+};
+use std::borrow::Cow;
+use std::ops::Range;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// A trait of an authentication method
+/// of the Socks5 protocol, selecting which
+/// at compile time (as the `A` of `Socks5<A>`)
+/// makes unsupported methods unrepresentable.
+///
+/// Custom methods (for instance, one of the
+/// private methods 0x80-0xFE) are added by
+/// implementing the trait outside of the crate
+#[async_trait::async_trait]
+pub trait AuthMethod: Send + Sync {
+    /// Returns the code of the method
+    /// (METHOD) offered in the greeting
+    fn method_id(&self) -> u8;
+
+    /// Appends the codes of all the methods offered
+    /// in the greeting to `methods`, in the order of
+    /// preference. By default it is the method of
+    /// `method_id` only
+    fn offer_methods(&self, methods: &mut Vec<u8>) {
+        methods.push(self.method_id());
+    }
+
+    /// Checks that the method can be offered at all.
+    /// It is called before anything is sent to the server,
+    /// so that an error never leaves a half-sent greeting
+    fn validate(&self) -> Result<(), ErrorKind> {
+        Ok(())
+    }
+
+    /// Tells whether the method is one of the crate's own
+    /// ones offering no authentication alone, the greeting
+    /// of which the handshake builds itself with no
+    /// subnegotiation to perform. It cannot be overridden
+    /// outside of the crate, where it is always `false`
+    #[doc(hidden)]
+    fn is_builtin_no_auth(&self, _: sealed::Token) -> bool {
+        false
+    }
+
+    /// Performs the method-dependent subnegotiation,
+    /// once the server has selected the method
+    async fn negotiate<S>(
+        &self,
+        stream: &mut S,
+        timeouts: &ConnectionTimeouts,
+    ) -> Result<(), ErrorKind>
+    where
+        S: IOStream + Send;
+
+    /// Performs the subnegotiation of the method
+    /// the server has selected among the offered ones.
+    /// By default it is `negotiate`, since
+    /// a single method is offered
+    async fn negotiate_selected<S>(
+        &self,
+        _selected: u8,
+        stream: &mut S,
+        timeouts: &ConnectionTimeouts,
+    ) -> Result<(), ErrorKind>
+    where
+        S: IOStream + Send,
+    {
+        self.negotiate(stream, timeouts).await
+    }
+}
+
+/// The token only the crate is able to create,
+/// so that the methods taking it are never
+/// overridden outside of the crate
+pub(crate) mod sealed {
+    pub struct Token(pub(crate) ());
+}
+
+/// The "no authentication required" method (0x00)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoAuth;
+
+/// The `Username/Password` method (0x02),
+/// authenticating with the subnegotiation
+/// described in RFC 1929
+#[derive(Clone, Debug)]
+pub struct UserPass<'a> {
+    /// The username (UNAME)
+    username: Cow<'a, str>,
+    /// The password (PASSWD)
+    password: Cow<'a, str>,
+}
+
+/// The GSSAPI method (0x01), which is offered in
+/// the greeting, but its subnegotiation (RFC 1961)
+/// is not implemented, so that the server selecting
+/// it fails the handshake with
+/// `ErrorKind::Method(NotSupportedMethod::GssAPI)`.
+///
+/// It tells a server requiring GSSAPI from one
+/// accepting none of the methods offered
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Gssapi;
+
+impl<'a> UserPass<'a> {
+    pub fn new(
+        username: impl Into<Cow<'a, str>>,
+        password: impl Into<Cow<'a, str>>,
+    ) -> UserPass<'a> {
+        UserPass {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    /// Returns the username
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+}
+
+#[async_trait::async_trait]
+impl AuthMethod for NoAuth {
+    fn method_id(&self) -> u8 {
+        0x00
+    }
+
+    fn is_builtin_no_auth(&self, _: sealed::Token) -> bool {
+        true
+    }
+
+    async fn negotiate<S>(&self, _: &mut S, _: &ConnectionTimeouts) -> Result<(), ErrorKind>
+    where
+        S: IOStream + Send,
+    {
+        // There is no subnegotiation at all
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl AuthMethod for Gssapi {
+    fn method_id(&self) -> u8 {
+        0x01
+    }
+
+    async fn negotiate<S>(&self, _: &mut S, _: &ConnectionTimeouts) -> Result<(), ErrorKind>
+    where
+        S: IOStream + Send,
+    {
+        // The security context is never established
+        Err(ErrorKind::Method(NotSupportedMethod::GssAPI))
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a> AuthMethod for UserPass<'a> {
+    fn method_id(&self) -> u8 {
+        0x02
+    }
+
+    fn validate(&self) -> Result<(), ErrorKind> {
+        validate_credentials(&self.username, &self.password)
+    }
+
+    async fn negotiate<S>(
+        &self,
+        stream: &mut S,
+        timeouts: &ConnectionTimeouts,
+    ) -> Result<(), ErrorKind>
+    where
+        S: IOStream + Send,
+    {
+        authenticate(&self.username, &self.password, stream, timeouts).await
+    }
+}
+
+/// The authentication method selected at runtime,
+/// which is the one `TcpNoAuth` uses.
+///
+/// With credentials, both `Username/Password`
+/// and no authentication are offered (the strongest
+/// first), so that the same client connects both to
+/// the servers requiring the credentials and to
+/// the ones requiring none
+#[async_trait::async_trait]
+impl<'a> AuthMethod for AuthenticationKind<'a> {
+    fn method_id(&self) -> u8 {
+        match self {
+            AuthenticationKind::NoAuthentication => 0x00,
+            AuthenticationKind::GenericSecurityServicesAPI => 0x01,
+            AuthenticationKind::UsernamePassword { .. } => 0x02,
+            AuthenticationKind::PrivateMethods => 0x80,
+            AuthenticationKind::NoAcceptable => 0xFF,
+        }
+    }
+
+    fn offer_methods(&self, methods: &mut Vec<u8>) {
+        methods.push(self.method_id());
+
+        if let AuthenticationKind::UsernamePassword { .. } = self {
+            methods.push(0x00);
+        }
+    }
+
+    fn is_builtin_no_auth(&self, _: sealed::Token) -> bool {
+        matches!(self, AuthenticationKind::NoAuthentication)
+    }
+
+    fn validate(&self) -> Result<(), ErrorKind> {
+        match self {
+            AuthenticationKind::NoAuthentication => Ok(()),
+            AuthenticationKind::UsernamePassword { username, password } => {
+                validate_credentials(username, password)
+            }
+
+            // The rest of the methods are not implemented,
+            // so that we must not send a handshake
+            // we are unable to proceed with
+            AuthenticationKind::GenericSecurityServicesAPI => {
+                Err(ErrorKind::Method(NotSupportedMethod::GssAPI))
+            }
+            AuthenticationKind::PrivateMethods => {
+                Err(ErrorKind::Method(NotSupportedMethod::PrivateMethods))
+            }
+            AuthenticationKind::NoAcceptable => {
+                Err(ErrorKind::Method(NotSupportedMethod::NoAcceptable))
+            }
+        }
+    }
+
+    async fn negotiate<S>(
+        &self,
+        stream: &mut S,
+        timeouts: &ConnectionTimeouts,
+    ) -> Result<(), ErrorKind>
+    where
+        S: IOStream + Send,
+    {
+        self.validate()?;
+
+        match self {
+            AuthenticationKind::UsernamePassword { username, password } => {
+                authenticate(username, password, stream, timeouts).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn negotiate_selected<S>(
+        &self,
+        selected: u8,
+        stream: &mut S,
+        timeouts: &ConnectionTimeouts,
+    ) -> Result<(), ErrorKind>
+    where
+        S: IOStream + Send,
+    {
+        match selected {
+            // The server requires no authentication,
+            // so that the credentials are not sent
+            0x00 => self.validate(),
+            _ => self.negotiate(stream, timeouts).await,
+        }
+    }
+}
+
+/// Checks that the credentials fit the
+/// one-byte length fields (ULEN and PLEN)
+pub(crate) fn validate_credentials(username: &str, password: &str) -> Result<(), ErrorKind> {
+    if username.len() > 255 || password.len() > 255 {
+        return Err(ErrorKind::CredentialsTooLong);
+    }
+
+    Ok(())
+}
+
+/// Performs the `Username/Password`
+/// subnegotiation (RFC 1929)
+async fn authenticate<S>(
+    username: &str,
+    password: &str,
+    stream: &mut S,
+    timeouts: &ConnectionTimeouts,
+) -> Result<(), ErrorKind>
+where
+    S: IOStream + Send,
+{
+    let (buf, password_range) = credentials_payload(username, password)?;
+
+    // Writing the request to the server
+    let future = stream.write_all(&buf);
+    let future = optional_timeout(timeouts.write_timeout, future);
+    future
+        .await
+        .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Write))??;
+    trace_redacted_bytes("sent", &buf, password_range);
+
+    // Reading the reply from the server, which is
+    // exactly 2 bytes long (the version and the status),
+    // as a whole, since a single read may return it in parts
+    let mut reply = [0; 2];
+    let future = stream.read_exact(&mut reply);
+    let future = optional_timeout(timeouts.read_timeout, future);
+    future
+        .await
+        .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Read))??;
+    trace_bytes("received", &reply);
+
+    check_credentials_reply(reply)
+}
+
+/// Builds the request of the `Username/Password`
+/// subnegotiation and returns it along with
+/// the range of the password inside of it
+pub(crate) fn credentials_payload(
+    username: &str,
+    password: &str,
+) -> Result<(Vec<u8>, Range<usize>), ErrorKind> {
+    // Computing the length of the request
+    // The buffer length is computed this way:
+    //  (+1) for the version of the subnegotiation (0x01)
+    //  (+1) for the username length (ULEN)
+    //  (+n) for the username (UNAME)
+    //  (+1) for the password length (PLEN)
+    //  (+n) for the password (PASSWD)
+    let mut buf = Vec::with_capacity(1 + 1 + username.len() + 1 + password.len());
+    let mut writer = PayloadWriter::new(&mut buf);
+
+    // The VER field contains the current version of the subnegotiation
+    // which is X'01'
+    writer.byte(1);
+
+    // Set username length to the ULEN field
+    // and username to the UNAME field
+    writer
+        .short_bytes(username.as_bytes())
+        .map_err(|_| ErrorKind::CredentialsTooLong)?;
+
+    // Set password length to the PLEN field
+    // and password to the PASSWD field,
+    // remembering where it is, so that
+    // it is never dumped
+    let password_at = writer.position() + 1;
+    writer
+        .short_bytes(password.as_bytes())
+        .map_err(|_| ErrorKind::CredentialsTooLong)?;
+    let password_range = password_at..password_at + password.len();
+
+    Ok((buf, password_range))
+}
+
+/// Checks the reply to the `Username/Password`
+/// subnegotiation (the version and the status)
+pub(crate) fn check_credentials_reply(reply: [u8; 2]) -> Result<(), ErrorKind> {
+    // The version of the subnegotiation
+    // must be the one we have sent (0x01)
+    if reply[0] != 0x01 {
+        return Err(ErrorKind::BadBuffer);
+    }
+
+    // The status 0x00 means that the server
+    // has accepted our credentials, so that we
+    // can proceed to the connection request.
+    // Any other status means a failure
+    if reply[1] != 0x00 {
+        return Err(ErrorKind::AuthenticationFailed);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,701 @@
+use crate::clients::socks5::auth::{
+    check_credentials_reply, credentials_payload, validate_credentials,
+};
+use crate::clients::socks5::no_auth::{ErrorKind, DEFAULT_MAX_REPLY_BYTES};
+use crate::clients::socks5::{Command, Destination, PayloadBuf, PayloadWriter, Port};
+use crate::proxy::{BoundAddr, BoundEndpoint};
+use byteorder::{BigEndian, ByteOrder};
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::ops::Deref;
+
+/// The number of bytes kept inline by the buffers
+/// of a handshake, which is the length of the longest
+/// reply bound to an IP address (an IPv6 one) and of
+/// the longest request to one, so that a handshake with
+/// neither a domain name, nor credentials allocates nothing
+pub(crate) const REPLY_CAPACITY: usize = 4 + 16 + 2;
+
+/// Represents the Socks5 handshake as a state
+/// machine that owns no I/O at all (so-called
+/// sans-I/O), so that the protocol can be driven
+/// over any transport (an in-memory pipe, a QUIC
+/// stream or a test harness) with the bytes written
+/// and read by the caller. `Socks5::connect`
+/// is a tokio driver of it.
+///
+/// The caller calls `step` with the bytes read from
+/// the server (nothing at first, and nothing after
+/// a `Step::Write` or a `Step::Authenticate`), and acts
+/// on the returned step until it is `Step::Done`
+/// or `Step::Error`. If the server closes the
+/// connection instead, `closed` returns the error.
+///
+/// # Example
+///
+/// ```no_run
+/// use async_proxy::clients::socks5::handshake::{Socks5Handshake, Step};
+/// use async_proxy::clients::socks5::Destination;
+/// use std::io::{Read, Write};
+/// use std::net::{Ipv4Addr, TcpStream};
+///
+/// let mut stream = TcpStream::connect("127.0.0.1:1080").unwrap();
+/// let mut handshake = Socks5Handshake::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80);
+///
+/// let mut input = Vec::new();
+/// let bound = loop {
+///     match handshake.step(&input) {
+///         Step::Write(payload) => {
+///             stream.write_all(&payload).unwrap();
+///             input.clear();
+///         }
+///         Step::NeedMore(len) => {
+///             input.resize(len, 0);
+///             let read_bytes = stream.read(&mut input).unwrap();
+///             if read_bytes == 0 {
+///                 panic!("{}", handshake.closed());
+///             }
+///             input.truncate(read_bytes);
+///         }
+///         Step::Authenticate(_) => unreachable!("no methods are delegated"),
+///         Step::Done { bound, .. } => break bound,
+///         Step::Error(e) => panic!("{}", e),
+///     }
+/// };
+/// ```
+pub struct Socks5Handshake {
+    /// The destination sent in the request
+    destination: Destination,
+    /// The port of the destination service
+    port: u16,
+    /// The command sent in the request
+    command: Command,
+    /// The version byte of the protocol,
+    /// which is 0x05 unless the server
+    /// is a non-standard one
+    pub(crate) version: u8,
+    /// The authentication methods offered
+    /// to the server, the strongest first
+    methods: Cow<'static, [u8]>,
+    /// Whether the subnegotiation of a selected
+    /// method is left to the caller
+    delegated: bool,
+    /// The credentials of the `Username/Password`
+    /// subnegotiation performed by the machine itself
+    credentials: Option<(String, String)>,
+    /// The maximum length of a reply
+    /// the server may declare
+    max_reply_bytes: usize,
+    /// The bytes received but
+    /// not consumed yet
+    received: InlineBuf,
+    /// The method selected by the server
+    selected: Option<u8>,
+    /// The reply field of the server's
+    /// reply to the request, once received
+    reply_code: Option<u8>,
+    /// The state of the handshake
+    state: State,
+}
+
+/// Represents what the caller
+/// of `Socks5Handshake::step` has to do next,
+/// where `P` is the type of the bytes to be written
+/// (which are a vector for the caller of `step`)
+#[derive(Debug)]
+pub enum Step<P = Vec<u8>> {
+    /// The bytes to be written to the server,
+    /// after which `step` is called with nothing
+    Write(P),
+    /// The number of bytes the current message is
+    /// missing, which are to be read from the server
+    /// and passed to `step`. Reading no more than that
+    /// keeps the bytes following the handshake
+    /// (the tunnel's ones) in the stream
+    NeedMore(usize),
+    /// The server has selected a delegated method
+    /// (see `Socks5Handshake::with_methods`), which
+    /// subnegotiation is to be performed by the caller
+    /// before `step` is called with nothing
+    Authenticate(u8),
+    /// The handshake has succeeded
+    Done {
+        /// The endpoint reported
+        /// in the server's reply
+        bound: BoundEndpoint,
+        /// The authentication method
+        /// selected by the server
+        auth_method: u8,
+    },
+    /// The handshake has failed
+    Error(ErrorKind),
+}
+
+/// The state of the handshake,
+/// that is, what comes next
+enum State {
+    /// Nothing has been sent yet
+    Start,
+    /// Waiting for the method selection
+    Greeting,
+    /// Waiting for the caller to perform
+    /// the subnegotiation of a delegated method
+    Delegated,
+    /// Waiting for the reply to the
+    /// `Username/Password` subnegotiation
+    Credentials,
+    /// Waiting for the fixed part of the reply
+    ReplyHeader,
+    /// Waiting for the length of
+    /// a domain name bound address
+    ReplyNameLength,
+    /// Waiting for the bound
+    /// address and port
+    ReplyRest { address_type: u8, rest_len: usize },
+    /// The handshake is over
+    Finished,
+}
+
+impl Socks5Handshake {
+    /// Creates the handshake of a request to
+    /// `destination`, offering no authentication
+    pub fn new(destination: Destination, port: impl Into<Port>) -> Socks5Handshake {
+        Socks5Handshake {
+            destination,
+            port: port.into().0,
+            command: Command::TcpConnectionEstablishment,
+            version: 5,
+            methods: Cow::Borrowed(&[0x00]),
+            delegated: false,
+            credentials: None,
+            max_reply_bytes: DEFAULT_MAX_REPLY_BYTES,
+            received: InlineBuf::new(),
+            selected: None,
+            reply_code: None,
+            state: State::Start,
+        }
+    }
+
+    /// Creates a handshake that is only waiting for
+    /// a reply (for instance, the second reply
+    /// to the tcp port binding request)
+    pub(crate) fn awaiting_reply(max_reply_bytes: usize) -> Socks5Handshake {
+        let mut handshake = Socks5Handshake::new(Destination::Ipv4Addr(Ipv4Addr::UNSPECIFIED), 0);
+        handshake.max_reply_bytes = max_reply_bytes;
+        handshake.state = State::ReplyHeader;
+        handshake
+    }
+
+    /// Sets the command sent in the request,
+    /// by default it is the tcp connection
+    /// establishment (CONNECT)
+    pub fn with_command(&mut self, command: Command) {
+        self.command = command;
+    }
+
+    /// Offers both `Username/Password` and no
+    /// authentication (the strongest first), where
+    /// the subnegotiation is performed by the machine
+    pub fn with_credentials(&mut self, username: impl Into<String>, password: impl Into<String>) {
+        self.methods = Cow::Borrowed(&[0x02, 0x00]);
+        self.delegated = false;
+        self.credentials = Some((username.into(), password.into()));
+    }
+
+    /// Offers the `methods` instead, where the
+    /// subnegotiation of the one selected (no
+    /// authentication included) is performed by
+    /// the caller, as `Step::Authenticate` tells
+    pub fn with_methods(&mut self, methods: &[u8]) {
+        self.methods = Cow::Owned(methods.to_vec());
+        self.delegated = true;
+        self.credentials = None;
+    }
+
+    /// Sets the version byte written in the greeting
+    /// and the request and expected in the replies,
+    /// by default it is 0x05.
+    ///
+    /// It is an escape hatch for the servers that
+    /// speak Socks5 with another version byte, which
+    /// are not compliant with RFC 1928
+    #[cfg(feature = "custom-protocol-version")]
+    pub fn with_protocol_version(&mut self, version: u8) {
+        self.version = version;
+    }
+
+    /// Sets the maximum length of a reply (in bytes),
+    /// by default it is `DEFAULT_MAX_REPLY_BYTES`
+    pub fn with_max_reply_bytes(&mut self, max_reply_bytes: usize) {
+        self.max_reply_bytes = max_reply_bytes;
+    }
+
+    /// Replaces the destination, which can be done
+    /// until the request is written (for instance,
+    /// once a domain name is resolved locally
+    /// during a delegated subnegotiation)
+    pub fn set_destination(&mut self, destination: Destination) {
+        self.destination = destination;
+    }
+
+    /// Returns the reply field of the server's
+    /// reply to the request, or `None`
+    /// if it has not been received yet
+    pub fn reply_code(&self) -> Option<u8> {
+        self.reply_code
+    }
+
+    /// Returns the bytes received past the
+    /// handshake, which belong to the tunnel
+    pub fn buffered(&self) -> &[u8] {
+        &self.received
+    }
+
+    /// Returns the error of the server having
+    /// closed the connection in the middle
+    /// of the handshake
+    pub fn closed(&mut self) -> ErrorKind {
+        let kind = match self.state {
+            // The server has hung up without a reply
+            State::Greeting if self.received.is_empty() => ErrorKind::ConnectionClosedByProxy,
+            // A short reply is a bad one
+            State::Greeting => ErrorKind::BadBuffer,
+            _ => io::Error::from(io::ErrorKind::UnexpectedEof).into(),
+        };
+        self.state = State::Finished;
+
+        kind
+    }
+
+    /// Consumes the bytes read from the server
+    /// and returns what has to be done next
+    pub fn step(&mut self, input: &[u8]) -> Step {
+        match self.step_inline(input) {
+            Step::Write(payload) => Step::Write(payload.into_vec()),
+            Step::NeedMore(len) => Step::NeedMore(len),
+            Step::Authenticate(selected) => Step::Authenticate(selected),
+            Step::Done { bound, auth_method } => Step::Done { bound, auth_method },
+            Step::Error(kind) => Step::Error(kind),
+        }
+    }
+
+    /// Consumes the bytes read from the server just as
+    /// `step` does, but returns the bytes to be written
+    /// kept inline, so that a handshake fitting into
+    /// `REPLY_CAPACITY` (see `InlineBuf`) allocates nothing
+    pub(crate) fn step_inline(&mut self, input: &[u8]) -> Step<InlineBuf> {
+        self.received.extend_from_slice(input);
+
+        match self.advance() {
+            Err(kind) => {
+                self.state = State::Finished;
+                Step::Error(kind)
+            }
+            Ok(step) => step,
+        }
+    }
+
+    /// Advances the handshake by as many
+    /// states as the received bytes allow
+    fn advance(&mut self) -> Result<Step<InlineBuf>, ErrorKind> {
+        loop {
+            match self.state {
+                State::Start => {
+                    // Checking the credentials, so that we never
+                    // send a handshake we are unable to proceed with
+                    if let Some((username, password)) = &self.credentials {
+                        validate_credentials(username, password)?;
+                    }
+
+                    // The version of the protocol (0x05),
+                    // the number of the methods offered
+                    // and the methods themselves
+                    let mut greeting = InlineBuf::new();
+                    PayloadWriter::new(&mut greeting)
+                        .byte(self.version)
+                        .short_bytes(&self.methods)
+                        .map_err(|_| ErrorKind::BadBuffer)?;
+
+                    self.state = State::Greeting;
+                    return Ok(Step::Write(greeting));
+                }
+                State::Greeting => {
+                    check_reply_start(&self.received, self.version)?;
+
+                    let reply = match self.take::<2>() {
+                        Ok(reply) => reply,
+                        Err(step) => return Ok(step),
+                    };
+                    check_selection(reply, self.version, &self.methods)?;
+                    self.selected = Some(reply[1]);
+
+                    if self.delegated {
+                        self.state = State::Delegated;
+                        return Ok(Step::Authenticate(reply[1]));
+                    }
+
+                    match (&self.credentials, reply[1]) {
+                        (Some((username, password)), 0x02) => {
+                            let (payload, _) = credentials_payload(username, password)?;
+                            self.state = State::Credentials;
+                            return Ok(Step::Write(InlineBuf::from(payload)));
+                        }
+                        _ => return self.request(),
+                    }
+                }
+                State::Delegated => return self.request(),
+                State::Credentials => {
+                    let reply = match self.take::<2>() {
+                        Ok(reply) => reply,
+                        Err(step) => return Ok(step),
+                    };
+                    check_credentials_reply(reply)?;
+
+                    return self.request();
+                }
+                State::ReplyHeader => {
+                    let header = match self.take::<4>() {
+                        Ok(header) => header,
+                        Err(step) => return Ok(step),
+                    };
+                    let address_type =
+                        check_reply_header(header, self.version, &mut self.reply_code)?;
+
+                    // The rest of the reply is the bound address
+                    // (depending on its type) and port (+2)
+                    self.state = match address_type {
+                        // Domain name, the first byte
+                        // of which is the name length
+                        0x03 => State::ReplyNameLength,
+                        _ => self.reply_rest(address_type, ip_rest_len(address_type))?,
+                    };
+                }
+                State::ReplyNameLength => {
+                    let [name_len] = match self.take::<1>() {
+                        Ok(name_len) => name_len,
+                        Err(step) => return Ok(step),
+                    };
+                    self.state = self.reply_rest(0x03, name_len as usize + 2)?;
+                }
+                State::ReplyRest {
+                    address_type,
+                    rest_len,
+                } => {
+                    if let Some(missing) = self.missing(rest_len) {
+                        return Ok(Step::NeedMore(missing));
+                    }
+                    // Parsing the bound endpoint in
+                    // place, before the rest is consumed
+                    let bound = parse_bound(address_type, &self.received[..rest_len])?;
+                    self.received.consume(rest_len);
+
+                    self.state = State::Finished;
+                    return Ok(Step::Done {
+                        bound,
+                        auth_method: self.selected.unwrap_or(0x00),
+                    });
+                }
+                State::Finished => return Err(finished_error().into()),
+            }
+        }
+    }
+
+    /// Builds the request and returns it to be
+    /// written, waiting for the reply afterwards
+    fn request(&mut self) -> Result<Step<InlineBuf>, ErrorKind> {
+        let mut request = InlineBuf::new();
+        PayloadWriter::new(&mut request)
+            // The version of the socks protocol being used
+            .byte(self.version)
+            // The command (for instance,
+            // the tcp connection establishment one)
+            .byte(self.command as u8)
+            // A 0x00 byte as it is
+            // rule of the socks5 protocol
+            .byte(0)
+            // The destination, which domain name
+            // may be too long to be represented
+            .destination(&self.destination)
+            .map_err(|_| ErrorKind::DomainNameTooLong)?
+            // The port as a big endian short
+            .port(self.port);
+
+        self.state = State::ReplyHeader;
+        Ok(Step::Write(request))
+    }
+
+    /// Returns the state waiting for the rest of the reply
+    /// of `rest_len` bytes, unless the whole reply is longer
+    /// than the maximum set, which is checked before anything
+    /// is buffered for the rest of it
+    fn reply_rest(&self, address_type: u8, rest_len: usize) -> Result<State, ErrorKind> {
+        check_reply_len(address_type, rest_len, self.max_reply_bytes)?;
+
+        Ok(State::ReplyRest {
+            address_type,
+            rest_len,
+        })
+    }
+
+    /// Returns the number of bytes missing
+    /// to `len` received ones, if any
+    fn missing(&self, len: usize) -> Option<usize> {
+        match self.received.len() {
+            received if received < len => Some(len - received),
+            _ => None,
+        }
+    }
+
+    /// Consumes a message of `N` bytes,
+    /// unless more bytes are needed for it
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], Step<InlineBuf>> {
+        if let Some(missing) = self.missing(N) {
+            return Err(Step::NeedMore(missing));
+        }
+
+        let mut message = [0; N];
+        message.copy_from_slice(&self.received[..N]);
+        self.received.consume(N);
+        Ok(message)
+    }
+}
+
+/// The bytes of a message of the handshake (either
+/// received or to be written), kept inline up to
+/// `REPLY_CAPACITY` of them and moved to the heap
+/// once they outgrow it (for instance, a domain
+/// name bound address or the credentials)
+pub(crate) struct InlineBuf {
+    /// The bytes kept inline
+    inline: [u8; REPLY_CAPACITY],
+    /// The number of the inline bytes
+    len: usize,
+    /// The bytes moved to the heap,
+    /// which the inline ones are ignored for
+    heap: Option<Vec<u8>>,
+}
+
+impl InlineBuf {
+    /// Creates an empty buffer
+    /// with nothing allocated
+    pub(crate) fn new() -> InlineBuf {
+        InlineBuf {
+            inline: [0; REPLY_CAPACITY],
+            len: 0,
+            heap: None,
+        }
+    }
+
+    /// Appends the bytes, moving all of them
+    /// to the heap if they do not fit inline
+    pub(crate) fn extend_from_slice(&mut self, bytes: &[u8]) {
+        match &mut self.heap {
+            Some(heap) => heap.extend_from_slice(bytes),
+            None if self.len + bytes.len() <= REPLY_CAPACITY => {
+                self.inline[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+                self.len += bytes.len();
+            }
+            None => {
+                let mut heap = Vec::with_capacity(self.len + bytes.len());
+                heap.extend_from_slice(&self.inline[..self.len]);
+                heap.extend_from_slice(bytes);
+                self.heap = Some(heap);
+            }
+        }
+    }
+
+    /// Removes the first `n` bytes
+    pub(crate) fn consume(&mut self, n: usize) {
+        match &mut self.heap {
+            Some(heap) => {
+                heap.drain(..n);
+            }
+            None => {
+                self.inline.copy_within(n..self.len, 0);
+                self.len -= n;
+            }
+        }
+    }
+
+    /// Converts the buffer into a vector,
+    /// which the heap bytes are as they are
+    pub(crate) fn into_vec(self) -> Vec<u8> {
+        match self.heap {
+            Some(heap) => heap,
+            None => self.inline[..self.len].to_vec(),
+        }
+    }
+}
+
+impl Deref for InlineBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.heap {
+            Some(heap) => heap,
+            None => &self.inline[..self.len],
+        }
+    }
+}
+
+impl From<Vec<u8>> for InlineBuf {
+    fn from(heap: Vec<u8>) -> InlineBuf {
+        InlineBuf {
+            inline: [0; REPLY_CAPACITY],
+            len: 0,
+            heap: Some(heap),
+        }
+    }
+}
+
+impl PayloadBuf for InlineBuf {
+    fn extend_from_slice(&mut self, bytes: &[u8]) {
+        InlineBuf::extend_from_slice(self, bytes)
+    }
+
+    fn position(&self) -> usize {
+        self.len()
+    }
+}
+
+impl fmt::Debug for InlineBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Checks the start of the reply to the greeting:
+/// a Socks4 reply starts with 0x00, while an HTTP
+/// one starts with `H` (as in `HTTP/1.1`), so that
+/// we are talking to a wrong proxy server
+fn check_reply_start(received: &[u8], version: u8) -> Result<(), ErrorKind> {
+    match received.first() {
+        Some(&first) if first == version => Ok(()),
+        Some(0x00 | b'H') => Err(ErrorKind::WrongProxyType),
+        _ => Ok(()),
+    }
+}
+
+/// Checks the method selected in the reply
+/// to the greeting against the offered ones
+fn check_selection(reply: [u8; 2], version: u8, methods: &[u8]) -> Result<(), ErrorKind> {
+    // The former byte must be the version
+    if reply[0] != version {
+        return Err(ErrorKind::BadBuffer);
+    }
+
+    // The method 0xFF means that the server
+    // accepts none of the methods we have offered
+    // (for instance, it requires credentials)
+    if reply[1] == 0xFF {
+        return Err(ErrorKind::NoAcceptableAuthMethods);
+    }
+
+    // The selected method must be one of the ones
+    // we have offered. Anything else is a protocol
+    // violation we must not try to interpret
+    if !methods.contains(&reply[1]) {
+        return Err(ErrorKind::BadBuffer);
+    }
+
+    Ok(())
+}
+
+/// Checks the fixed part of the reply to the request,
+/// storing its reply field in `reply_code`, and returns
+/// the type of the bound address following it
+fn check_reply_header(
+    header: [u8; 4],
+    version: u8,
+    reply_code: &mut Option<u8>,
+) -> Result<u8, ErrorKind> {
+    // The fixed part of a reply consists of:
+    //  (+1) for the number of the version of the socks protocol
+    //  (+1) for the reply field
+    //  (+1) for the reserved byte
+    //  (+1) for the bound address type
+    //
+    // The version must be 0x05 and the reserved
+    // byte must be 0x00, anything else means a protocol
+    // violation (or that the server is not a Socks5 one)
+    if header[0] != version || header[2] != 0x00 {
+        return Err(ErrorKind::BadBuffer);
+    }
+
+    // Analyzing the reply field
+    *reply_code = Some(header[1]);
+    match header[1] {
+        // Means that request accepted
+        0x00 => {}
+        0x01 => return Err(ErrorKind::SocksServerFailure),
+        0x02 => return Err(ErrorKind::RequestDenied),
+        0x03 => return Err(ErrorKind::NetworkUnreachable),
+        0x04 => return Err(ErrorKind::HostUnreachable),
+        0x05 => return Err(ErrorKind::ConnectionRefused),
+        0x06 => return Err(ErrorKind::TTLExpired),
+        0x07 => return Err(ErrorKind::NotSupported),
+        0x08 => return Err(ErrorKind::DestinationNotSupported),
+        _ => return Err(ErrorKind::BadBuffer),
+    }
+
+    // Does not match any address type,
+    // means that we got a bad buffer
+    match header[3] {
+        0x01 | 0x03 | 0x04 => Ok(header[3]),
+        _ => Err(ErrorKind::BadBuffer),
+    }
+}
+
+/// Returns the length of the rest of a reply
+/// bound to an IP address (of `address_type`),
+/// which is the address followed by the port (+2)
+fn ip_rest_len(address_type: u8) -> usize {
+    match address_type {
+        0x01 => 4 + 2,
+        _ => 16 + 2,
+    }
+}
+
+/// Checks the length of the whole reply, the rest
+/// of which is `rest_len` bytes long, against the
+/// maximum one, before anything is read for the rest
+fn check_reply_len(
+    address_type: u8,
+    rest_len: usize,
+    max_reply_bytes: usize,
+) -> Result<(), ErrorKind> {
+    // A domain name is preceded by its length
+    let reply_len = 4 + rest_len + if address_type == 0x03 { 1 } else { 0 };
+    if reply_len > max_reply_bytes {
+        return Err(ErrorKind::BadBuffer);
+    }
+
+    Ok(())
+}
+
+/// The error of a step taken once the handshake is over
+/// (built with `io::Error::new`, since `io::Error::other`
+/// is not available on the older toolchains)
+#[allow(clippy::io_other_error)]
+fn finished_error() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "the handshake is already over")
+}
+
+/// Parses the rest of the reply to the request,
+/// which is the bound address (of `address_type`)
+/// followed by the bound port
+fn parse_bound(address_type: u8, rest: &[u8]) -> Result<BoundEndpoint, ErrorKind> {
+    let (addr, port) = rest.split_at(rest.len() - 2);
+    let port = BigEndian::read_u16(port);
+    let addr = match address_type {
+        0x01 => BoundAddr::Ipv4(Ipv4Addr::from(BigEndian::read_u32(addr))),
+        0x03 => {
+            BoundAddr::Domain(String::from_utf8(addr.to_vec()).map_err(|_| ErrorKind::BadBuffer)?)
+        }
+        _ => BoundAddr::Ipv6(Ipv6Addr::from(BigEndian::read_u128(addr))),
+    };
+
+    Ok(BoundEndpoint::new(addr, port))
+}
@@ -0,0 +1,276 @@
+use crate::clients::socks5::Destination;
+use std::borrow::Cow;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Creates a `Destination` from a string literal,
+/// which is validated at compile time, so that
+/// a typo in a destination baked into the binary
+/// fails the build instead of the connection.
+///
+/// The literal is an IPv4 address, an IPv6 address
+/// (neither enclosed in brackets, nor with a `%zone`
+/// or an embedded IPv4 address) or an ASCII domain name,
+/// which is checked by its length and characters
+/// (internationalized domain names are not converted,
+/// use `Destination::hostname` for them).
+/// The expression is a constant one, so that it
+/// initializes a `const` as well
+///
+/// ```
+/// use async_proxy::clients::socks5::Destination;
+/// use async_proxy::destination;
+/// use std::net::Ipv4Addr;
+///
+/// const PROXIED: Destination = destination!("example.com");
+///
+/// assert_eq!(PROXIED, Destination::hostname("example.com").unwrap());
+/// assert_eq!(destination!("127.0.0.1"), Destination::Ipv4Addr(Ipv4Addr::LOCALHOST));
+/// ```
+///
+/// A literal that is neither an IP address, nor
+/// a valid domain name does not compile
+///
+/// ```compile_fail
+/// let destination = async_proxy::destination!("example..com");
+/// ```
+#[macro_export]
+macro_rules! destination {
+    ($literal:literal) => {{
+        const LITERAL: $crate::clients::socks5::literal::Literal =
+            $crate::clients::socks5::literal::parse($literal);
+        const _: () = assert!(
+            LITERAL.is_valid(),
+            "the destination is neither an IP address, nor a valid domain name"
+        );
+
+        LITERAL.into_destination($literal)
+    }};
+}
+
+/// The destination a literal has been parsed
+/// into at compile time by `destination!`
+#[derive(Clone, Copy, Debug)]
+pub enum Literal {
+    Ipv4Addr([u8; 4]),
+    Ipv6Addr([u16; 8]),
+    DomainName,
+    Invalid,
+}
+
+impl Literal {
+    /// Checks whether the literal is
+    /// a destination of any kind
+    pub const fn is_valid(self) -> bool {
+        !matches!(self, Literal::Invalid)
+    }
+
+    /// Creates the destination of the literal
+    /// parsed from `literal`, which is borrowed
+    /// by a domain name destination
+    pub const fn into_destination(self, literal: &'static str) -> Destination {
+        match self {
+            Literal::Ipv4Addr([a, b, c, d]) => Destination::Ipv4Addr(Ipv4Addr::new(a, b, c, d)),
+            Literal::Ipv6Addr([a, b, c, d, e, f, g, h]) => {
+                Destination::Ipv6Addr(Ipv6Addr::new(a, b, c, d, e, f, g, h))
+            }
+            Literal::DomainName => Destination::DomainName(Cow::Borrowed(literal)),
+            Literal::Invalid => panic!("the destination literal is invalid"),
+        }
+    }
+}
+
+/// Parses a literal just as `Destination::from_str`
+/// does (an IPv4 address first, then an IPv6 address
+/// and a domain name), but at compile time
+pub const fn parse(literal: &str) -> Literal {
+    let bytes = literal.as_bytes();
+
+    if let Some(octets) = parse_ipv4(bytes) {
+        Literal::Ipv4Addr(octets)
+    } else if let Some(segments) = parse_ipv6(bytes) {
+        Literal::Ipv6Addr(segments)
+    } else if is_valid_hostname(bytes) {
+        Literal::DomainName
+    } else {
+        Literal::Invalid
+    }
+}
+
+/// Parses a dotted-decimal IPv4 address,
+/// rejecting the octets with leading zeros
+/// just as `Ipv4Addr::from_str` does
+const fn parse_ipv4(bytes: &[u8]) -> Option<[u8; 4]> {
+    let mut octets = [0; 4];
+    let mut octet = 0;
+    let mut i = 0;
+
+    while octet < 4 {
+        // Each octet but the first follows a dot
+        if octet > 0 {
+            if i >= bytes.len() || bytes[i] != b'.' {
+                return None;
+            }
+            i += 1;
+        }
+
+        let start = i;
+        let mut value: u16 = 0;
+        while i < bytes.len() && bytes[i].is_ascii_digit() && i - start < 3 {
+            value = value * 10 + (bytes[i] - b'0') as u16;
+            i += 1;
+        }
+
+        let digits = i - start;
+        if digits == 0 || value > 255 || (digits > 1 && bytes[start] == b'0') {
+            return None;
+        }
+
+        octets[octet] = value as u8;
+        octet += 1;
+    }
+
+    if i == bytes.len() {
+        Some(octets)
+    } else {
+        None
+    }
+}
+
+/// Parses an IPv6 address of hexadecimal
+/// segments, where a `::` stands for
+/// one or more zero segments
+const fn parse_ipv6(bytes: &[u8]) -> Option<[u16; 8]> {
+    // The segments preceding the `::` (or all of
+    // them, if there is none) and the following ones
+    let mut head = [0; 8];
+    let mut head_len = 0;
+    let mut tail = [0; 8];
+    let mut tail_len = 0;
+    let mut compressed = false;
+    let mut i = 0;
+
+    if bytes.len() >= 2 && bytes[0] == b':' && bytes[1] == b':' {
+        compressed = true;
+        i = 2;
+    }
+
+    while i < bytes.len() {
+        if head_len + tail_len == 8 {
+            return None;
+        }
+
+        let start = i;
+        let mut value: u16 = 0;
+        while i < bytes.len() && i - start < 4 {
+            let digit = match bytes[i] {
+                b'0'..=b'9' => bytes[i] - b'0',
+                b'a'..=b'f' => bytes[i] - b'a' + 10,
+                b'A'..=b'F' => bytes[i] - b'A' + 10,
+                _ => break,
+            };
+            value = value * 16 + digit as u16;
+            i += 1;
+        }
+
+        if i == start {
+            return None;
+        }
+
+        if compressed {
+            tail[tail_len] = value;
+            tail_len += 1;
+        } else {
+            head[head_len] = value;
+            head_len += 1;
+        }
+
+        if i == bytes.len() {
+            break;
+        }
+
+        // The segments are separated with a colon,
+        // where a second one stands for the `::`
+        if bytes[i] != b':' {
+            return None;
+        }
+        i += 1;
+
+        if i < bytes.len() && bytes[i] == b':' {
+            if compressed {
+                return None;
+            }
+            compressed = true;
+            i += 1;
+        } else if i == bytes.len() {
+            return None;
+        }
+    }
+
+    // The `::` stands for one segment at least,
+    // while with no `::` there are all eight of them
+    let len = head_len + tail_len;
+    if (compressed && len > 7) || (!compressed && len != 8) {
+        return None;
+    }
+
+    let mut segments = [0; 8];
+    let mut j = 0;
+    while j < head_len {
+        segments[j] = head[j];
+        j += 1;
+    }
+    j = 0;
+    while j < tail_len {
+        segments[8 - tail_len + j] = tail[j];
+        j += 1;
+    }
+
+    Some(segments)
+}
+
+/// Checks a domain name by its length and
+/// characters just as `Destination::hostname`
+/// does without the `dns-validation` feature,
+/// and rejects an all-numeric last label as well
+/// (as `webpki` does), so that a mistyped IPv4
+/// address is never taken as a domain name
+const fn is_valid_hostname(bytes: &[u8]) -> bool {
+    // Dropping the trailing dot
+    // of a fully qualified name
+    let mut len = bytes.len();
+    if len > 0 && bytes[len - 1] == b'.' {
+        len -= 1;
+    }
+
+    if len == 0 || len > 253 {
+        return false;
+    }
+
+    let mut label_start = 0;
+    let mut numeric = true;
+    let mut i = 0;
+    while i <= len {
+        if i == len || bytes[i] == b'.' {
+            let label_len = i - label_start;
+            if label_len == 0
+                || label_len > 63
+                || bytes[label_start] == b'-'
+                || bytes[i - 1] == b'-'
+            {
+                return false;
+            }
+            if i == len {
+                return !numeric;
+            }
+            label_start = i + 1;
+            numeric = true;
+        } else if !(bytes[i].is_ascii_alphanumeric() || bytes[i] == b'-' || bytes[i] == b'_') {
+            return false;
+        } else if !bytes[i].is_ascii_digit() {
+            numeric = false;
+        }
+        i += 1;
+    }
+
+    false
+}
@@ -1,21 +1,53 @@
 use crate::clients::socks5;
-use crate::general::ConnectionTimeouts;
-use crate::proxy::ProxyConstructor;
-use byteorder::{BigEndian, ByteOrder};
+use crate::clients::socks5::auth::{sealed, AuthMethod};
+use crate::clients::socks5::handshake::{Socks5Handshake, Step, REPLY_CAPACITY};
+use crate::general::{
+    self, observed, optional_timeout, record_error, trace_bytes, ConnectionTimeouts, ErrorCategory,
+    IOStream, IpPreference, Observer, Resolver, SystemResolver, TimeoutPhase,
+};
+use crate::proxy::{self, BoundEndpoint, CancelError, ProxyConstructor, ReadHalf, WriteHalf};
+use bytes::{Buf, BufMut};
 use core::task::{Context, Poll};
+use futures_util::future::poll_fn;
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::env::{self, VarError};
+use std::fmt;
+use std::future::Future;
 use std::io;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, SocketAddr};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, RawSocket};
 use std::pin::Pin;
 use std::str::FromStr;
-use std::{fmt, ops::Not};
-use tokio::io::{AsyncRead, AsyncWrite};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, BufReader};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
-use tokio::time::timeout;
+
+/// The default maximum length of a reply
+/// to a request (in bytes), which is finite,
+/// while still generous enough for any reply
+/// of the standard Socks5 (at most 262 bytes
+/// with a domain name bound address)
+pub const DEFAULT_MAX_REPLY_BYTES: usize = 1024;
 
 /// Represents the proxy constructor
 /// that builds a stream when the function
-/// `connect` is invoked
-pub struct TcpNoAuth<'a> {
+/// `connect` is invoked.
+///
+/// The constructor is generic over the authentication
+/// method `A` (see `socks5::auth`), selected at compile
+/// time, and over the stream `S` it operates on, so that
+/// any `IOStream` (for instance, a TLS-wrapped connection
+/// to the proxy server) can be used to speak the Socks5
+/// protocol over it. By default it is the plain `TcpStream`
+pub struct Socks5<A, S = TcpStream> {
     /// Represents an address of
     /// a service to what user
     /// wants to connect through a proxy
@@ -24,11 +56,114 @@ pub struct TcpNoAuth<'a> {
     port: u16,
     /// Timeouts for the connection
     timeouts: ConnectionTimeouts,
-    /// Type of Authentication for the connection
-    /// by default Authentication is not required
-    auth: AuthenticationKind<'a>,
+    /// The authentication method
+    /// offered to the server
+    auth: A,
+    /// The policy of resolving a domain name
+    /// destination, by default it is resolved
+    /// remotely, by the proxy server
+    resolution: socks5::ResolutionPolicy,
+    /// The resolver of a domain name destination
+    /// resolved locally, by default it is the
+    /// OS resolver (`SystemResolver`)
+    resolver: Arc<dyn Resolver>,
+    /// The IP version preferred when a domain
+    /// name resolved locally points to both,
+    /// by default the first address is taken
+    ip_preference: IpPreference,
+    /// The timeout of the whole `Username/Password`
+    /// subnegotiation, by default there is none, so
+    /// that only the read and write timeouts apply
+    auth_timeout: Option<Duration>,
+    /// The timeout of the wait for the peer
+    /// connecting to the endpoint bound by the BIND
+    /// request, by default it is the read timeout
+    accept_timeout: Option<Duration>,
+    /// The command sent in the request,
+    /// by default it is the tcp connection
+    /// establishment (CONNECT)
+    command: socks5::Command,
+    /// The maximum length of a reply the
+    /// server may declare, by default it is
+    /// `DEFAULT_MAX_REPLY_BYTES`
+    max_reply_bytes: usize,
+    /// The version byte of the protocol,
+    /// which is 0x05 unless the server
+    /// is a non-standard one
+    protocol_version: u8,
+    /// The order the authentication methods are
+    /// offered in, by default it is empty, so that
+    /// they are offered the strongest first
+    method_order: Vec<u8>,
+    /// The observer of the connection
+    /// attempts, by default there is none
+    observer: Option<Arc<dyn Observer<ErrorKind>>>,
+    /// Marks the type of a stream
+    /// the constructor operates on
+    _stream: PhantomData<fn() -> S>,
 }
 
+/// Represents the Socks5 proxy constructor with
+/// the authentication method selected at runtime
+/// (`AuthenticationKind`), by default authentication
+/// is not required.
+///
+/// The stream to a proxy server may be any `IOStream`,
+/// not only a `TcpStream`: for instance, a `UnixStream`
+/// to a proxy server listening on a Unix domain socket
+/// (see the `socks5-over-unix` example)
+pub type TcpNoAuth<'a, S = TcpStream> = Socks5<AuthenticationKind<'a>, S>;
+
+/// Represents the Socks5 proxy constructor
+/// that speaks the protocol over a TLS connection
+/// to a proxy server (so-called SOCKS-over-TLS),
+/// which is required by some commercial proxies.
+///
+/// A user establishes the TLS connection to the proxy
+/// server by himself (using `tokio_rustls`) and then feeds
+/// the TLS stream into `connect` as into any other `IOStream`
+///
+/// # Example
+///
+/// ```no_run
+/// use async_proxy::clients::socks5::{Destination, TlsNoAuth};
+/// use async_proxy::general::ConnectionTimeouts;
+/// use async_proxy::proxy::ProxyConstructor;
+/// use tokio::net::TcpStream;
+/// use tokio_rustls::rustls::ClientConfig;
+/// use tokio_rustls::webpki::DNSNameRef;
+/// use tokio_rustls::TlsConnector;
+/// use std::net::{Ipv4Addr, SocketAddr};
+/// use std::time::Duration;
+/// use std::sync::Arc;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let timeouts = ConnectionTimeouts::new(
+///         Duration::from_secs(8),
+///         Duration::from_secs(8),
+///         Duration::from_secs(8)
+///     );
+///
+///     // Establishing the TLS connection to the proxy server
+///     let connector = TlsConnector::from(Arc::new(ClientConfig::new()));
+///     let proxy_name = DNSNameRef::try_from_ascii_str("proxy.example.com").unwrap();
+///     let proxy_addr: SocketAddr = "203.0.113.10:1080".parse().unwrap();
+///     let stream = TcpStream::connect(proxy_addr).await.unwrap();
+///     let stream = connector.connect(proxy_name, stream).await.unwrap();
+///
+///     // Speaking Socks5 over the TLS stream
+///     let socks5_proxy: TlsNoAuth = TlsNoAuth::new(
+///         Destination::Ipv4Addr(Ipv4Addr::new(52, 20, 16, 20)),
+///         30_000,
+///         timeouts
+///     );
+///     let stream = socks5_proxy.connect(stream).await;
+/// }
+/// ```
+#[cfg(feature = "tls")]
+pub type TlsNoAuth<'a> = TcpNoAuth<'a, tokio_rustls::client::TlsStream<TcpStream>>;
+
 // All types of authentication for the connection
 // I supported only one
 pub enum AuthenticationKind<'a> {
@@ -38,8 +173,8 @@ pub enum AuthenticationKind<'a> {
     GenericSecurityServicesAPI,
     // Authentication by username and password
     UsernamePassword {
-        username: &'a str,
-        password: &'a str,
+        username: Cow<'a, str>,
+        password: Cow<'a, str>,
     },
 
     PrivateMethods,
@@ -53,8 +188,9 @@ pub enum AuthenticationKind<'a> {
 pub enum ErrorKind {
     /// Indicates that an operation
     /// took too much time so that
-    /// timeouts has been reached
-    OperationTimeoutReached,
+    /// timeouts has been reached,
+    /// in the phase carried
+    OperationTimeoutReached(TimeoutPhase),
     /// Indicates an I/O error
     IOError(std::io::Error),
     /// Indicates that a socks5-proxy
@@ -95,6 +231,37 @@ pub enum ErrorKind {
     DestinationNotSupported,
     /// Indicates the the type of not supported method currently
     Method(NotSupportedMethod),
+    /// Indicates that the server has rejected
+    /// the credentials passed during the
+    /// `Username/Password` subnegotiation
+    AuthenticationFailed,
+    /// Indicates that the username or the password
+    /// of the `Username/Password` method is longer
+    /// than 255 bytes, so that it cannot be sent
+    CredentialsTooLong,
+    /// Indicates that the server has replied
+    /// with the method 0xFF, accepting none of
+    /// the offered authentication methods
+    NoAcceptableAuthMethods,
+    /// May occur only if a domain name destination
+    /// is resolved locally and indicates that
+    /// the domain name cannot be resolved
+    /// into any address (of the IP version
+    /// preferred, if only one is accepted)
+    UnresolvableDestination,
+    /// Indicates that a received UDP datagram
+    /// is a fragment (its FRAG byte is not zero)
+    /// and therefore carries a partial payload
+    FragmentedDatagram(u8),
+    /// Indicates that the server looks like
+    /// a server of another proxy protocol
+    /// (for instance, a Socks4 or an HTTP one)
+    WrongProxyType,
+    /// Indicates that the server has closed
+    /// the connection instead of replying
+    /// to the greeting, which is not the
+    /// same as replying with a bad buffer
+    ConnectionClosedByProxy,
 }
 
 #[derive(Debug)]
@@ -111,6 +278,9 @@ pub enum NotSupportedMethod {
     IANA,
     /// Indicated that the values reserved for private methods
     PrivateMethods,
+    /// Indicated that the "no acceptable methods"
+    /// value cannot be offered as a method
+    NoAcceptable,
 }
 
 /// Represents an error that
@@ -133,45 +303,547 @@ pub enum StrParsingError {
     InvalidTimeouts,
 }
 
+/// Represents an error of a connection
+/// attempt, carrying the details of the attempt
+/// along with the kind of the error
+#[derive(Debug)]
+pub struct Error {
+    /// The kind of the error
+    kind: ErrorKind,
+    /// The reply field of the server's
+    /// reply to the connection request,
+    /// if the reply has been received
+    reply_code: Option<u8>,
+    /// The destination of the attempt
+    destination: String,
+    /// The port of the destination
+    port: u16,
+}
+
+/// Represents an error that can occur
+/// when taking credentials from the environment
+#[derive(Debug)]
+pub enum EnvCredentialsError {
+    /// Indicates that the password is set,
+    /// while the username is not
+    MissingUsername,
+    /// Indicates that the username is set,
+    /// while the password is not
+    MissingPassword,
+    /// Indicates that a credential
+    /// is not valid unicode
+    NotUnicode,
+}
+
+/// Represents an error that can occur
+/// when building a `TcpNoAuth`
+#[derive(Debug)]
+pub enum BuildError {
+    /// Indicates that the
+    /// destination is not set
+    MissingDestination,
+    /// Indicates that the
+    /// port is not set
+    MissingPort,
+    /// Indicates that the
+    /// timeouts are not set
+    MissingTimeouts,
+}
+
+/// The builder of `TcpNoAuth`,
+/// created by `TcpNoAuth::builder`.
+///
+/// The destination, the port and the timeouts
+/// are required, while the rest of the options
+/// fall back to the same defaults as `TcpNoAuth::new` does
+pub struct TcpNoAuthBuilder<'a, S = TcpStream> {
+    destination: Option<socks5::Destination>,
+    port: Option<u16>,
+    timeouts: Option<ConnectionTimeouts>,
+    auth: AuthenticationKind<'a>,
+    resolution: socks5::ResolutionPolicy,
+    resolver: Arc<dyn Resolver>,
+    ip_preference: IpPreference,
+    auth_timeout: Option<Duration>,
+    accept_timeout: Option<Duration>,
+    command: socks5::Command,
+    max_reply_bytes: usize,
+    protocol_version: u8,
+    method_order: Vec<u8>,
+    observer: Option<Arc<dyn Observer<ErrorKind>>>,
+    _stream: PhantomData<fn() -> S>,
+}
+
 /// Represents the socks5-tcp
 /// proxy client stream implementation
-pub struct TcpNoAuthStream {
-    /// The tcp stream on which
+pub struct TcpNoAuthStream<S = TcpStream> {
+    /// The stream on which
     /// the client operates on
-    wrapped_stream: TcpStream,
+    wrapped_stream: S,
+    /// The endpoint reported
+    /// in the server's reply
+    bound: BoundEndpoint,
+    /// The authentication method
+    /// selected by the server
+    auth_method: u8,
+    /// The command sent in the request
+    command: socks5::Command,
+    /// The timeouts of the constructor,
+    /// used by `read_timed` and `write_timed`
+    timeouts: ConnectionTimeouts,
+    /// The kind of the last I/O error
+    /// of the tunnel, if any
+    last_error: Option<io::ErrorKind>,
+    /// The accept timeout of the
+    /// constructor, used by `accept`
+    accept_timeout: Option<Duration>,
+    /// The maximum length of a reply
+    /// of the constructor, used by `accept`
+    max_reply_bytes: usize,
+    /// The version byte of the protocol
+    /// of the constructor, used by `accept`
+    protocol_version: u8,
+    /// Whether `accept` has been called, after
+    /// which the stream is no longer awaiting
+    /// the second reply
+    accepted: bool,
+}
+
+/// The outcome of a successful handshake
+pub(crate) struct Negotiated {
+    /// The endpoint reported
+    /// in the server's reply
+    pub(crate) bound: BoundEndpoint,
+    /// The authentication method
+    /// selected by the server
+    pub(crate) auth_method: u8,
 }
 
-impl<'a> TcpNoAuth<'a> {
+impl<'a, S> TcpNoAuth<'a, S> {
     pub fn new(
         destination: socks5::Destination,
-        port: u16,
+        port: impl Into<socks5::Port>,
         timeouts: ConnectionTimeouts,
-    ) -> TcpNoAuth<'a> {
-        TcpNoAuth {
+    ) -> TcpNoAuth<'a, S> {
+        Socks5::with_auth_method(
             destination,
             port,
             timeouts,
+            AuthenticationKind::NoAuthentication,
+        )
+    }
+
+    /// Creates a constructor connecting to the IP
+    /// address and the port of `addr`, just as the
+    /// Socks4 clients take a single socket address
+    pub fn to_socket_addr(addr: SocketAddr, timeouts: ConnectionTimeouts) -> TcpNoAuth<'a, S> {
+        let (destination, port) = socks5::Destination::from_socket_addr(addr);
+        TcpNoAuth::new(destination, port, timeouts)
+    }
+
+    /// Returns a builder of the
+    /// proxy constructor with no options set
+    pub fn builder() -> TcpNoAuthBuilder<'a, S> {
+        TcpNoAuthBuilder {
+            destination: None,
+            port: None,
+            timeouts: None,
             auth: AuthenticationKind::NoAuthentication,
+            resolution: socks5::ResolutionPolicy::Remote,
+            resolver: Arc::new(SystemResolver),
+            ip_preference: IpPreference::Any,
+            auth_timeout: None,
+            accept_timeout: None,
+            command: socks5::Command::TcpConnectionEstablishment,
+            max_reply_bytes: DEFAULT_MAX_REPLY_BYTES,
+            protocol_version: 5,
+            method_order: Vec::new(),
+            observer: None,
+            _stream: PhantomData,
         }
     }
 
     /// Uses `Username/Password` method for authentication
     pub fn with_authentication(&mut self, username: &'a str, password: &'a str) {
-        self.auth = AuthenticationKind::UsernamePassword { username, password };
+        self.auth = AuthenticationKind::UsernamePassword {
+            username: Cow::Borrowed(username),
+            password: Cow::Borrowed(password),
+        };
+    }
+
+    /// Uses `Username/Password` method for authentication
+    /// with the credentials taken from the environment variables
+    /// `{prefix}_USER` and `{prefix}_PASS` (for instance, `SOCKS5_USER`
+    /// and `SOCKS5_PASS` for the prefix `SOCKS5`), so that secrets
+    /// are kept out of code and configuration strings.
+    ///
+    /// If none of the variables is set, the authentication
+    /// method is left unchanged, while only one of them
+    /// being set is an error
+    pub fn with_env_credentials(&mut self, prefix: &str) -> Result<(), EnvCredentialsError> {
+        let username = env::var(format!("{}_USER", prefix));
+        let password = env::var(format!("{}_PASS", prefix));
+
+        match (username, password) {
+            (Ok(username), Ok(password)) => {
+                self.auth = AuthenticationKind::UsernamePassword {
+                    username: Cow::Owned(username),
+                    password: Cow::Owned(password),
+                };
+                Ok(())
+            }
+            (Err(VarError::NotPresent), Err(VarError::NotPresent)) => Ok(()),
+            (Err(VarError::NotUnicode(_)), _) | (_, Err(VarError::NotUnicode(_))) => {
+                Err(EnvCredentialsError::NotUnicode)
+            }
+            (Ok(_), Err(VarError::NotPresent)) => Err(EnvCredentialsError::MissingPassword),
+            (Err(VarError::NotPresent), Ok(_)) => Err(EnvCredentialsError::MissingUsername),
+        }
+    }
+}
+
+impl<A, S> Socks5<A, S> {
+    /// Creates the proxy constructor
+    /// offering the authentication method
+    /// `auth` (for instance, `auth::NoAuth`
+    /// or `auth::UserPass`)
+    pub fn with_auth_method(
+        destination: socks5::Destination,
+        port: impl Into<socks5::Port>,
+        timeouts: ConnectionTimeouts,
+        auth: A,
+    ) -> Socks5<A, S> {
+        Socks5 {
+            destination,
+            port: port.into().0,
+            timeouts,
+            auth,
+            resolution: socks5::ResolutionPolicy::Remote,
+            resolver: Arc::new(SystemResolver),
+            ip_preference: IpPreference::Any,
+            auth_timeout: None,
+            accept_timeout: None,
+            command: socks5::Command::TcpConnectionEstablishment,
+            max_reply_bytes: DEFAULT_MAX_REPLY_BYTES,
+            protocol_version: 5,
+            method_order: Vec::new(),
+            observer: None,
+            _stream: PhantomData,
+        }
+    }
+
+    /// Replaces the destination
+    /// and the port of it
+    pub(crate) fn set_destination(&mut self, destination: socks5::Destination, port: u16) {
+        self.destination = destination;
+        self.port = port;
+    }
+
+    /// Sets the policy of resolving
+    /// a domain name destination
+    pub fn with_resolution(&mut self, policy: socks5::ResolutionPolicy) {
+        self.resolution = policy;
+    }
+
+    /// Sets the resolver of a domain name
+    /// destination resolved locally
+    pub fn with_resolver(&mut self, resolver: Arc<dyn Resolver>) {
+        self.resolver = resolver;
+    }
+
+    /// Sets the IP version preferred when
+    /// a domain name destination resolved
+    /// locally points to both
+    pub fn with_ip_preference(&mut self, preference: IpPreference) {
+        self.ip_preference = preference;
+    }
+
+    /// Sets the timeout of the whole
+    /// `Username/Password` subnegotiation,
+    /// bounding a server that stalls (or drip-feeds
+    /// bytes) during the authentication
+    pub fn with_auth_timeout(&mut self, timeout: Duration) {
+        self.auth_timeout = Some(timeout);
+    }
+
+    /// Sets the timeout of the wait for the peer
+    /// connecting to the endpoint bound by the
+    /// BIND request (see `TcpNoAuthStream::accept`),
+    /// which is the read timeout unless set
+    pub fn with_accept_timeout(&mut self, timeout: Duration) {
+        self.accept_timeout = Some(timeout);
+    }
+
+    /// Sets the command sent in the request.
+    ///
+    /// With the tcp port binding (BIND) command,
+    /// the bound endpoint of the proxy stream is
+    /// the one the server listens on, and
+    /// `TcpNoAuthStream::accept` waits for
+    /// the inbound connection
+    pub fn with_command(&mut self, command: socks5::Command) {
+        self.command = command;
+    }
+
+    /// Sets the maximum length of a reply
+    /// (in bytes), so that a reply declaring
+    /// a longer one is `ErrorKind::BadBuffer`
+    /// before anything is allocated for it,
+    /// bounding the memory an untrusted
+    /// server can make us use
+    pub fn with_max_reply_bytes(&mut self, max_reply_bytes: usize) {
+        self.max_reply_bytes = max_reply_bytes;
+    }
+
+    /// Sets the version byte written in the greeting
+    /// and the request and expected in the replies,
+    /// by default it is 0x05.
+    ///
+    /// It is an escape hatch for the servers that
+    /// speak Socks5 with another version byte, which
+    /// are not compliant with RFC 1928
+    #[cfg(feature = "custom-protocol-version")]
+    pub fn with_protocol_version(&mut self, version: u8) {
+        self.protocol_version = version;
+    }
+
+    /// Sets the order the authentication methods are
+    /// offered in: the methods of the authentication set
+    /// that are listed in `order` are offered first, in the
+    /// order listed, followed by the rest of them. Methods
+    /// in `order` that the authentication set does not
+    /// perform are never offered.
+    ///
+    /// By default the strongest method is offered first
+    /// (as RFC 1928 suggests), while this is an escape hatch
+    /// for the servers that only accept the first method
+    /// offered or want no authentication listed last
+    pub fn with_method_order(&mut self, order: &[u8]) {
+        self.method_order = order.to_vec();
+    }
+
+    /// Sets the observer of the connection attempts,
+    /// which is told when a handshake starts and
+    /// how it ends, along with the time it has taken
+    pub fn with_observer(&mut self, observer: Arc<dyn Observer<ErrorKind>>) {
+        self.observer = Some(observer);
+    }
+
+    /// Returns the destination of a service
+    /// we are connecting to through proxy
+    pub fn destination(&self) -> &socks5::Destination {
+        &self.destination
+    }
+
+    /// Returns the port of the destination service
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Returns the timeouts set
+    pub fn timeouts(&self) -> &ConnectionTimeouts {
+        &self.timeouts
+    }
+
+    /// Returns the authentication method
+    /// used for the connection
+    pub fn auth(&self) -> &A {
+        &self.auth
+    }
+
+    /// Returns the policy of resolving
+    /// a domain name destination
+    pub fn resolution(&self) -> socks5::ResolutionPolicy {
+        self.resolution
+    }
+
+    /// Returns the IP version preferred
+    /// when resolving a domain name locally
+    pub fn ip_preference(&self) -> IpPreference {
+        self.ip_preference
+    }
+
+    /// Returns the timeout of the whole
+    /// `Username/Password` subnegotiation
+    pub fn auth_timeout(&self) -> Option<Duration> {
+        self.auth_timeout
+    }
+
+    /// Returns the timeout of the wait for the
+    /// peer connecting to the endpoint bound by
+    /// the BIND request, falling back on the read timeout
+    pub fn accept_timeout(&self) -> Option<Duration> {
+        self.accept_timeout.or(self.timeouts.read_timeout)
+    }
+
+    /// Returns the command sent in the request
+    pub fn command(&self) -> socks5::Command {
+        self.command
+    }
+
+    /// Returns the maximum length of a reply
+    pub fn max_reply_bytes(&self) -> usize {
+        self.max_reply_bytes
+    }
+
+    /// Returns the order the authentication
+    /// methods are offered in, which is empty
+    /// unless set (the strongest first)
+    pub fn method_order(&self) -> &[u8] {
+        &self.method_order
+    }
+
+    /// Resolves a domain name locally (using the
+    /// resolver set) and returns the resolved address
+    /// selected by the IP preference as a destination.
+    /// The resolution is bounded by the connecting timeout
+    async fn resolve(&self, name: &str) -> Result<socks5::Destination, ErrorKind> {
+        let future = self.resolver.resolve(name);
+        let future = optional_timeout(self.timeouts.connecting_timeout, future);
+        let addrs = future
+            .await
+            .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Connect))??;
+
+        match self.ip_preference.select(&addrs) {
+            Some(IpAddr::V4(addr)) => Ok(socks5::Destination::Ipv4Addr(addr)),
+            Some(IpAddr::V6(addr)) => Ok(socks5::Destination::Ipv6Addr(addr)),
+            None => Err(ErrorKind::UnresolvableDestination),
+        }
+    }
+}
+
+impl<'a, S> TcpNoAuthBuilder<'a, S> {
+    /// Sets the destination of a service
+    /// to connect to through proxy
+    pub fn destination(mut self, destination: socks5::Destination) -> Self {
+        self.destination = Some(destination);
+        self
+    }
+
+    /// Sets the port of the destination service
+    pub fn port(mut self, port: impl Into<socks5::Port>) -> Self {
+        self.port = Some(port.into().0);
+        self
+    }
+
+    /// Sets the timeouts
+    pub fn timeouts(mut self, timeouts: ConnectionTimeouts) -> Self {
+        self.timeouts = Some(timeouts);
+        self
+    }
+
+    /// Uses `Username/Password` method for authentication
+    pub fn credentials(mut self, username: &'a str, password: &'a str) -> Self {
+        self.auth = AuthenticationKind::UsernamePassword {
+            username: Cow::Borrowed(username),
+            password: Cow::Borrowed(password),
+        };
+        self
+    }
+
+    /// Sets the policy of resolving
+    /// a domain name destination
+    pub fn resolution(mut self, policy: socks5::ResolutionPolicy) -> Self {
+        self.resolution = policy;
+        self
+    }
+
+    /// Sets the resolver of a domain name
+    /// destination resolved locally
+    pub fn resolver(mut self, resolver: Arc<dyn Resolver>) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    /// Sets the IP version preferred when
+    /// a domain name destination resolved
+    /// locally points to both
+    pub fn ip_preference(mut self, preference: IpPreference) -> Self {
+        self.ip_preference = preference;
+        self
+    }
+
+    /// Sets the timeout of the whole
+    /// `Username/Password` subnegotiation
+    pub fn auth_timeout(mut self, timeout: Duration) -> Self {
+        self.auth_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the timeout of the wait for the peer
+    /// connecting to the endpoint bound by the BIND request
+    pub fn accept_timeout(mut self, timeout: Duration) -> Self {
+        self.accept_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the command sent in the request
+    pub fn command(mut self, command: socks5::Command) -> Self {
+        self.command = command;
+        self
+    }
+
+    /// Sets the maximum length of a reply
+    pub fn max_reply_bytes(mut self, max_reply_bytes: usize) -> Self {
+        self.max_reply_bytes = max_reply_bytes;
+        self
+    }
+
+    /// Sets the version byte of the protocol
+    /// (an escape hatch for non-compliant servers)
+    #[cfg(feature = "custom-protocol-version")]
+    pub fn protocol_version(mut self, version: u8) -> Self {
+        self.protocol_version = version;
+        self
+    }
+
+    /// Sets the order the authentication methods are offered
+    /// in (an escape hatch for non-compliant servers)
+    pub fn method_order(mut self, order: &[u8]) -> Self {
+        self.method_order = order.to_vec();
+        self
+    }
+
+    /// Sets the observer of the connection attempts
+    pub fn observer(mut self, observer: Arc<dyn Observer<ErrorKind>>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Builds the proxy constructor,
+    /// unless a required option is not set
+    pub fn build(self) -> Result<TcpNoAuth<'a, S>, BuildError> {
+        Ok(Socks5 {
+            destination: self.destination.ok_or(BuildError::MissingDestination)?,
+            port: self.port.ok_or(BuildError::MissingPort)?,
+            timeouts: self.timeouts.ok_or(BuildError::MissingTimeouts)?,
+            auth: self.auth,
+            resolution: self.resolution,
+            resolver: self.resolver,
+            ip_preference: self.ip_preference,
+            auth_timeout: self.auth_timeout,
+            accept_timeout: self.accept_timeout,
+            command: self.command,
+            max_reply_bytes: self.max_reply_bytes,
+            protocol_version: self.protocol_version,
+            method_order: self.method_order,
+            observer: self.observer,
+            _stream: PhantomData,
+        })
     }
 }
 
 /// Impl for parsing a `Socks4General`
 /// from a string
-impl<'a> FromStr for TcpNoAuth<'a> {
-    type Err = StrParsingError;
+impl<'a, 's, S> TryFrom<&'s str> for TcpNoAuth<'a, S> {
+    type Error = StrParsingError;
 
-    /// Parses a `Socks4General` from a
+    /// Parses a `TcpNoAuth` from a
     /// string in format:
     ///   (ipv4 or ipv6 or domain.com) port timeouts
-    fn from_str(s: &str) -> Result<TcpNoAuth<'a>, Self::Err> {
+    fn try_from(s: &'s str) -> Result<TcpNoAuth<'a, S>, Self::Error> {
         // Splitting the string on spaces
-        let mut s = s.split(" ");
+        let mut s = s.split(' ');
 
         // Parsing an address and timeouts
         let (destination, port, timeouts) = (
@@ -193,6 +865,58 @@ impl<'a> FromStr for TcpNoAuth<'a> {
     }
 }
 
+impl<'a, S> FromStr for TcpNoAuth<'a, S> {
+    type Err = StrParsingError;
+
+    /// Parses a `TcpNoAuth` from a
+    /// string just as `try_from` does
+    fn from_str(s: &str) -> Result<TcpNoAuth<'a, S>, Self::Err> {
+        TcpNoAuth::try_from(s)
+    }
+}
+
+impl fmt::Display for StrParsingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            StrParsingError::SyntaxError => f.write_str("expected `destination port timeouts`"),
+            StrParsingError::InvalidDestination => f.write_str("invalid destination"),
+            StrParsingError::InvalidPort => f.write_str("invalid port"),
+            StrParsingError::InvalidTimeouts => f.write_str("invalid timeouts"),
+        }
+    }
+}
+
+impl std::error::Error for StrParsingError {}
+
+impl ErrorKind {
+    /// Returns the category of the error, telling
+    /// a refused request from a broken server
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            ErrorKind::RequestDenied
+            | ErrorKind::NetworkUnreachable
+            | ErrorKind::HostUnreachable
+            | ErrorKind::ConnectionRefused
+            | ErrorKind::TTLExpired
+            | ErrorKind::NotSupported
+            | ErrorKind::DestinationNotSupported
+            | ErrorKind::UnresolvableDestination => ErrorCategory::PolicyDenied,
+            ErrorKind::IOError(_)
+            | ErrorKind::SocksServerFailure
+            | ErrorKind::ConnectionClosedByProxy => ErrorCategory::NetworkError,
+            ErrorKind::BadBuffer
+            | ErrorKind::DomainNameTooLong
+            | ErrorKind::FragmentedDatagram(_)
+            | ErrorKind::WrongProxyType => ErrorCategory::ProtocolError,
+            ErrorKind::OperationTimeoutReached(_) => ErrorCategory::Timeout,
+            ErrorKind::Method(_)
+            | ErrorKind::AuthenticationFailed
+            | ErrorKind::CredentialsTooLong
+            | ErrorKind::NoAcceptableAuthMethods => ErrorCategory::AuthFailure,
+        }
+    }
+}
+
 impl fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
@@ -211,7 +935,24 @@ impl fmt::Display for ErrorKind {
             ErrorKind::DestinationNotSupported => {
                 f.write_str("the type of passed destination is not supported")
             }
-            ErrorKind::OperationTimeoutReached => f.write_str("operation timeout reached"),
+            ErrorKind::OperationTimeoutReached(phase) => f.write_str(&phase.to_string()),
+            ErrorKind::AuthenticationFailed => f.write_str("authentication failed"),
+            ErrorKind::CredentialsTooLong => f.write_str("username or password is too long"),
+            ErrorKind::NoAcceptableAuthMethods => {
+                f.write_str("the server accepts none of the offered authentication methods")
+            }
+            ErrorKind::UnresolvableDestination => {
+                f.write_str("unable to resolve the destination domain name")
+            }
+            ErrorKind::WrongProxyType => {
+                f.write_str("the server does not look like a Socks5 server")
+            }
+            ErrorKind::ConnectionClosedByProxy => {
+                f.write_str("the proxy server has closed the connection")
+            }
+            ErrorKind::FragmentedDatagram(frag) => {
+                write!(f, "received a fragmented datagram (FRAG {:#04x})", frag)
+            }
             ErrorKind::Method(method_kind) => match method_kind {
                 NotSupportedMethod::NoAuthRequired => {
                     f.write_str("the authentication not required")
@@ -225,208 +966,594 @@ impl fmt::Display for ErrorKind {
                 NotSupportedMethod::PrivateMethods => f.write_str(
                     "field of method reserved for private method. Currently not supported",
                 ),
+                NotSupportedMethod::NoAcceptable => {
+                    f.write_str("no acceptable methods cannot be offered as a method")
+                }
             },
         }
     }
 }
-#[async_trait::async_trait]
-impl<'a> ProxyConstructor for TcpNoAuth<'a> {
-    type Stream = TcpStream;
-    type ProxyStream = TcpNoAuthStream;
-    type ErrorKind = ErrorKind;
-
-    async fn connect(
-        &mut self,
-        mut stream: Self::Stream,
-    ) -> Result<Self::ProxyStream, Self::ErrorKind> {
-        // The length of the initial Socks5 request's buffer
-        const BUF_LEN: usize = 3;
-
-        // Creating the payload buffer
-        let mut buf = Vec::<u8>::with_capacity(BUF_LEN);
-
-        // The number of the Socks protocol version
-        // (0x05 or just 5 in this case)
-        buf.push(5);
-
-        // The number of supported authentication methods
-        // (1 in this case)
-        buf.push(1);
-
-        match self.auth {
-            // The only one value of the supported
-            // authentication method.
-            // (0x00 or just 0 — No authentication)
-            // by default uses this kind
-            AuthenticationKind::NoAuthentication => buf.push(0),
-
-            // This means user chose the kind of authentication
-            // as a Username/Password
-            AuthenticationKind::UsernamePassword {
-                username: _,
-                password: _,
-            } => {
-                // Add to the message for server
-                // method of authentication
-                // X'02' USERNAME/PASSWORD
-                buf.push(2);
-            }
+impl<A: AuthMethod> Socks5<A, TcpStream> {
+    /// Establishes a proxied connection on the tcp
+    /// stream just as `connect` does, but with `TCP_NODELAY`
+    /// set during the handshake (made of small requests and
+    /// replies, so that it is latency-sensitive), restoring
+    /// the prior setting once the handshake has succeeded
+    pub async fn connect_with_nodelay(
+        &self,
+        stream: TcpStream,
+    ) -> Result<TcpNoAuthStream<TcpStream>, Error> {
+        let nodelay = stream
+            .nodelay()
+            .and_then(|nodelay| stream.set_nodelay(true).map(|_| nodelay))
+            .map_err(|e| self.error(ErrorKind::IOError(e), None))?;
+
+        let stream = self.connect(stream).await?;
+
+        // The server has replied with the success (0x00),
+        // which is reported along with a failure
+        stream
+            .get_ref()
+            .set_nodelay(nodelay)
+            .map_err(|e| self.error(ErrorKind::IOError(e), Some(0x00)))?;
+
+        Ok(stream)
+    }
+}
+
+impl<A, S> Socks5<A, S>
+where
+    A: AuthMethod,
+    S: IOStream + Send,
+{
+    /// Establishes a proxied connection on the stream
+    /// just as `connect` does, but returns the future
+    /// itself instead of a boxed one (which the trait
+    /// methods return), saving an allocation per
+    /// connection where the trait is not needed
+    /// (the authentication method and the resolver,
+    /// which are trait objects, are still boxed
+    /// if called at all).
+    ///
+    /// With an IP address destination and no
+    /// authentication, the handshake allocates nothing
+    /// at all, but only with `ConnectionTimeouts::none()`,
+    /// since each timer of the timeouts allocates an entry
+    pub async fn connect_unboxed(&self, stream: S) -> Result<TcpNoAuthStream<S>, Error> {
+        self.connect_to(&self.destination, self.port, stream).await
+    }
+
+    /// Establishes a proxied connection on the stream
+    /// to the given destination instead of the one
+    /// of the constructor, which is left untouched
+    async fn connect_to(
+        &self,
+        destination: &socks5::Destination,
+        port: u16,
+        mut stream: S,
+    ) -> Result<TcpNoAuthStream<S>, Error> {
+        // Performing the handshake and returning
+        // a socks5 proxy client instance
+        // if everything was successful
+        let negotiated = self.handshake_to(destination, port, &mut stream).await?;
+
+        Ok(self.stream_from(stream, negotiated))
+    }
+
+    /// Wraps the stream a handshake has been
+    /// performed on into the proxy stream,
+    /// carrying the settings of the constructor
+    fn stream_from(&self, stream: S, negotiated: Negotiated) -> TcpNoAuthStream<S> {
+        TcpNoAuthStream {
+            wrapped_stream: stream,
+            bound: negotiated.bound,
+            auth_method: negotiated.auth_method,
+            command: self.command,
+            timeouts: self.timeouts.clone(),
+            last_error: None,
+            accept_timeout: self.accept_timeout(),
+            max_reply_bytes: self.max_reply_bytes,
+            protocol_version: self.protocol_version,
+            accepted: false,
+        }
+    }
+
+    /// Establishes a proxied connection on
+    /// the stream just as `connect` does, but stops
+    /// as soon as the `cancel` future completes,
+    /// giving the stream back instead of dropping it.
+    ///
+    /// Note that if cancelled in the middle of the
+    /// handshake, the stream is left in an unknown
+    /// state of the protocol, so that it is only
+    /// good for being shut down gracefully
+    pub async fn connect_with_cancel<C>(
+        &self,
+        mut stream: S,
+        cancel: C,
+    ) -> Result<TcpNoAuthStream<S>, CancelError<S, Error>>
+    where
+        C: Future<Output = ()>,
+    {
+        // Racing the handshake against the cancellation,
+        // the latter is polled first so that it takes
+        // priority if both are ready
+        let outcome = {
+            let handshake = self.handshake(&mut stream);
+            tokio::pin!(handshake);
+            tokio::pin!(cancel);
+
+            poll_fn(|cx| {
+                if cancel.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(None);
+                }
 
-            _ => println!("Not supported method authentication"),
+                handshake.as_mut().poll(cx).map(Some)
+            })
+            .await
         };
 
-        // Writing the initial payload to the server
-        let read_bytes = self.send_payload(&mut buf, &mut stream).await.unwrap();
+        match outcome {
+            Some(Ok(negotiated)) => Ok(self.stream_from(stream, negotiated)),
+            Some(Err(e)) => Err(CancelError::Failed(e)),
+            None => Err(CancelError::Cancelled(stream)),
+        }
+    }
 
-        // The server must send reply
-        // with the length of 2 bytes.
-        // Anything else is a sense of an error
-        if read_bytes != 2 {
-            return Err(ErrorKind::BadBuffer);
+    /// Checks whether a Socks5 proxy server is alive and
+    /// which authentication method it requires, performing
+    /// nothing but the greeting (the method selection),
+    /// so that no connection to a destination is requested.
+    ///
+    /// All the methods known by the crate (0x00, 0x01 and
+    /// 0x02) are offered and the method selected by the server
+    /// is returned. Since a server selects a single method,
+    /// the result holds at most one method, and it is empty
+    /// if the server accepts none of the offered ones.
+    /// The stream is left in the middle of the handshake,
+    /// so that it is consumed.
+    ///
+    /// The constructor is only borrowed (just as by `connect`),
+    /// so that the same configured constructor probes a server
+    /// and then connects through it over another stream
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use async_proxy::clients::socks5::{Destination, TcpNoAuth};
+    /// use async_proxy::general::ConnectionTimeouts;
+    /// use async_proxy::proxy::ProxyConstructor;
+    /// use tokio::net::TcpStream;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let timeouts = ConnectionTimeouts::new(
+    ///         Duration::from_secs(8),
+    ///         Duration::from_secs(8),
+    ///         Duration::from_secs(8)
+    ///     );
+    ///
+    ///     let destination = Destination::hostname("example.com").unwrap();
+    ///     let socks5_proxy = TcpNoAuth::new(destination, 80, timeouts);
+    ///
+    ///     // Probing the proxy server first
+    ///     let stream = TcpStream::connect("203.0.113.10:1080").await.unwrap();
+    ///     let methods = socks5_proxy.probe(stream).await.unwrap();
+    ///
+    ///     // Connecting through it with the same constructor
+    ///     // if it requires no authentication
+    ///     if methods == [0x00] {
+    ///         let stream = TcpStream::connect("203.0.113.10:1080").await.unwrap();
+    ///         let stream = socks5_proxy.connect(stream).await.unwrap();
+    ///     }
+    /// }
+    /// ```
+    pub async fn probe(&self, mut stream: S) -> Result<Vec<u8>, ErrorKind> {
+        // The methods offered: no authentication,
+        // GSSAPI and `Username/Password`
+        const PROBED_METHODS: [u8; 3] = [0x00, 0x01, 0x02];
+
+        // The greeting is checked by the state machine,
+        // which is left as soon as a method is selected,
+        // so that no request is ever sent
+        let mut handshake = Socks5Handshake::new(self.destination.clone(), self.port);
+        handshake.version = self.protocol_version;
+        handshake.with_methods(&PROBED_METHODS);
+
+        let mut input = [0; REPLY_CAPACITY];
+        let mut read_bytes = 0;
+        loop {
+            let step = handshake.step_inline(&input[..read_bytes]);
+            read_bytes = 0;
+
+            match step {
+                Step::Write(payload) => self.write_payload(&payload, &mut stream).await?,
+                Step::NeedMore(len) => {
+                    let buf = &mut input[..len.min(REPLY_CAPACITY)];
+                    let read_timeout = self.timeouts.read_timeout;
+                    read_bytes = read_input(&mut handshake, buf, &mut stream, read_timeout).await?
+                }
+                Step::Authenticate(selected) => return Ok(vec![selected]),
+                // The server accepts none of the methods
+                Step::Error(ErrorKind::NoAcceptableAuthMethods) => return Ok(Vec::new()),
+                Step::Error(kind) => return Err(kind),
+                // The delegated handshake never
+                // gets past the method selection
+                Step::Done { .. } => return Err(ErrorKind::BadBuffer),
+            }
         }
+    }
+
+    /// Establishes a proxied connection on the stream
+    /// just as `connect` does and then writes the initial
+    /// payload (for instance, an HTTP request) to the tunnel,
+    /// saving a separate call for protocols where
+    /// the client speaks first.
+    ///
+    /// Note that it is not a real TCP Fast Open: the
+    /// payload is written right after the server's reply,
+    /// within the write timeout set
+    pub async fn connect_with_initial_data(
+        &self,
+        stream: S,
+        initial: &[u8],
+    ) -> Result<TcpNoAuthStream<S>, Error> {
+        let mut stream = self.connect(stream).await?;
+
+        // Writing the initial payload to the tunnel.
+        // The server has replied with the success (0x00),
+        // which is reported along with a failure
+        let future = stream.wrapped_stream.write_all(initial);
+        let future = optional_timeout(self.timeouts.write_timeout, future);
+        match future.await {
+            Ok(Ok(())) => Ok(stream),
+            Ok(Err(e)) => Err(self.error(ErrorKind::IOError(e), Some(0x00))),
+            Err(_) => Err(self.error(
+                ErrorKind::OperationTimeoutReached(TimeoutPhase::Write),
+                Some(0x00),
+            )),
+        }
+    }
+
+    /// Attempts the destinations in order through the proxy,
+    /// each on a fresh stream produced by `new_stream`
+    /// (since every attempt consumes one), and returns the
+    /// stream of the first one connected to, unless the
+    /// error of the last attempt.
+    ///
+    /// A failure to produce a stream is the `ErrorKind::IOError`
+    /// of the attempt, which moves on to the next destination.
+    /// The destination of the constructor is never changed
+    /// (even if the future is dropped in the middle of an
+    /// attempt), while the error reports the one of the last attempt
+    pub async fn connect_to_any<F, Fut>(
+        &self,
+        destinations: &[(socks5::Destination, u16)],
+        mut new_stream: F,
+    ) -> Result<TcpNoAuthStream<S>, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = io::Result<S>>,
+    {
+        // The error reported if there
+        // is no destination to attempt at all
+        let e = io::Error::new(io::ErrorKind::InvalidInput, "no destinations to attempt");
+        let mut result = Err(self.error(ErrorKind::IOError(e), None));
 
-        // The former read byte must be 0x05,
-        // while the latter must not be 0xFF
-        if buf[0] != 0x05 || buf[1] == 0xFF {
-            return Err(ErrorKind::BadBuffer);
+        for (destination, port) in destinations {
+            result = match new_stream().await {
+                Ok(stream) => self.connect_to(destination, *port, stream).await,
+                Err(e) => Err(attempt_error(
+                    ErrorKind::IOError(e),
+                    None,
+                    destination,
+                    *port,
+                )),
+            };
+
+            // Stopping at the first success
+            if result.is_ok() {
+                break;
+            }
         }
-        match buf[1] {
-            0x0 => return Err(ErrorKind::Method(NotSupportedMethod::NoAuthRequired)),
-            0x01 => return Err(ErrorKind::Method(NotSupportedMethod::GssAPI)),
 
-            // This means
-            // method of authentication UserName/Password
-            0x02 => {
-                // The VER field contains the current version of the subnegotiation
-                // which is X'01'
-                buf[0] = 1;
+        result
+    }
 
-                if let AuthenticationKind::UsernamePassword { username, password } = self.auth {
-                    let buf_size: usize = 1 + 1 + username.len() + 1 + password.len();
+    /// Performs the whole Socks5 handshake
+    /// over a borrowed stream and returns the
+    /// endpoint bound by the server along with the
+    /// selected authentication method, unless an
+    /// error with the details of the attempt
+    pub(crate) async fn handshake(&self, stream: &mut S) -> Result<Negotiated, Error> {
+        self.handshake_to(&self.destination, self.port, stream)
+            .await
+    }
 
-                    buf.resize(buf_size, 0);
+    /// Performs the whole Socks5 handshake just as
+    /// `handshake` does, but requesting the given
+    /// destination instead of the one of the constructor
+    async fn handshake_to(
+        &self,
+        destination: &socks5::Destination,
+        port: u16,
+        stream: &mut S,
+    ) -> Result<Negotiated, Error> {
+        let mut reply_code = None;
 
-                    // The length of UNAME
-                    let username_length = username.len();
+        let future = self.negotiate(destination, port, stream, &mut reply_code);
+        observed(self.observer.as_deref(), future)
+            .await
+            .map_err(|kind| attempt_error(kind, reply_code, destination, port))
+    }
 
-                    // Set username length to the ULEN field
-                    buf[1] = username_length as u8;
+    /// Attaches the details of the
+    /// attempt to the kind of an error
+    pub(crate) fn error(&self, kind: ErrorKind, reply_code: Option<u8>) -> Error {
+        attempt_error(kind, reply_code, &self.destination, self.port)
+    }
 
-                    // Set username to the UNAME field
-                    // (2) start index because field of UNAME start from 2
-                    // and last index it is start index + length of username
-                    buf[2..2 + username_length].clone_from_slice(username.as_bytes());
+    /// Performs the steps of the Socks5 handshake,
+    /// storing the reply field of the server's reply
+    /// to the connection request in `reply_code`
+    /// as soon as the reply is received.
+    ///
+    /// With an IP address destination and no authentication
+    /// (the hottest case), the greeting is built by the state
+    /// machine itself and the replies are read into a buffer
+    /// on the stack, so that nothing is allocated but the
+    /// entries of the timers of the timeouts (if any)
+    async fn negotiate(
+        &self,
+        destination: &socks5::Destination,
+        port: u16,
+        stream: &mut S,
+        reply_code: &mut Option<u8>,
+    ) -> Result<Negotiated, ErrorKind> {
+        // Checking that the method can be offered,
+        // so that we never send a handshake
+        // we are unable to proceed with
+        self.auth.validate()?;
 
-                    // Length of password
-                    let pass_length = password.len();
+        let mut handshake = Socks5Handshake::new(destination.clone(), port);
+        handshake.with_command(self.command);
+        handshake.with_max_reply_bytes(self.max_reply_bytes);
+        handshake.version = self.protocol_version;
 
-                    // Set password of length to the PLEN field
-                    // 2 + username_length this is index right after UNAME field
-                    buf[2 + username_length] = pass_length as u8;
+        // A domain name resolved locally is resolved
+        // once the method is selected, which is left
+        // to us along with the subnegotiation
+        let resolved_locally = matches!(
+            (destination, self.resolution),
+            (
+                socks5::Destination::DomainName(_),
+                socks5::ResolutionPolicy::Local
+            )
+        );
 
-                    // Set password to the PASSWD field
-                    // 2 + username_length + 1 this index rigth after PLEN field
-                    buf[2 + username_length + 1..].clone_from_slice(password.as_bytes());
+        // No authentication alone is offered by the state
+        // machine itself, with no subnegotiation to perform,
+        // while any other method is delegated to us
+        if resolved_locally || !self.auth.is_builtin_no_auth(sealed::Token(())) {
+            // The authentication methods offered, each
+            // subnegotiation of which is performed by the method
+            let mut methods = Vec::new();
+            self.auth.offer_methods(&mut methods);
 
-                    let read_bytes = self.send_payload(&mut buf, &mut stream).await.unwrap();
+            // Reordering the methods offered, where
+            // the ones not listed keep coming last
+            // in the order of the authentication
+            if !self.method_order.is_empty() {
+                let order = &self.method_order;
+                methods.sort_by_key(|method| {
+                    order
+                        .iter()
+                        .position(|listed| listed == method)
+                        .unwrap_or(order.len())
+                });
+            }
 
-                    // The server must send reply
-                    // with the length of 2 bytes.
-                    // Anything else is a sense of an error
-                    if read_bytes != 2 {
-                        return Err(ErrorKind::BadBuffer);
-                    }
+            handshake.with_methods(&methods);
+        }
 
-                    // Analyzing the received reply
-                    // and returning a socks4 general proxy client
-                    // instance if everything was successful
-                    return match buf[1] {
-                        // Means that request accepted
-                        0x00 => Ok(TcpNoAuthStream {
-                            wrapped_stream: stream,
-                        }),
-                        0x01 => Err(ErrorKind::SocksServerFailure),
-                        0x02 => Err(ErrorKind::RequestDenied),
-                        0x03 => Err(ErrorKind::NetworkUnreachable),
-                        0x04 => Err(ErrorKind::HostUnreachable),
-                        0x05 => Err(ErrorKind::ConnectionRefused),
-                        0x06 => Err(ErrorKind::TTLExpired),
-                        0x07 => Err(ErrorKind::NotSupported),
-                        0x08 => Err(ErrorKind::DestinationNotSupported),
-                        _ => Err(ErrorKind::BadBuffer),
-                    };
+        // Driving the handshake, where the input is what
+        // has been read from the server, into a buffer on
+        // the stack fitting any reply bound to an IP address
+        let mut input = [0; REPLY_CAPACITY];
+        let mut read_bytes = 0;
+        loop {
+            let step = handshake.step_inline(&input[..read_bytes]);
+            *reply_code = handshake.reply_code();
+            read_bytes = 0;
+
+            match step {
+                Step::Write(payload) => self.write_payload(&payload, stream).await?,
+                Step::NeedMore(len) => {
+                    // A longer message (a domain name bound
+                    // address) is read in parts
+                    let buf = &mut input[..len.min(REPLY_CAPACITY)];
+                    let read_timeout = self.timeouts.read_timeout;
+                    read_bytes = read_input(&mut handshake, buf, stream, read_timeout).await?
                 }
-            }
-            0x03..=0x7F => return Err(ErrorKind::Method(NotSupportedMethod::IANA)),
-            0x80..=0xFE => return Err(ErrorKind::Method(NotSupportedMethod::PrivateMethods)),
-            0xFF => return Err(ErrorKind::BadBuffer),
-        };
+                Step::Authenticate(selected) => {
+                    // Performing the method-dependent subnegotiation
+                    // (if any) before proceeding to the connection request,
+                    // the whole of it is bounded by the authentication timeout
+                    let future = self
+                        .auth
+                        .negotiate_selected(selected, stream, &self.timeouts);
+                    optional_timeout(self.auth_timeout, future)
+                        .await
+                        .map_err(|_| {
+                            ErrorKind::OperationTimeoutReached(TimeoutPhase::Handshake)
+                        })??;
 
-        // Computing the length of a Socks5 request
-        // The buffer length is computed this way:
-        //  (+1) for the number of the version of the socks protocol (4 in this case)
-        //  (+1) for the command number (1 or 2)
-        //  (+1) for the reserved byte, must be 0x00
-        //  (+1) for the destination address type,
-        //       must be 0x01, 0x03 or 0x04,
-        //       where 0x01 stands for an IPv4 address,
-        //       0x03 stands for a domain name and
-        //       0x04 stands for an IPv6 address
-        //  [+4]* if the type of the address is IPv4,
-        //  [+n]* if the type of the address is domain
-        //  [+16]* if the type of the address is IPv6
-        //  (+2) for port (in the network byte order)
-        let dest_buf_len = self.destination.len_as_buffer();
-        let buf_len = 1 + 1 + 1 + dest_buf_len + 2;
-
-        // Reallocating the payload buffer
-        buf.resize(buf_len, 0);
-
-        // Setting the version of the socks protocol
-        // being used in the payload buffer
-        buf[0] = 5;
-
-        // Setting the tcp connection establishment command
-        buf[1] = socks5::Command::TcpConnectionEstablishment as u8;
-
-        // Setting a 0x00 byte as it is
-        // rule of the socks5 protocol
-        // buf[2] = 0;
-
-        // Filling the buffer with the destiation
-        self.destination.extend_buffer(&mut buf[3..]).unwrap();
-
-        // Writing port as a big endian short
-        BigEndian::write_u16(&mut buf[3 + dest_buf_len..3 + dest_buf_len + 2], self.port);
-
-        // Sending our generated payload
-        let read_bytes = self.send_payload(&mut buf, &mut stream).await.unwrap();
-
-        // The server must send reply
-        // with the length of 2 bytes.
-        // Anything else is a sense of an error
-        if read_bytes != 2 {
-            return Err(ErrorKind::BadBuffer);
+                    // If the domain name destination has to be resolved
+                    // locally, the resolved address is sent instead of it
+                    if let (true, socks5::Destination::DomainName(name)) =
+                        (resolved_locally, destination)
+                    {
+                        handshake.set_destination(self.resolve(name).await?);
+                    }
+                }
+                Step::Done { bound, auth_method } => {
+                    return Ok(Negotiated { bound, auth_method });
+                }
+                Step::Error(kind) => return Err(kind),
+            }
         }
+    }
+
+    /// Writes the whole payload to the server
+    /// and reads a reply into the same buffer,
+    /// returning the number of bytes read
+    async fn exchange_payload(&self, buf: &mut [u8], stream: &mut S) -> Result<usize, ErrorKind> {
+        let future = stream.write_all(buf);
+        let future = optional_timeout(self.timeouts.write_timeout, future);
+        future
+            .await
+            .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Write))??;
+        trace_bytes("sent", buf);
+
+        // Reading a reply from the server
+        let future = stream.read(buf);
+        let future = optional_timeout(self.timeouts.read_timeout, future);
+        let read_bytes = future
+            .await
+            .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Read))??;
+        trace_bytes("received", &buf[..read_bytes]);
+
+        Ok(read_bytes)
+    }
+
+    /// Writes the whole payload
+    /// to the server
+    async fn write_payload(&self, buf: &[u8], stream: &mut S) -> Result<(), ErrorKind> {
+        let future = stream.write_all(buf);
+        let future = optional_timeout(self.timeouts.write_timeout, future);
+        future
+            .await
+            .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Write))??;
+        trace_bytes("sent", buf);
+
+        Ok(())
+    }
+}
+
+/// Reads no more than `input.len()` bytes from
+/// the server into the input of the handshake and
+/// returns the number of them, where the server
+/// having closed the connection is the error
+/// the handshake tells
+async fn read_input<S>(
+    handshake: &mut Socks5Handshake,
+    input: &mut [u8],
+    stream: &mut S,
+    read_timeout: Option<Duration>,
+) -> Result<usize, ErrorKind>
+where
+    S: IOStream,
+{
+    let future = stream.read(input);
+    let future = optional_timeout(read_timeout, future);
+    let read_bytes = future
+        .await
+        .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Read))??;
+
+    if read_bytes == 0 {
+        return Err(handshake.closed());
+    }
+    trace_bytes("received", &input[..read_bytes]);
+
+    Ok(read_bytes)
+}
 
-        // Analyzing the received reply
-        // and returning a socks4 general proxy client
-        // instance if everything was successful
-        match buf[1] {
-            // Means that request accepted
-            0x00 => Ok(TcpNoAuthStream {
-                wrapped_stream: stream,
-            }),
-            0x01 => Err(ErrorKind::SocksServerFailure),
-            0x02 => Err(ErrorKind::RequestDenied),
-            0x03 => Err(ErrorKind::NetworkUnreachable),
-            0x04 => Err(ErrorKind::HostUnreachable),
-            0x05 => Err(ErrorKind::ConnectionRefused),
-            0x06 => Err(ErrorKind::TTLExpired),
-            0x07 => Err(ErrorKind::NotSupported),
-            0x08 => Err(ErrorKind::DestinationNotSupported),
-            _ => Err(ErrorKind::BadBuffer),
+/// Attaches the details of an attempt to
+/// the destination to the kind of an error
+fn attempt_error(
+    kind: ErrorKind,
+    reply_code: Option<u8>,
+    destination: &socks5::Destination,
+    port: u16,
+) -> Error {
+    Error {
+        kind,
+        reply_code,
+        destination: destination.to_string(),
+        port,
+    }
+}
+
+impl Error {
+    /// Returns the kind of the error
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// Consumes the error and
+    /// returns the kind of it
+    pub fn into_kind(self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Returns the category of the kind of the error
+    pub fn category(&self) -> ErrorCategory {
+        self.kind.category()
+    }
+
+    /// Returns the reply field of the server's
+    /// reply to the connection request, or `None`
+    /// if the attempt has failed before the reply
+    pub fn reply_code(&self) -> Option<u8> {
+        self.reply_code
+    }
+
+    /// Returns the destination of the attempt
+    pub fn destination(&self) -> &str {
+        &self.destination
+    }
+
+    /// Returns the port of the destination
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (destination: {}:{}",
+            self.kind, self.destination, self.port
+        )?;
+
+        if let Some(reply_code) = self.reply_code {
+            write!(f, ", reply code: {:#04x}", reply_code)?;
         }
+
+        f.write_str(")")
+    }
+}
+
+impl From<io::Error> for ErrorKind {
+    fn from(e: io::Error) -> ErrorKind {
+        ErrorKind::IOError(e)
+    }
+}
+
+#[async_trait::async_trait]
+impl<A, S> ProxyConstructor for Socks5<A, S>
+where
+    A: AuthMethod,
+    S: IOStream + Send,
+{
+    type Stream = S;
+    type ProxyStream = TcpNoAuthStream<S>;
+    type ErrorKind = Error;
+
+    async fn connect(&self, stream: Self::Stream) -> Result<Self::ProxyStream, Self::ErrorKind> {
+        self.connect_unboxed(stream).await
     }
 
     /// Writing the initial payload to the server
@@ -435,59 +1562,337 @@ impl<'a> ProxyConstructor for TcpNoAuth<'a> {
         buf: &mut Vec<u8>,
         stream: &mut Self::Stream,
     ) -> Result<usize, Self::ErrorKind> {
-        let future = stream.write_all(&buf);
-        let future = timeout(self.timeouts.write_timeout, future);
-        let _ = future
+        self.exchange_payload(buf, stream)
             .await
-            .map_err(|_| ErrorKind::OperationTimeoutReached)?
-            .map_err(|e| ErrorKind::IOError(e))?;
+            .map_err(|kind| self.error(kind, None))
+    }
+}
 
-        // Reading a reply from the server
-        let future = stream.read(buf);
-        let future = timeout(self.timeouts.read_timeout, future);
-        let read_bytes = future
+impl<S> TcpNoAuthStream<S> {
+    /// Returns the endpoint that the server
+    /// has bound, as reported in its reply
+    /// (where the address may be the unspecified
+    /// one, see `reachable_bound_endpoint`)
+    pub fn bound_endpoint(&self) -> &BoundEndpoint {
+        &self.bound
+    }
+
+    /// Returns the authentication method (METHOD)
+    /// selected by the server in the greeting reply,
+    /// for instance, 0x00 for no authentication
+    /// or 0x02 for `Username/Password`
+    pub fn auth_method(&self) -> u8 {
+        self.auth_method
+    }
+
+    /// Returns the command sent in the request
+    pub fn command(&self) -> socks5::Command {
+        self.command
+    }
+
+    /// Returns a shared reference
+    /// to the underlying stream
+    pub fn get_ref(&self) -> &S {
+        &self.wrapped_stream
+    }
+
+    /// Returns a mutable reference
+    /// to the underlying stream
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.wrapped_stream
+    }
+
+    /// Consumes the proxy stream and
+    /// returns the underlying stream
+    /// it operates on
+    pub fn into_inner(self) -> S {
+        self.wrapped_stream
+    }
+
+    /// Consumes the proxy stream and returns
+    /// the underlying stream along with the bytes
+    /// read past the reply of the server, which
+    /// have not been returned yet. The reply is read
+    /// exactly, never past its end, so that there are
+    /// none and the data following the reply (even if
+    /// sent along with it) is read from the stream
+    pub fn into_inner_with_buffered(self) -> (S, Vec<u8>) {
+        (self.wrapped_stream, Vec::new())
+    }
+}
+
+impl<S: IOStream> TcpNoAuthStream<S> {
+    /// Wraps the tunnel in a `BufReader`, so that
+    /// line-oriented protocols (`read_line`, `read_until`)
+    /// are spoken through it
+    pub fn buffered(self) -> BufReader<Self> {
+        BufReader::new(self)
+    }
+
+    /// Shuts down the tunnel within the given timeout,
+    /// just as `general::shutdown_with_timeout` does
+    pub async fn shutdown_with_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        general::shutdown_with_timeout(self, timeout).await
+    }
+
+    /// Reads what the destination sends first,
+    /// just as `general::read_initial` does
+    pub async fn read_initial(&mut self, max: usize) -> io::Result<Vec<u8>> {
+        general::read_initial(self, max).await
+    }
+
+    /// Returns the timeouts of the constructor
+    /// the stream has been established with
+    pub fn timeouts(&self) -> &ConnectionTimeouts {
+        &self.timeouts
+    }
+
+    /// Returns the kind of the last I/O error the
+    /// tunnel has failed with (the reads and writes
+    /// of the underlying stream), if any.
+    ///
+    /// The crate cannot know the policy of the proxy
+    /// server, while the way the tunnel has ended tells
+    /// something: a read returning 0 bytes (EOF) means a
+    /// clean close, either by the destination or by the
+    /// server closing an idle tunnel on its own, while
+    /// `io::ErrorKind::ConnectionReset` (or `BrokenPipe` on
+    /// a write) means that the connection has been aborted,
+    /// which is how most servers drop a tunnel they refuse
+    /// to keep (for instance, once their idle timeout is reached)
+    pub fn last_error_kind(&self) -> Option<io::ErrorKind> {
+        self.last_error
+    }
+
+    /// Reads from the tunnel within the read timeout
+    /// of the constructor (with no limit if there is
+    /// none), so that the timeouts bound the whole
+    /// lifetime of the connection and not just the
+    /// handshake. Fails with `io::ErrorKind::TimedOut`
+    /// if the timeout is reached
+    pub async fn read_timed(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_timeout = self.timeouts.read_timeout;
+        general::read_timed(self, buf, read_timeout).await
+    }
+
+    /// Writes to the tunnel within the write timeout
+    /// of the constructor, just as `read_timed` reads,
+    /// returning the number of bytes written.
+    /// Fails with `io::ErrorKind::TimedOut`
+    /// if the timeout is reached
+    pub async fn write_timed(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let write_timeout = self.timeouts.write_timeout;
+        general::write_timed(self, buf, write_timeout).await
+    }
+
+    /// Waits for the second reply to the tcp port
+    /// binding (BIND) request, which the server sends
+    /// once a peer has connected to the bound endpoint,
+    /// and returns the endpoint of the peer.
+    /// From then on, the stream is the tunnel to the peer.
+    ///
+    /// The reply is awaited within the accept timeout of
+    /// the constructor (the read timeout unless set), failing
+    /// with `ErrorKind::OperationTimeoutReached(TimeoutPhase::Handshake)`
+    /// if no peer connects in time, and with `ErrorKind::NotSupported`
+    /// if the request has not been a BIND one or `accept` has already
+    /// been called (even if it has failed or timed out, since part
+    /// of the reply may have been consumed)
+    pub async fn accept(&mut self) -> Result<BoundEndpoint, ErrorKind> {
+        if self.command != socks5::Command::TcpPortBinding || self.accepted {
+            return Err(ErrorKind::NotSupported);
+        }
+        self.accepted = true;
+
+        let accept_timeout = self.accept_timeout;
+        let future = self.read_second_reply();
+        let future = optional_timeout(accept_timeout, future);
+        future
             .await
-            .map_err(|_| ErrorKind::OperationTimeoutReached)?
-            .map_err(|e| ErrorKind::IOError(e))?;
+            .map_err(|_| ErrorKind::OperationTimeoutReached(TimeoutPhase::Handshake))?
+    }
 
-        Ok(read_bytes)
+    /// Reads the second reply to the BIND request,
+    /// with no timeout of its own, since the wait for
+    /// the peer is bounded by `accept` as a whole
+    async fn read_second_reply(&mut self) -> Result<BoundEndpoint, ErrorKind> {
+        let mut handshake = Socks5Handshake::awaiting_reply(self.max_reply_bytes);
+        handshake.version = self.protocol_version;
+        let mut input = [0; REPLY_CAPACITY];
+        let mut read_bytes = 0;
+        loop {
+            match handshake.step_inline(&input[..read_bytes]) {
+                Step::NeedMore(len) => {
+                    let buf = &mut input[..len.min(REPLY_CAPACITY)];
+                    let stream = &mut self.wrapped_stream;
+                    read_bytes = read_input(&mut handshake, buf, stream, None).await?
+                }
+                Step::Done { bound, .. } => return Ok(bound),
+                Step::Error(kind) => return Err(kind),
+                // Nothing is written while
+                // waiting for a reply
+                Step::Write(_) | Step::Authenticate(_) => return Err(ErrorKind::BadBuffer),
+            }
+        }
     }
 }
 
-impl AsyncRead for TcpNoAuthStream {
+impl<S: IOStream> AsyncRead for TcpNoAuthStream<S> {
     fn poll_read(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<io::Result<usize>> {
-        let pinned = &mut Pin::into_inner(self).wrapped_stream;
-        Pin::new(pinned).poll_read(cx, buf)
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_read(cx, buf);
+        record_error(&mut this.last_error, poll)
+    }
+
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [MaybeUninit<u8>]) -> bool {
+        self.wrapped_stream.prepare_uninitialized_buffer(buf)
+    }
+
+    fn poll_read_buf<B: BufMut>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut B,
+    ) -> Poll<io::Result<usize>>
+    where
+        Self: Sized,
+    {
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_read_buf(cx, buf);
+        record_error(&mut this.last_error, poll)
     }
 }
 
-impl AsyncWrite for TcpNoAuthStream {
+impl<S: IOStream> AsyncWrite for TcpNoAuthStream<S> {
     fn poll_write(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize, io::Error>> {
-        let stream = &mut Pin::into_inner(self).wrapped_stream;
-        Pin::new(stream).poll_write(cx, buf)
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_write(cx, buf);
+        record_error(&mut this.last_error, poll)
+    }
+
+    fn poll_write_buf<B: Buf>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut B,
+    ) -> Poll<Result<usize, io::Error>>
+    where
+        Self: Sized,
+    {
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_write_buf(cx, buf);
+        record_error(&mut this.last_error, poll)
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
-        let stream = &mut Pin::into_inner(self).wrapped_stream;
-        Pin::new(stream).poll_flush(cx)
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_flush(cx);
+        record_error(&mut this.last_error, poll)
     }
 
     fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
-        let stream = &mut Pin::into_inner(self).wrapped_stream;
-        Pin::new(stream).poll_shutdown(cx)
+        let this = Pin::into_inner(self);
+        let poll = Pin::new(&mut this.wrapped_stream).poll_shutdown(cx);
+        record_error(&mut this.last_error, poll)
     }
 }
 
-impl Into<TcpStream> for TcpNoAuthStream {
-    fn into(self) -> TcpStream {
-        self.wrapped_stream
+impl From<TcpNoAuthStream> for TcpStream {
+    fn from(stream: TcpNoAuthStream) -> TcpStream {
+        stream.wrapped_stream
+    }
+}
+
+/// Gives the raw file descriptor of the
+/// underlying stream, for instance, to set
+/// a socket option the crate does not expose
+#[cfg(unix)]
+impl<S: AsRawFd> AsRawFd for TcpNoAuthStream<S> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.wrapped_stream.as_raw_fd()
+    }
+}
+
+/// Gives the raw socket of the
+/// underlying stream, for instance, to set
+/// a socket option the crate does not expose
+#[cfg(windows)]
+impl<S: AsRawSocket> AsRawSocket for TcpNoAuthStream<S> {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.wrapped_stream.as_raw_socket()
+    }
+}
+
+impl TcpNoAuthStream<TcpStream> {
+    /// Returns the endpoint that the server has
+    /// bound, with an unspecified address substituted
+    /// with the address of the server (see
+    /// `BoundEndpoint::substitute_unspecified`), which is
+    /// the endpoint to be used for subsequent operations
+    pub fn reachable_bound_endpoint(&self) -> io::Result<BoundEndpoint> {
+        let server = self.wrapped_stream.peer_addr()?.ip();
+        Ok(self.bound.clone().substitute_unspecified(server))
+    }
+
+    /// Receives data from the underlying tcp stream
+    /// without removing it from the queue, so that
+    /// the next read returns the same data
+    pub async fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.wrapped_stream.peek(buf).await
+    }
+
+    /// Attempts to receive data from the underlying
+    /// tcp stream without removing it from the queue
+    pub fn poll_peek(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.wrapped_stream.poll_peek(cx, buf)
+    }
+
+    /// Splits the proxy stream into the owned
+    /// read and write halves, which can be used
+    /// concurrently (for instance, from two tasks)
+    pub fn into_split(self) -> (ReadHalf, WriteHalf) {
+        proxy::split(self.wrapped_stream, Vec::new(), 0)
+    }
+
+    /// Checks whether the tunnel is still alive (for
+    /// instance, before reusing a pooled connection)
+    /// without blocking and without consuming any data.
+    /// The proxy having closed the connection
+    /// (or an error) means the tunnel is dead
+    pub fn is_alive(&mut self) -> bool {
+        general::is_alive(&mut self.wrapped_stream)
+    }
+
+    /// Sets the `SO_LINGER` option of the underlying
+    /// tcp stream, bounding how long closing it waits
+    /// for the unsent data to be transmitted
+    pub fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
+        self.wrapped_stream.set_linger(linger)
+    }
+
+    /// Sets the `SO_KEEPALIVE` option of the underlying
+    /// tcp stream along with the idle time before the first
+    /// probe (`None` disables it), so that an idle tunnel is
+    /// not dropped by a NAT forgetting idle connections.
+    /// The probes are tcp ones, since any bytes written
+    /// into the tunnel would reach the destination
+    pub fn set_keepalive(&self, keepalive: Option<Duration>) -> io::Result<()> {
+        self.wrapped_stream.set_keepalive(keepalive)
+    }
+}
+
+/// Prints the peer address of the underlying
+/// tcp stream only, never the transferred data
+impl fmt::Debug for TcpNoAuthStream<TcpStream> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TcpNoAuthStream")
+            .field("peer_addr", &self.wrapped_stream.peer_addr().ok())
+            .finish()
     }
 }
@@ -0,0 +1,86 @@
+use crate::clients::socks5::no_auth::{Error, ErrorKind, TcpNoAuth};
+use crate::clients::socks5::{Command, Destination};
+use crate::general::{ConnectionTimeouts, IOStream};
+use crate::proxy::{BoundAddr, ProxyConstructor};
+use std::net::IpAddr;
+use tokio::net::TcpStream;
+
+/// Represents the proxy constructor that resolves
+/// a domain name through the proxy server with the
+/// RESOLVE command (0xF0) and returns the address it
+/// resolves to, opening no connection to it at all
+/// (so that the name is never resolved locally).
+///
+/// RESOLVE is an extension of Tor rather than a part
+/// of the standard Socks5 (RFC 1928): a server that
+/// does not support it replies with "command not
+/// supported", which is `ErrorKind::NotSupported`
+pub struct Resolve<'a, S = TcpStream> {
+    /// The constructor the handshake is performed with,
+    /// which destination is the name being resolved
+    inner: TcpNoAuth<'a, S>,
+}
+
+impl<'a, S> Resolve<'a, S> {
+    /// Creates a constructor resolving the `name`
+    /// destination (usually a domain name)
+    pub fn new(name: Destination, timeouts: ConnectionTimeouts) -> Resolve<'a, S> {
+        // The port is ignored by the server
+        let mut inner = TcpNoAuth::new(name, 0, timeouts);
+        inner.with_command(Command::Resolve);
+
+        Resolve { inner }
+    }
+
+    /// Uses `Username/Password` method for authentication
+    /// (which Tor uses to isolate the circuits)
+    pub fn with_authentication(&mut self, username: &'a str, password: &'a str) {
+        self.inner.with_authentication(username, password);
+    }
+
+    /// Returns the destination being resolved
+    pub fn name(&self) -> &Destination {
+        self.inner.destination()
+    }
+
+    /// Returns the timeouts set
+    pub fn timeouts(&self) -> &ConnectionTimeouts {
+        self.inner.timeouts()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, S> ProxyConstructor for Resolve<'a, S>
+where
+    S: IOStream + Send,
+{
+    type Stream = S;
+    type ProxyStream = IpAddr;
+    type ErrorKind = Error;
+
+    /// Performs the handshake and returns the address the
+    /// name resolves to, which is the bound address of the
+    /// reply. The stream is closed once the reply is read
+    async fn connect(
+        &self,
+        mut stream: Self::Stream,
+    ) -> Result<Self::ProxyStream, Self::ErrorKind> {
+        let negotiated = self.inner.handshake(&mut stream).await?;
+
+        // The name must be resolved into an address,
+        // while another domain name makes no sense
+        match negotiated.bound.addr {
+            BoundAddr::Ipv4(addr) => Ok(IpAddr::V4(addr)),
+            BoundAddr::Ipv6(addr) => Ok(IpAddr::V6(addr)),
+            BoundAddr::Domain(_) => Err(self.inner.error(ErrorKind::BadBuffer, Some(0x00))),
+        }
+    }
+
+    async fn send_payload(
+        &self,
+        buf: &mut Vec<u8>,
+        stream: &mut Self::Stream,
+    ) -> Result<usize, Self::ErrorKind> {
+        self.inner.send_payload(buf, stream).await
+    }
+}
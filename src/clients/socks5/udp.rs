@@ -0,0 +1,448 @@
+use crate::clients::socks5::no_auth::{Error, ErrorKind, TcpNoAuth};
+use crate::clients::socks5::{Command, Destination, PayloadWriter};
+use crate::general::{ConnectionTimeouts, IOStream};
+use crate::proxy::{BoundAddr, BoundEndpoint, ProxyConstructor};
+use byteorder::{BigEndian, ByteOrder};
+use futures_util::future::poll_fn;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use tokio::net::{lookup_host, TcpStream, UdpSocket};
+
+/// The maximal length of a UDP datagram,
+/// which `Socks5UdpStream::recv_from` reads
+const MAX_DATAGRAM_LEN: usize = 65535;
+
+/// The bit of the FRAG byte marking
+/// the end of a fragment sequence
+const END_OF_SEQUENCE: u8 = 0x80;
+
+/// Represents a received Socks5 UDP datagram:
+/// the parsed header and the payload it carries
+#[derive(Debug)]
+pub struct Datagram<'a> {
+    /// The FRAG byte, where 0x00 means a standalone
+    /// datagram, the low 7 bits are the position of a
+    /// fragment and the high bit marks the last fragment
+    pub frag: u8,
+    /// The destination the datagram has been sent from
+    pub destination: Destination,
+    /// The port of the destination
+    pub port: u16,
+    /// The payload of the datagram
+    pub data: &'a [u8],
+}
+
+impl<'a> Datagram<'a> {
+    /// Parses a datagram with the Socks5 UDP header:
+    ///  (+2) for the reserved bytes, must be 0x0000
+    ///  (+1) for the FRAG byte
+    ///  (+1) for the address type and
+    ///  [+n]* for the address itself
+    ///  (+2) for port (in the network byte order)
+    /// followed by the payload.
+    ///
+    /// Fragments are parsed as well, use `unfragmented`
+    /// or a `Reassembler` to handle them
+    pub fn parse(buf: &'a [u8]) -> Result<Datagram<'a>, ErrorKind> {
+        // The reserved bytes must be zeroes
+        if buf.len() < 3 || buf[0] != 0x00 || buf[1] != 0x00 {
+            return Err(ErrorKind::BadBuffer);
+        }
+
+        let (destination, len) =
+            Destination::from_buffer(&buf[3..]).map_err(|_| ErrorKind::BadBuffer)?;
+
+        // The port follows the address
+        let port_at = 3 + len;
+        if buf.len() < port_at + 2 {
+            return Err(ErrorKind::BadBuffer);
+        }
+
+        Ok(Datagram {
+            frag: buf[2],
+            destination,
+            port: BigEndian::read_u16(&buf[port_at..port_at + 2]),
+            data: &buf[port_at + 2..],
+        })
+    }
+
+    /// Returns `true` if the datagram
+    /// is a fragment of a larger one
+    pub fn is_fragment(&self) -> bool {
+        self.frag != 0x00
+    }
+
+    /// Returns the datagram if it is a standalone one,
+    /// unless `ErrorKind::FragmentedDatagram`, so that
+    /// a partial payload is never taken for a whole one
+    pub fn unfragmented(self) -> Result<Datagram<'a>, ErrorKind> {
+        if self.is_fragment() {
+            return Err(ErrorKind::FragmentedDatagram(self.frag));
+        }
+
+        Ok(self)
+    }
+}
+
+/// Appends a standalone datagram carrying `data`
+/// to `destination`:`port` to the buffer `buf`,
+/// that is the Socks5 UDP header (with the reserved
+/// bytes and FRAG set to zeroes, and the address type
+/// matching the destination) followed by the payload.
+///
+/// Fails with `ErrorKind::DomainNameTooLong`
+/// if the domain name cannot be encoded
+pub fn encode_request(
+    destination: &Destination,
+    port: u16,
+    data: &[u8],
+    buf: &mut Vec<u8>,
+) -> Result<(), ErrorKind> {
+    // The header is computed this way:
+    //  (+2) for the reserved bytes (0x0000)
+    //  (+1) for the FRAG byte (0x00, standalone)
+    //  (+1) for the address type and
+    //  [+n]* for the address itself
+    //  (+2) for port (in the network byte order)
+    // The fields are appended one after another, while
+    // nothing is left in the buffer if the domain name
+    // cannot be encoded
+    let start = buf.len();
+    let result = PayloadWriter::new(buf)
+        .bytes(&[0x00, 0x00, 0x00])
+        .destination(destination)
+        .map(|writer| {
+            writer.port(port).bytes(data);
+        });
+
+    if result.is_err() {
+        buf.truncate(start);
+        return Err(ErrorKind::DomainNameTooLong);
+    }
+
+    Ok(())
+}
+
+/// Strips the Socks5 UDP header of a datagram
+/// received from the relay and returns the destination
+/// it has been sent from, its port and the payload.
+///
+/// A fragment is surfaced as `ErrorKind::FragmentedDatagram`
+/// (use `Datagram::parse` and a `Reassembler` to handle them)
+pub fn decode_reply(buf: &[u8]) -> Result<(Destination, u16, &[u8]), ErrorKind> {
+    let datagram = Datagram::parse(buf)?.unfragmented()?;
+    Ok((datagram.destination, datagram.port, datagram.data))
+}
+
+/// Reassembles fragmented datagrams
+/// following RFC 1928: the fragments of a sequence
+/// are numbered from 1, the last one has the high
+/// bit of FRAG set, and the sequence is abandoned
+/// as soon as a fragment arrives out of order
+/// or a standalone datagram arrives.
+///
+/// The reassembler keeps no timer, so that
+/// the caller is supposed to `reset` it if
+/// no fragment has arrived for too long
+#[derive(Default)]
+pub struct Reassembler {
+    /// The destination and port
+    /// of the first fragment
+    source: Option<(Destination, u16)>,
+    /// The position of the last
+    /// fragment that has been queued
+    last_position: u8,
+    /// The payload reassembled so far
+    queue: Vec<u8>,
+}
+
+impl Reassembler {
+    pub fn new() -> Reassembler {
+        Reassembler::default()
+    }
+
+    /// Abandons the fragment
+    /// sequence being reassembled
+    pub fn reset(&mut self) {
+        self.source = None;
+        self.last_position = 0;
+        self.queue.clear();
+    }
+
+    /// Takes a received datagram and returns
+    /// a whole payload with its destination and port
+    /// when it is available: either the datagram is
+    /// a standalone one or it completes a sequence
+    pub fn push(&mut self, datagram: Datagram<'_>) -> Option<(Destination, u16, Vec<u8>)> {
+        // A standalone datagram is handed as it is,
+        // abandoning a sequence being reassembled
+        if !datagram.is_fragment() {
+            self.reset();
+            return Some((datagram.destination, datagram.port, datagram.data.to_vec()));
+        }
+
+        let position = datagram.frag & !END_OF_SEQUENCE;
+
+        // A fragment that does not follow the previous one
+        // abandons the sequence, while it may start a new one
+        if position != self.last_position + 1 {
+            self.reset();
+
+            if position != 1 {
+                return None;
+            }
+        }
+
+        // Queueing the fragment
+        if self.source.is_none() {
+            self.source = Some((datagram.destination, datagram.port));
+        }
+        self.queue.extend_from_slice(datagram.data);
+        self.last_position = position;
+
+        // Handing the whole payload
+        // if the sequence is over
+        if datagram.frag & END_OF_SEQUENCE == 0 {
+            return None;
+        }
+
+        let data = std::mem::take(&mut self.queue);
+        let (destination, port) = self.source.take()?;
+        self.reset();
+
+        Some((destination, port, data))
+    }
+}
+
+/// Represents the proxy constructor that
+/// establishes a UDP association (the UDP ASSOCIATE
+/// command) and returns the relay the datagrams
+/// are to be sent to.
+///
+/// By default the client address sent in the request is
+/// the wildcard 0.0.0.0:0, meaning that the client does
+/// not know it yet, while `with_client_addr` restricts
+/// the association to datagrams from a specific address
+pub struct UdpAssociate<'a, S = TcpStream> {
+    /// The constructor the handshake is performed with,
+    /// which destination is the client address
+    inner: TcpNoAuth<'a, S>,
+}
+
+/// Represents an established UDP association:
+/// the stream of the control connection, which
+/// has to be kept open for as long as the association
+/// is used, and the relay reported by the server
+pub struct UdpAssociation<S = TcpStream> {
+    /// The control connection
+    control: S,
+    /// The endpoint of the relay
+    relay: BoundEndpoint,
+}
+
+/// Represents a UDP association along with
+/// a local UDP socket, created by `UdpAssociation::bind`,
+/// which adds and strips the Socks5 UDP header of the
+/// datagrams sent through the relay.
+///
+/// The control connection is held for as long as
+/// the stream lives, since the association terminates
+/// as soon as it is closed
+pub struct Socks5UdpStream {
+    /// The control connection
+    control: TcpStream,
+    /// The local socket the datagrams
+    /// are sent and received on
+    socket: UdpSocket,
+    /// The address of the relay
+    relay: SocketAddr,
+}
+
+impl<'a, S> UdpAssociate<'a, S> {
+    pub fn new(timeouts: ConnectionTimeouts) -> UdpAssociate<'a, S> {
+        let mut inner = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::UNSPECIFIED), 0, timeouts);
+        inner.with_command(Command::UdpPortBinding);
+
+        UdpAssociate { inner }
+    }
+
+    /// Restricts the association to the datagrams
+    /// sent from the given address of the client
+    pub fn with_client_addr(&mut self, addr: SocketAddr) {
+        let (destination, port) = Destination::from_socket_addr(addr);
+        self.inner.set_destination(destination, port);
+    }
+
+    /// Uses `Username/Password` method for authentication
+    pub fn with_authentication(&mut self, username: &'a str, password: &'a str) {
+        self.inner.with_authentication(username, password);
+    }
+
+    /// Returns the client address sent in the
+    /// request, which is 0.0.0.0:0 unless restricted
+    pub fn client_addr(&self) -> (&Destination, u16) {
+        (self.inner.destination(), self.inner.port())
+    }
+
+    /// Returns the timeouts set
+    pub fn timeouts(&self) -> &ConnectionTimeouts {
+        self.inner.timeouts()
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, S> ProxyConstructor for UdpAssociate<'a, S>
+where
+    S: IOStream + Send,
+{
+    type Stream = S;
+    type ProxyStream = UdpAssociation<S>;
+    type ErrorKind = Error;
+
+    async fn connect(
+        &self,
+        mut stream: Self::Stream,
+    ) -> Result<Self::ProxyStream, Self::ErrorKind> {
+        // Performing the handshake, where the
+        // bound endpoint of the reply is the relay
+        let negotiated = self.inner.handshake(&mut stream).await?;
+
+        Ok(UdpAssociation {
+            control: stream,
+            relay: negotiated.bound,
+        })
+    }
+
+    async fn send_payload(
+        &self,
+        buf: &mut Vec<u8>,
+        stream: &mut Self::Stream,
+    ) -> Result<usize, Self::ErrorKind> {
+        self.inner.send_payload(buf, stream).await
+    }
+}
+
+impl<S> UdpAssociation<S> {
+    /// Returns the endpoint of the relay
+    /// the datagrams are to be sent to
+    pub fn relay(&self) -> &BoundEndpoint {
+        &self.relay
+    }
+
+    /// Returns a shared reference
+    /// to the control connection
+    pub fn control(&self) -> &S {
+        &self.control
+    }
+
+    /// Consumes the association and returns
+    /// the control connection, the association
+    /// terminates as soon as it is closed
+    pub fn into_control(self) -> S {
+        self.control
+    }
+}
+
+impl UdpAssociation<TcpStream> {
+    /// Binds a UDP socket to `local_addr` and returns
+    /// the stream sending datagrams through the relay.
+    ///
+    /// An unspecified relay address (0.0.0.0 or ::) means
+    /// the address of the proxy server itself, while
+    /// a relay domain name is resolved locally, failing
+    /// with `ErrorKind::UnresolvableDestination` if
+    /// it resolves to no address
+    pub async fn bind(self, local_addr: SocketAddr) -> Result<Socks5UdpStream, ErrorKind> {
+        let server = self.control.peer_addr()?.ip();
+        let relay = self.relay.clone().substitute_unspecified(server);
+        let port = relay.port;
+        let relay = match &relay.addr {
+            BoundAddr::Ipv4(ip) => SocketAddr::new(IpAddr::V4(*ip), port),
+            BoundAddr::Ipv6(ip) => SocketAddr::new(IpAddr::V6(*ip), port),
+            BoundAddr::Domain(name) => lookup_host((name.as_str(), port))
+                .await?
+                .next()
+                .ok_or(ErrorKind::UnresolvableDestination)?,
+        };
+
+        let socket = UdpSocket::bind(local_addr).await?;
+
+        Ok(Socks5UdpStream {
+            control: self.control,
+            socket,
+            relay,
+        })
+    }
+}
+
+impl Socks5UdpStream {
+    /// Sends `data` to `destination`:`port` through
+    /// the relay, returning the number of bytes of
+    /// `data` sent.
+    ///
+    /// Fails with `ErrorKind::DomainNameTooLong`
+    /// if the domain name cannot be encoded
+    pub async fn send_to(
+        &self,
+        data: &[u8],
+        destination: &Destination,
+        port: u16,
+    ) -> Result<usize, ErrorKind> {
+        let mut buf = Vec::with_capacity(4 + destination.len_as_buffer() + 2 + data.len());
+        encode_request(destination, port, data, &mut buf)?;
+
+        let sent = poll_fn(|cx| self.socket.poll_send_to(cx, &buf, &self.relay)).await?;
+
+        // A datagram is either sent
+        // as a whole or not at all
+        Ok(sent.saturating_sub(buf.len() - data.len()))
+    }
+
+    /// Receives a datagram from the relay and returns
+    /// its payload along with the destination it has
+    /// been sent from and its port.
+    ///
+    /// Datagrams that do not come from the relay are
+    /// dropped, as are fragments (which RFC 1928 lets
+    /// a client not supporting fragmentation do)
+    pub async fn recv_from(&self) -> Result<(Vec<u8>, Destination, u16), ErrorKind> {
+        let mut buf = vec![0; MAX_DATAGRAM_LEN];
+
+        loop {
+            let (len, from) = poll_fn(|cx| self.socket.poll_recv_from(cx, &mut buf)).await?;
+            if from != self.relay {
+                continue;
+            }
+
+            match decode_reply(&buf[..len]) {
+                Ok((destination, port, data)) => return Ok((data.to_vec(), destination, port)),
+                Err(ErrorKind::FragmentedDatagram(_)) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Returns the address of the relay
+    /// the datagrams are sent to
+    pub fn relay(&self) -> SocketAddr {
+        self.relay
+    }
+
+    /// Returns the local address
+    /// the UDP socket is bound to
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Returns a shared reference
+    /// to the control connection
+    pub fn control(&self) -> &TcpStream {
+        &self.control
+    }
+
+    /// Returns a shared reference
+    /// to the local UDP socket
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+}
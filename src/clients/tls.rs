@@ -0,0 +1,81 @@
+use crate::general::IOStream;
+use std::io;
+use std::sync::Arc;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::ClientConfig;
+use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::TlsConnector;
+
+/// Performs a TLS client handshake with the destination
+/// over a proxy stream (any `ProxyStream` of the proxy
+/// constructors, for instance `TcpNoAuthStream`),
+/// verifying the certificate of the destination against
+/// the Mozilla's root certificates.
+///
+/// The `server_name` is the host name of the destination,
+/// which is sent as SNI and which the certificate is
+/// verified against
+///
+/// # Example
+///
+/// ```no_run
+/// use async_proxy::clients::socks5::{Destination, TcpNoAuth};
+/// use async_proxy::clients::tls;
+/// use async_proxy::general::ConnectionTimeouts;
+/// use async_proxy::proxy::ProxyConstructor;
+/// use tokio::net::TcpStream;
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let timeouts = ConnectionTimeouts::new(
+///         Duration::from_secs(8),
+///         Duration::from_secs(8),
+///         Duration::from_secs(8)
+///     );
+///
+///     // Connecting to example.com:443 through the proxy
+///     let destination = Destination::hostname("example.com").unwrap();
+///     let socks5_proxy = TcpNoAuth::new(destination, 443, timeouts);
+///     let stream = TcpStream::connect("203.0.113.10:1080").await.unwrap();
+///     let stream = socks5_proxy.connect(stream).await.unwrap();
+///
+///     // Establishing the TLS connection to the destination
+///     let stream = tls::connect_through(stream, "example.com").await.unwrap();
+/// }
+/// ```
+pub async fn connect_through<S>(proxy_stream: S, server_name: &str) -> io::Result<TlsStream<S>>
+where
+    S: IOStream,
+{
+    // Setting up the TLS client configuration
+    // with the Mozilla's root certificates
+    let mut config = ClientConfig::new();
+    config
+        .root_store
+        .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    let connector = TlsConnector::from(Arc::new(config));
+
+    connect_through_with(&connector, proxy_stream, server_name).await
+}
+
+/// Performs a TLS client handshake with the destination
+/// over a proxy stream, just like `connect_through`,
+/// but with a connector configured by a user
+/// (for instance, with custom root certificates
+/// or client certificates)
+pub async fn connect_through_with<S>(
+    connector: &TlsConnector,
+    proxy_stream: S,
+    server_name: &str,
+) -> io::Result<TlsStream<S>>
+where
+    S: IOStream,
+{
+    // Validating the server name before anything is sent,
+    // since it must be a valid DNS name to be sent as SNI
+    let name = DNSNameRef::try_from_ascii_str(server_name)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid server name"))?;
+
+    connector.connect(name, proxy_stream).await
+}
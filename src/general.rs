@@ -1,6 +1,14 @@
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::{timeout, Elapsed};
+use std::future::Future;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use std::net::IpAddr;
 use std::str::FromStr;
-use std::time::Duration;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+use std::io;
+use std::fmt;
 
 /// General trait which implementing type
 /// represents something where we can both
@@ -14,33 +22,488 @@ impl<T> IOStream for T
 where
     T: AsyncRead + AsyncWrite + Unpin {}
 
+/// A trait of something that resolves
+/// domain names into IP addresses, used by
+/// the proxy clients that resolve a destination
+/// locally, so that the OS resolver can be replaced
+/// (for instance, by a hosts map in tests, or by
+/// a DNS-over-HTTPS client)
+#[async_trait::async_trait]
+pub trait Resolver: Send + Sync {
+    /// Resolves a domain name into
+    /// the addresses it points to
+    async fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>>;
+}
+
+/// Represents the IP version preferred when
+/// a domain name resolved locally points to
+/// both IPv4 and IPv6 addresses (for instance,
+/// when one of the families is unreachable
+/// through the proxy server).
+/// The Socks4 clients are not concerned, since
+/// they take an IPv4 destination only
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IpPreference {
+    /// The first address resolved is taken,
+    /// whichever its version.
+    /// It is the default preference
+    #[default]
+    Any,
+    /// An IPv4 address is taken if there is
+    /// one, an IPv6 one otherwise
+    Ipv4First,
+    /// An IPv6 address is taken if there is
+    /// one, an IPv4 one otherwise
+    Ipv6First,
+    /// Only an IPv4 address is taken
+    Ipv4Only,
+    /// Only an IPv6 address is taken
+    Ipv6Only,
+}
+
+impl IpPreference {
+    /// Selects the address to be taken out of
+    /// the resolved ones (in the order of the
+    /// resolver), if there is any acceptable
+    pub fn select(self, addrs: &[IpAddr]) -> Option<IpAddr> {
+        let v4 = || addrs.iter().copied().find(IpAddr::is_ipv4);
+        let v6 = || addrs.iter().copied().find(IpAddr::is_ipv6);
+
+        match self {
+            IpPreference::Any => addrs.first().copied(),
+            IpPreference::Ipv4First => v4().or_else(v6),
+            IpPreference::Ipv6First => v6().or_else(v4),
+            IpPreference::Ipv4Only => v4(),
+            IpPreference::Ipv6Only => v6(),
+        }
+    }
+}
+
+/// A trait of something that observes the
+/// connection attempts of a proxy constructor
+/// (for instance, to record the handshake latency
+/// and outcome into metrics), where `E` is the
+/// error kind of the proxy client.
+///
+/// The methods do nothing by default, so that
+/// only the hooks needed are implemented.
+/// A handshake that is cancelled (its future
+/// dropped) reports no outcome at all
+pub trait Observer<E>: Send + Sync {
+    /// Called right before the handshake starts
+    fn on_connect_start(&self) {}
+
+    /// Called once the handshake has succeeded,
+    /// with the time it has taken
+    fn on_connect_success(&self, _elapsed: Duration) {}
+
+    /// Called once the handshake has failed,
+    /// with the error and the time it has taken
+    fn on_connect_error(&self, _error: &E, _elapsed: Duration) {}
+}
+
+/// The default resolver, which
+/// uses the OS resolver through
+/// `tokio::net::lookup_host`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+#[async_trait::async_trait]
+impl Resolver for SystemResolver {
+    async fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        // The port does not matter,
+        // since only addresses are taken
+        let addrs = lookup_host((host, 0)).await?;
+        Ok(addrs.map(|addr| addr.ip()).collect())
+    }
+}
+
 /// Just a structure containing 
-/// connecting/read/write timeouts
+/// connecting/read/write timeouts,
+/// where `None` means no timeout at all
 #[derive(Clone)]
 pub struct ConnectionTimeouts {
-    pub connecting_timeout: Duration,
-    pub write_timeout: Duration,
-    pub read_timeout: Duration
+    pub connecting_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+    pub read_timeout: Option<Duration>
+}
+
+/// The timeout each of the default
+/// `ConnectionTimeouts` is set to
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Represents a builder of `ConnectionTimeouts`,
+/// naming each of the timeouts set, while the
+/// ones not set are `DEFAULT_TIMEOUT`
+pub struct ConnectionTimeoutsBuilder {
+    timeouts: ConnectionTimeouts
 }
 
 impl ConnectionTimeouts {
+    /// Returns a builder of the timeouts,
+    /// starting from the default ones
+    pub fn builder() -> ConnectionTimeoutsBuilder {
+        ConnectionTimeoutsBuilder { timeouts: ConnectionTimeouts::default() }
+    }
+
+    /// Creates the timeouts with all
+    /// of them set to the same `timeout`
+    pub fn uniform(timeout: Duration) -> ConnectionTimeouts {
+        ConnectionTimeouts::new(timeout, timeout, timeout)
+    }
+
+    /// Creates the timeouts
+    /// with all of them present
     pub fn new(connecting_timeout: Duration,
                write_timeout: Duration,
                read_timeout: Duration)
         -> ConnectionTimeouts
     {
         ConnectionTimeouts { 
-            connecting_timeout,
-            write_timeout,
-            read_timeout
+            connecting_timeout: Some(connecting_timeout),
+            write_timeout: Some(write_timeout),
+            read_timeout: Some(read_timeout)
+        }
+    }
+
+    /// Creates the timeouts
+    /// with none of them present
+    pub fn none() -> ConnectionTimeouts {
+        ConnectionTimeouts {
+            connecting_timeout: None,
+            write_timeout: None,
+            read_timeout: None
+        }
+    }
+
+    /// Removes the connecting timeout
+    pub fn with_no_connecting_timeout(mut self) -> ConnectionTimeouts {
+        self.connecting_timeout = None;
+        self
+    }
+
+    /// Removes the write timeout
+    pub fn with_no_write_timeout(mut self) -> ConnectionTimeouts {
+        self.write_timeout = None;
+        self
+    }
+
+    /// Removes the read timeout
+    pub fn with_no_read_timeout(mut self) -> ConnectionTimeouts {
+        self.read_timeout = None;
+        self
+    }
+}
+
+/// Sets each of the timeouts to `DEFAULT_TIMEOUT`
+impl Default for ConnectionTimeouts {
+    fn default() -> ConnectionTimeouts {
+        ConnectionTimeouts::uniform(DEFAULT_TIMEOUT)
+    }
+}
+
+impl ConnectionTimeoutsBuilder {
+    /// Sets the connecting timeout
+    pub fn connecting(mut self, timeout: Duration) -> Self {
+        self.timeouts.connecting_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the read timeout
+    pub fn read(mut self, timeout: Duration) -> Self {
+        self.timeouts.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the write timeout
+    pub fn write(mut self, timeout: Duration) -> Self {
+        self.timeouts.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Builds the timeouts
+    pub fn build(self) -> ConnectionTimeouts {
+        self.timeouts
+    }
+}
+
+/// Awaits a future within the given timeout,
+/// or awaits it with no limit if there is none
+pub(crate) async fn optional_timeout<F>(duration: Option<Duration>, future: F)
+    -> Result<F::Output, Elapsed>
+where
+    F: Future
+{
+    match duration {
+        Some(duration) => timeout(duration, future).await,
+        None => Ok(future.await)
+    }
+}
+
+/// Awaits a handshake future, reporting
+/// its start and its outcome to the observer
+/// along with the time it has taken, if any
+pub(crate) async fn observed<F, T, E>(observer: Option<&dyn Observer<E>>, future: F)
+    -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>
+{
+    let observer = match observer {
+        Some(observer) => observer,
+        None => return future.await
+    };
+
+    observer.on_connect_start();
+    let started = Instant::now();
+    let result = future.await;
+
+    match &result {
+        Ok(_) => observer.on_connect_success(started.elapsed()),
+        Err(e) => observer.on_connect_error(e, started.elapsed())
+    }
+
+    result
+}
+
+/// Shuts down a stream (flushing it first)
+/// within the given timeout, so that a slow proxy
+/// cannot make the shutdown hang indefinitely.
+/// Fails with `io::ErrorKind::TimedOut`
+/// if the timeout is reached
+pub(crate) async fn shutdown_with_timeout<W>(stream: &mut W, duration: Duration)
+    -> io::Result<()>
+where
+    W: AsyncWrite + Unpin
+{
+    match timeout(duration, stream.shutdown()).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "shutdown timeout reached"))
+    }
+}
+
+/// Represents the phase in which a timeout
+/// has been reached, telling a proxy server
+/// that has not accepted the bytes written to it
+/// from one that has not replied
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    /// Connecting, bounded by the connecting timeout
+    /// (for instance, resolving a domain name
+    /// destination locally)
+    Connect,
+    /// Writing to the proxy server, which
+    /// may mean that the server is not reading
+    /// (so that its receive window is full)
+    Write,
+    /// Reading from the proxy server,
+    /// which has not replied in time
+    Read,
+    /// A stage of the handshake bounded by a timeout
+    /// of its own as a whole (for instance, the
+    /// `Username/Password` subnegotiation, bounded
+    /// by the authentication timeout)
+    Handshake
+}
+
+impl fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeoutPhase::Connect => f.write_str("connect timed out"),
+            TimeoutPhase::Write => f.write_str("write timed out (proxy not reading)"),
+            TimeoutPhase::Read => f.write_str("read timed out (proxy not replying)"),
+            TimeoutPhase::Handshake => f.write_str("handshake timed out")
         }
     }
 }
 
+/// Represents the category of an error of the
+/// proxy clients (see `category` of their error kinds),
+/// telling a proxy server that works but has not
+/// connected to a destination (so that the destination
+/// is to be skipped) from one that is broken
+/// (so that the server is to be retired)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCategory {
+    /// The server works, but has denied the request
+    /// (by its rules, or as not supported) or has been
+    /// unable to reach the destination, or the
+    /// destination cannot be resolved
+    PolicyDenied,
+    /// The server cannot be talked to (an I/O error,
+    /// the server being unreachable or closing the
+    /// connection) or reports a failure of its own
+    NetworkError,
+    /// The server does not speak the protocol as expected
+    /// (a bad reply or another proxy protocol), or the
+    /// request cannot be expressed in it
+    ProtocolError,
+    /// A timeout has been reached
+    Timeout,
+    /// The authentication (or the identification)
+    /// has failed or cannot be performed
+    AuthFailure
+}
+
+/// Reads what the destination sends first (for
+/// instance, the banner of an SMTP, an FTP or an SSH
+/// server), so that the service reached through the
+/// tunnel can be checked. It stops at the end of the
+/// first line (returning the bytes read along with it),
+/// at the end of the stream or once `max` bytes are read
+pub(crate) async fn read_initial<R>(stream: &mut R, max: usize)
+    -> io::Result<Vec<u8>>
+where
+    R: AsyncRead + Unpin
+{
+    let mut initial = vec![0; max];
+    let mut filled = 0;
+
+    while filled < max {
+        let read_bytes = stream.read(&mut initial[filled..]).await?;
+        if read_bytes == 0 {
+            break
+        }
+
+        filled += read_bytes;
+        if initial[filled - read_bytes..filled].contains(&b'\n') {
+            break
+        }
+    }
+
+    initial.truncate(filled);
+    Ok(initial)
+}
+
+/// Keeps the kind of the error a poll of the
+/// underlying stream of a proxy stream has returned
+/// (if any) in `last_error`, passing the poll on
+pub(crate) fn record_error<T>(last_error: &mut Option<io::ErrorKind>, poll: Poll<io::Result<T>>)
+    -> Poll<io::Result<T>>
+{
+    if let Poll::Ready(Err(e)) = &poll {
+        *last_error = Some(e.kind());
+    }
+
+    poll
+}
+
+/// Reads from a stream within the given timeout
+/// (with no limit if there is none), failing with
+/// `io::ErrorKind::TimedOut` if it is reached
+pub(crate) async fn read_timed<R>(stream: &mut R, buf: &mut [u8], duration: Option<Duration>)
+    -> io::Result<usize>
+where
+    R: AsyncRead + Unpin
+{
+    match optional_timeout(duration, stream.read(buf)).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "read timeout reached"))
+    }
+}
+
+/// Writes to a stream within the given timeout
+/// (with no limit if there is none), failing with
+/// `io::ErrorKind::TimedOut` if it is reached
+pub(crate) async fn write_timed<W>(stream: &mut W, buf: &[u8], duration: Option<Duration>)
+    -> io::Result<usize>
+where
+    W: AsyncWrite + Unpin
+{
+    match optional_timeout(duration, stream.write(buf)).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "write timeout reached"))
+    }
+}
+
+/// Checks whether a tcp stream is still alive
+/// with a non-blocking peek of a single byte:
+/// the peer having closed the connection (the peek
+/// returns 0 bytes) or an error means it is dead,
+/// while no data or pending data means it is alive
+pub(crate) fn is_alive(stream: &mut TcpStream) -> bool {
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    let mut buf = [0];
+
+    match stream.poll_peek(&mut cx, &mut buf) {
+        Poll::Ready(Ok(0)) | Poll::Ready(Err(_)) => false,
+        Poll::Ready(Ok(_)) | Poll::Pending => true
+    }
+}
+
+/// The functions of a waker doing
+/// nothing at all when woken (or cloned
+/// into another such waker, or dropped)
+const NOOP_WAKER_VTABLE: RawWakerVTable = RawWakerVTable::new(
+    |_| RawWaker::new(std::ptr::null(), &NOOP_WAKER_VTABLE),
+    |_| {},
+    |_| {},
+    |_| {}
+);
+
+/// Creates a waker doing nothing when woken,
+/// which polls a future (or a stream) once
+/// with no task to be notified at all
+fn noop_waker() -> Waker {
+    let raw = RawWaker::new(std::ptr::null(), &NOOP_WAKER_VTABLE);
+
+    // Safety: the functions of the vtable
+    // never access the (null) data pointer
+    unsafe { Waker::from_raw(raw) }
+}
+
+/// The maximal number of bytes
+/// dumped by `trace_bytes`
+#[cfg(feature = "debug-bytes")]
+const TRACED_BYTES_LIMIT: usize = 64;
+
+/// Dumps the bytes sent to (or received from) a proxy
+/// server as hex at the TRACE level (the `debug-bytes`
+/// feature), truncated to `TRACED_BYTES_LIMIT` bytes
+pub(crate) fn trace_bytes(direction: &str, bytes: &[u8]) {
+    trace_redacted_bytes(direction, bytes, 0..0)
+}
+
+/// Dumps the bytes just as `trace_bytes` does,
+/// but with the `secret` bytes (for instance, a password)
+/// replaced with asterisks, so that secrets never get
+/// into logs
+#[cfg(feature = "debug-bytes")]
+pub(crate) fn trace_redacted_bytes(direction: &str, bytes: &[u8], secret: Range<usize>) {
+    use std::fmt::Write;
+
+    if !log::log_enabled!(log::Level::Trace) {
+        return
+    }
+
+    let mut dump = String::with_capacity(3 * TRACED_BYTES_LIMIT);
+    for (i, byte) in bytes.iter().take(TRACED_BYTES_LIMIT).enumerate() {
+        if i > 0 {
+            dump.push(' ');
+        }
+
+        match secret.contains(&i) {
+            true => dump.push_str("**"),
+            false => { let _ = write!(dump, "{:02x}", byte); }
+        }
+    }
+
+    if bytes.len() > TRACED_BYTES_LIMIT {
+        dump.push_str(" ..");
+    }
+
+    log::trace!("{} {} bytes: {}", direction, bytes.len(), dump);
+}
+
+/// Does nothing without the `debug-bytes` feature
+#[cfg(not(feature = "debug-bytes"))]
+#[inline]
+pub(crate) fn trace_redacted_bytes(_: &str, _: &[u8], _: Range<usize>) {}
+
 /// Parses connection timeouts in format
 /// "connection_timeout:read_timeout:write_timeout"
 /// where all timeouts are values represent milliseconds
-/// duration as u64
+/// duration as u64, with `0` meaning no timeout
 impl FromStr for ConnectionTimeouts {
     type Err = ();
 
@@ -67,11 +530,17 @@ impl FromStr for ConnectionTimeouts {
         );
 
         // Converting the parsed values
-        // into the approrpiate durations
-        Ok(ConnectionTimeouts::new(
-            Duration::from_millis(ct),
-            Duration::from_millis(rt),
-            Duration::from_millis(wt)
-        ))
+        // into the approrpiate durations,
+        // where zero means no timeout
+        let duration = |millis| match millis {
+            0 => None,
+            millis => Some(Duration::from_millis(millis))
+        };
+
+        Ok(ConnectionTimeouts {
+            connecting_timeout: duration(ct),
+            write_timeout: duration(wt),
+            read_timeout: duration(rt)
+        })
     }
 }
\ No newline at end of file
@@ -6,7 +6,7 @@
 //! 
 //! # Example
 //! 
-//! ```
+//! ```no_run
 //! use async_proxy::clients::socks4::no_ident::Socks4NoIdent;
 //! use async_proxy::general::ConnectionTimeouts;
 //! use async_proxy::proxy::ProxyConstructor;
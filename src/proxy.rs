@@ -1,3 +1,17 @@
+use crate::general::{optional_timeout, ConnectionTimeouts, IOStream};
+use bytes::{Buf, BufMut};
+use core::task::{Context, Poll};
+use futures_util::future::poll_fn;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
 /// A general trait that represents
 /// something that constructs a proxy stream,
 /// something, where we can write to and read from
@@ -16,8 +30,23 @@ pub trait ProxyConstructor {
     /// Takes ownership of an existant stream,
     /// establishes a proxixied connection on the stream
     /// and returns the proxy stream if the connection was
-    /// successful, unless an error
-    async fn connect(&mut self, stream: Self::Stream) -> Result<Self::ProxyStream, Self::ErrorKind>
+    /// successful, unless an error.
+    ///
+    /// The constructor is only borrowed, so that
+    /// a configured one can be shared (for instance,
+    /// in an `Arc`) and connected with from many
+    /// tasks simultaneously.
+    ///
+    /// # Cancellation safety
+    ///
+    /// Dropping the returned future (for instance, when
+    /// it loses a `tokio::select!` race) in the middle of
+    /// the handshake drops the stream as well, since it is
+    /// owned by the future, so that a stream with a partially
+    /// written handshake can never be reused by mistake.
+    /// Clients that need the stream back on cancellation
+    /// provide a `connect_with_cancel` variant
+    async fn connect(&self, stream: Self::Stream) -> Result<Self::ProxyStream, Self::ErrorKind>
     where
         Self: Sized;
 
@@ -26,4 +55,502 @@ pub trait ProxyConstructor {
         buf: &mut Vec<u8>,
         stream: &mut Self::Stream,
     ) -> Result<usize, Self::ErrorKind>;
+
+    /// Wraps the constructor into the one which `connect`
+    /// maps the proxy stream with `f` right after it has
+    /// been connected (for instance, into a framed codec
+    /// or a TLS client stream)
+    fn map<F, T>(self, f: F) -> MappedConstructor<Self, F>
+    where
+        Self: Sized,
+        F: Fn(Self::ProxyStream) -> T,
+    {
+        MappedConstructor { inner: self, f }
+    }
+}
+
+/// The proxy constructor returned by `ProxyConstructor::map`,
+/// which maps the proxy stream of the inner constructor
+pub struct MappedConstructor<C, F> {
+    /// The constructor the connection
+    /// is established with
+    inner: C,
+    /// The function the proxy
+    /// stream is mapped with
+    f: F,
+}
+
+impl<C, F> MappedConstructor<C, F> {
+    /// Returns a shared reference
+    /// to the inner constructor
+    pub fn get_ref(&self) -> &C {
+        &self.inner
+    }
+
+    /// Returns the inner constructor,
+    /// dropping the mapping function
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+}
+
+#[async_trait::async_trait]
+impl<C, F, T> ProxyConstructor for MappedConstructor<C, F>
+where
+    C: ProxyConstructor + Sync,
+    F: Fn(C::ProxyStream) -> T + Send + Sync,
+    T: Send,
+{
+    type Stream = C::Stream;
+    type ProxyStream = T;
+    type ErrorKind = C::ErrorKind;
+
+    async fn connect(&self, stream: Self::Stream) -> Result<Self::ProxyStream, Self::ErrorKind> {
+        self.inner.connect(stream).await.map(&self.f)
+    }
+
+    async fn send_payload(
+        &self,
+        buf: &mut Vec<u8>,
+        stream: &mut Self::Stream,
+    ) -> Result<usize, Self::ErrorKind> {
+        self.inner.send_payload(buf, stream).await
+    }
+}
+
+/// Represents an error of a connection
+/// attempt that can be cancelled
+#[derive(Debug)]
+pub enum CancelError<S, E> {
+    /// The handshake has failed
+    /// with the proxy client's error
+    Failed(E),
+    /// The attempt has been cancelled,
+    /// the stream is given back
+    Cancelled(S),
+}
+
+/// Represents the failure of a single
+/// proxy server attempted by `connect_via_any`
+#[derive(Debug)]
+pub enum AttemptError<E> {
+    /// The proxy server cannot be connected to
+    /// (the connection is refused, the server is
+    /// unreachable or the connecting timeout
+    /// has been reached)
+    Unreachable(io::Error),
+    /// The handshake with the proxy server
+    /// has failed with the proxy client's error
+    Handshake(E),
+}
+
+/// Represents the error of `connect_via_any`:
+/// the failures of all the proxy servers
+/// attempted, in the order of the attempts
+#[derive(Debug)]
+pub struct ProxiesFailed<E> {
+    /// The proxy servers along with
+    /// the failure of each of them
+    pub attempts: Vec<(SocketAddr, AttemptError<E>)>,
+}
+
+/// Attempts the proxy servers in order, connecting
+/// to each of them (bounded by the connecting timeout)
+/// and performing the handshake of the constructor,
+/// and returns the proxy stream of the first one
+/// the handshake succeeds with, unless the failures
+/// of all of them.
+///
+/// The constructor is shared among the attempts,
+/// so that the proxy servers are supposed to be
+/// interchangeable (for instance, a pool of them)
+pub async fn connect_via_any<C>(
+    proxies: &[SocketAddr],
+    constructor: &C,
+    timeouts: &ConnectionTimeouts,
+) -> Result<C::ProxyStream, ProxiesFailed<C::ErrorKind>>
+where
+    C: ProxyConstructor<Stream = TcpStream>,
+{
+    let mut attempts = Vec::with_capacity(proxies.len());
+
+    for &proxy in proxies {
+        // Connecting to the proxy server, where reaching
+        // the connecting timeout is an I/O error as well
+        let future = TcpStream::connect(proxy);
+        let stream = match optional_timeout(timeouts.connecting_timeout, future).await {
+            Ok(Ok(stream)) => stream,
+            Ok(Err(e)) => {
+                attempts.push((proxy, AttemptError::Unreachable(e)));
+                continue;
+            }
+            Err(_) => {
+                let e = io::Error::new(io::ErrorKind::TimedOut, "connecting timeout reached");
+                attempts.push((proxy, AttemptError::Unreachable(e)));
+                continue;
+            }
+        };
+
+        // Stopping at the first succeeded handshake
+        match constructor.connect(stream).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => attempts.push((proxy, AttemptError::Handshake(e))),
+        }
+    }
+
+    Err(ProxiesFailed { attempts })
+}
+
+/// Copies the bytes between the two streams in both
+/// directions at once (for instance, between an accepted
+/// client and the proxy stream to its destination), and
+/// returns the number of bytes copied from `a` to `b`
+/// and from `b` to `a`.
+///
+/// Once a stream reaches the end, the other one is
+/// shut down for writing, so that the end is passed on,
+/// and the relay is over when both directions are.
+/// An I/O error on either stream ends it at once
+pub async fn relay<A, B>(a: A, b: B) -> io::Result<(u64, u64)>
+where
+    A: IOStream,
+    B: IOStream,
+{
+    let (mut a_read, mut a_write) = tokio::io::split(a);
+    let (mut b_read, mut b_write) = tokio::io::split(b);
+
+    let mut a_to_b = Box::pin(pump(&mut a_read, &mut b_write));
+    let mut b_to_a = Box::pin(pump(&mut b_read, &mut a_write));
+    let (mut sent, mut received) = (None, None);
+
+    // Polling both directions until both are over,
+    // stopping at the first error of either of them
+    poll_fn(|cx| {
+        if sent.is_none() {
+            if let Poll::Ready(n) = a_to_b.as_mut().poll(cx) {
+                sent = Some(n?);
+            }
+        }
+        if received.is_none() {
+            if let Poll::Ready(n) = b_to_a.as_mut().poll(cx) {
+                received = Some(n?);
+            }
+        }
+
+        match (sent, received) {
+            (Some(sent), Some(received)) => Poll::Ready(Ok((sent, received))),
+            _ => Poll::Pending,
+        }
+    })
+    .await
+}
+
+/// Copies the bytes from `reader` to `writer` until
+/// the end of `reader`, and shuts `writer` down then
+async fn pump<R, W>(reader: &mut R, writer: &mut W) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let copied = tokio::io::copy(reader, writer).await?;
+    writer.shutdown().await?;
+
+    Ok(copied)
+}
+
+impl<E: fmt::Display> fmt::Display for AttemptError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AttemptError::Unreachable(e) => write!(f, "proxy server is unreachable: {}", e),
+            AttemptError::Handshake(e) => write!(f, "handshake failed: {}", e),
+        }
+    }
+}
+
+/// Lists the failure of each proxy server
+/// as `address (failure)`, separated with commas
+impl<E: fmt::Display> fmt::Display for ProxiesFailed<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.attempts.is_empty() {
+            return f.write_str("no proxy servers to attempt");
+        }
+
+        f.write_str("all proxy servers failed: ")?;
+        for (i, (proxy, e)) in self.attempts.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{} ({})", proxy, e)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for AttemptError<E> {}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for ProxiesFailed<E> {}
+
+/// Represents an address that a proxy server
+/// has bound on its side, as reported in
+/// the server's reply to a request
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BoundAddr {
+    /// Represents an IPv4 address
+    Ipv4(Ipv4Addr),
+    /// Represents an IPv6 address
+    Ipv6(Ipv6Addr),
+    /// Represents a domain name
+    Domain(String),
+}
+
+/// Represents an endpoint (an address and a port)
+/// that a proxy server has bound on its side,
+/// regardless of the proxy protocol
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BoundEndpoint {
+    /// The bound address
+    pub addr: BoundAddr,
+    /// The bound port
+    pub port: u16,
+}
+
+impl BoundEndpoint {
+    pub fn new(addr: BoundAddr, port: u16) -> BoundEndpoint {
+        BoundEndpoint { addr, port }
+    }
+
+    /// Returns the endpoint with an unspecified bound
+    /// address (0.0.0.0 or ::) substituted with `server`,
+    /// the address of the proxy server connected to.
+    ///
+    /// The servers that bind on all of their interfaces
+    /// report the wildcard address, which means their own
+    /// address, so that the endpoint is only reachable
+    /// (for instance, by the peer of a BIND request or
+    /// with the datagrams of a UDP association) once
+    /// the address is substituted. The bound port
+    /// is kept as it is
+    pub fn substitute_unspecified(self, server: IpAddr) -> BoundEndpoint {
+        let unspecified = match &self.addr {
+            BoundAddr::Ipv4(addr) => addr.is_unspecified(),
+            BoundAddr::Ipv6(addr) => addr.is_unspecified(),
+            BoundAddr::Domain(_) => false,
+        };
+
+        match (unspecified, server) {
+            (true, IpAddr::V4(server)) => BoundEndpoint::new(BoundAddr::Ipv4(server), self.port),
+            (true, IpAddr::V6(server)) => BoundEndpoint::new(BoundAddr::Ipv6(server), self.port),
+            (false, _) => self,
+        }
+    }
+}
+
+impl From<SocketAddr> for BoundEndpoint {
+    fn from(addr: SocketAddr) -> BoundEndpoint {
+        match addr {
+            SocketAddr::V4(addr) => BoundEndpoint::new(BoundAddr::Ipv4(*addr.ip()), addr.port()),
+            SocketAddr::V6(addr) => BoundEndpoint::new(BoundAddr::Ipv6(*addr.ip()), addr.port()),
+        }
+    }
+}
+
+impl fmt::Display for BoundAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BoundAddr::Ipv4(addr) => write!(f, "{}", addr),
+            BoundAddr::Ipv6(addr) => write!(f, "{}", addr),
+            BoundAddr::Domain(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Formats the endpoint as `address:port`,
+/// with an IPv6 address enclosed in brackets
+impl fmt::Display for BoundEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.addr {
+            BoundAddr::Ipv6(addr) => write!(f, "[{}]:{}", addr, self.port),
+            addr => write!(f, "{}:{}", addr, self.port),
+        }
+    }
+}
+
+/// Represents the owned read half of a proxy
+/// stream, returned by `into_split` of the proxy
+/// streams operating on a `TcpStream`, so that
+/// the tunnel can be read from another task
+pub struct ReadHalf {
+    /// The read half of the
+    /// underlying tcp stream
+    wrapped_half: OwnedReadHalf,
+    /// The bytes of the tunnel read by the client
+    /// along with the reply of the server, which
+    /// precede anything the half can return
+    buffered: Vec<u8>,
+    /// The position of the first
+    /// buffered byte not returned yet
+    position: usize,
+}
+
+/// Represents the owned write half of a proxy
+/// stream, returned by `into_split` of the proxy
+/// streams operating on a `TcpStream`, so that
+/// the tunnel can be written to from another task
+pub struct WriteHalf {
+    /// The write half of the
+    /// underlying tcp stream
+    wrapped_half: OwnedWriteHalf,
+}
+
+/// Splits the tcp stream of a proxy stream
+/// into the owned halves, where the read half
+/// returns the buffered bytes (starting at
+/// `position`) first
+pub(crate) fn split(
+    stream: TcpStream,
+    buffered: Vec<u8>,
+    position: usize,
+) -> (ReadHalf, WriteHalf) {
+    let (read_half, write_half) = stream.into_split();
+
+    let read_half = ReadHalf {
+        wrapped_half: read_half,
+        buffered,
+        position,
+    };
+
+    (
+        read_half,
+        WriteHalf {
+            wrapped_half: write_half,
+        },
+    )
+}
+
+impl ReadHalf {
+    /// Returns a shared reference to the
+    /// read half of the underlying tcp stream
+    pub fn get_ref(&self) -> &OwnedReadHalf {
+        &self.wrapped_half
+    }
+}
+
+impl WriteHalf {
+    /// Returns a shared reference to the
+    /// write half of the underlying tcp stream
+    pub fn get_ref(&self) -> &OwnedWriteHalf {
+        &self.wrapped_half
+    }
+}
+
+impl AsyncRead for ReadHalf {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = Pin::into_inner(self);
+
+        // Handing the buffered bytes first,
+        // since they precede anything the
+        // half can return
+        if this.position < this.buffered.len() {
+            let buffered = &this.buffered[this.position..];
+            let len = buffered.len().min(buf.len());
+            buf[..len].copy_from_slice(&buffered[..len]);
+            this.position += len;
+
+            return Poll::Ready(Ok(len));
+        }
+
+        Pin::new(&mut this.wrapped_half).poll_read(cx, buf)
+    }
+
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [MaybeUninit<u8>]) -> bool {
+        self.wrapped_half.prepare_uninitialized_buffer(buf)
+    }
+
+    fn poll_read_buf<B: BufMut>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut B,
+    ) -> Poll<io::Result<usize>>
+    where
+        Self: Sized,
+    {
+        let this = Pin::into_inner(self);
+
+        // Handing the buffered bytes first,
+        // just as `poll_read` does
+        if this.position < this.buffered.len() {
+            let buffered = &this.buffered[this.position..];
+            let len = buffered.len().min(buf.remaining_mut());
+            buf.put_slice(&buffered[..len]);
+            this.position += len;
+
+            return Poll::Ready(Ok(len));
+        }
+
+        Pin::new(&mut this.wrapped_half).poll_read_buf(cx, buf)
+    }
+}
+
+impl AsyncWrite for WriteHalf {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let half = &mut Pin::into_inner(self).wrapped_half;
+        Pin::new(half).poll_write(cx, buf)
+    }
+
+    fn poll_write_buf<B: Buf>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut B,
+    ) -> Poll<Result<usize, io::Error>>
+    where
+        Self: Sized,
+    {
+        let half = &mut Pin::into_inner(self).wrapped_half;
+        Pin::new(half).poll_write_buf(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let half = &mut Pin::into_inner(self).wrapped_half;
+        Pin::new(half).poll_flush(cx)
+    }
+
+    /// Shuts down the write direction
+    /// of the underlying tcp stream only
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let half = &mut Pin::into_inner(self).wrapped_half;
+        Pin::new(half).poll_shutdown(cx)
+    }
+}
+
+/// Prints the peer address of the underlying
+/// tcp stream only, never the transferred data
+impl fmt::Debug for ReadHalf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stream: &TcpStream = self.wrapped_half.as_ref();
+
+        f.debug_struct("ReadHalf")
+            .field("peer_addr", &stream.peer_addr().ok())
+            .finish()
+    }
+}
+
+/// Prints the peer address of the underlying
+/// tcp stream only, never the transferred data
+impl fmt::Debug for WriteHalf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stream: &TcpStream = self.wrapped_half.as_ref();
+
+        f.debug_struct("WriteHalf")
+            .field("peer_addr", &stream.peer_addr().ok())
+            .finish()
+    }
 }
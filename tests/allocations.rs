@@ -0,0 +1,76 @@
+mod common;
+
+use async_proxy::clients::socks5::{Destination, TcpNoAuth};
+use async_proxy::general::ConnectionTimeouts;
+use common::allocations::{allocations, CountingAllocator};
+use common::{Exchange, MockServer, SOCKS5_SUCCESS};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::mpsc;
+use std::thread;
+use tokio::net::TcpStream;
+use tokio::runtime::Builder;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Starts a mock server on its own thread, so that
+/// its allocations are not counted, playing the
+/// Socks5 script for a request of `request_len` bytes
+fn spawn_server(request_len: usize) -> SocketAddr {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut runtime = Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async move {
+            let script = vec![
+                Exchange::new(3, &[0x05, 0x00]),
+                Exchange::new(request_len, &SOCKS5_SUCCESS),
+            ];
+            sender
+                .send(MockServer::spawn_repeating(script).await)
+                .unwrap();
+            std::future::pending::<()>().await
+        })
+    });
+
+    receiver.recv().unwrap()
+}
+
+#[tokio::test]
+async fn ipv4_handshake_with_no_auth_allocates_nothing() {
+    let addr = spawn_server(10);
+
+    // Each tokio timer allocates an entry of
+    // its own, so that the timeouts are left out
+    let proxy = TcpNoAuth::new(
+        Destination::Ipv4Addr(Ipv4Addr::LOCALHOST),
+        80,
+        ConnectionTimeouts::none(),
+    );
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let before = allocations();
+    let stream = proxy.connect_unboxed(stream).await.unwrap();
+    assert_eq!(allocations(), before);
+
+    assert_eq!(stream.bound_endpoint().port, 0);
+}
+
+#[tokio::test]
+async fn ipv6_handshake_with_no_auth_allocates_nothing() {
+    let addr = spawn_server(22);
+
+    let proxy = TcpNoAuth::new(
+        Destination::Ipv6Addr(Ipv6Addr::LOCALHOST),
+        80,
+        ConnectionTimeouts::none(),
+    );
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let before = allocations();
+    proxy.connect_unboxed(stream).await.unwrap();
+    assert_eq!(allocations(), before);
+}
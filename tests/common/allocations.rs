@@ -0,0 +1,34 @@
+//! The allocator counting the allocations made
+//! by each thread, which the allocation tests and
+//! benchmarks install as the global one with
+//! `#[global_allocator]`
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// The global allocator counting allocations
+/// made by the current thread, so that the
+/// allocations of the mock server (running
+/// on its own thread) are not taken into account
+pub struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Returns the number of allocations
+/// the current thread has made so far
+pub fn allocations() -> usize {
+    ALLOCATIONS.with(|count| count.get())
+}
@@ -0,0 +1,153 @@
+//! Utilities shared by the integration tests,
+//! such as an in-process mock proxy server
+//! which lets the proxy clients be tested
+//! without a network
+#![allow(dead_code)]
+
+pub mod allocations;
+
+use async_proxy::general::ConnectionTimeouts;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio::time::delay_for;
+
+/// A single step of a mock server script:
+/// the server reads exactly `expect` bytes
+/// from a client and then replies with `reply`,
+/// optionally after a pause
+#[derive(Clone)]
+pub struct Exchange {
+    pub expect: usize,
+    pub reply: Vec<u8>,
+    pub pause: Option<Duration>,
+}
+
+impl Exchange {
+    pub fn new(expect: usize, reply: &[u8]) -> Exchange {
+        Exchange {
+            expect,
+            reply: reply.to_vec(),
+            pause: None,
+        }
+    }
+
+    /// Makes the server pause before replying,
+    /// so that the reply arrives separately
+    /// from the one of the previous step
+    pub fn after(mut self, pause: Duration) -> Exchange {
+        self.pause = Some(pause);
+        self
+    }
+}
+
+/// An in-process mock proxy server that
+/// accepts a single connection and plays
+/// a script over it, capturing the bytes
+/// it has received from a client
+pub struct MockServer {
+    addr: SocketAddr,
+    handle: JoinHandle<Vec<Vec<u8>>>,
+}
+
+impl MockServer {
+    /// Binds the server on a random local port
+    /// and starts waiting for a client
+    pub async fn spawn(script: Vec<Exchange>) -> MockServer {
+        MockServer::spawn_on("127.0.0.1:0", script).await
+    }
+
+    /// Binds the server on the given address
+    /// (for instance, the IPv6 loopback one)
+    /// and starts waiting for a client
+    pub async fn spawn_on(addr: &str, script: Vec<Exchange>) -> MockServer {
+        let mut listener = TcpListener::bind(addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            play(stream, &script).await
+        });
+
+        MockServer { addr, handle }
+    }
+
+    /// Binds a server on a random local port,
+    /// which plays the same script over every
+    /// connection it accepts, until the runtime
+    /// it is spawned on is shut down
+    pub async fn spawn_repeating(script: Vec<Exchange>) -> SocketAddr {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let script = script.clone();
+                tokio::spawn(async move { play(stream, &script).await });
+            }
+        });
+
+        addr
+    }
+
+    /// Returns the address the server listens on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Connects a tcp stream to the server
+    pub async fn connect(&self) -> TcpStream {
+        TcpStream::connect(self.addr).await.unwrap()
+    }
+
+    /// Waits for the script to be played and returns
+    /// the bytes received during each exchange
+    pub async fn received(self) -> Vec<Vec<u8>> {
+        self.handle.await.unwrap()
+    }
+}
+
+/// Plays a script over a connection and
+/// returns the bytes received during each exchange
+async fn play(mut stream: TcpStream, script: &[Exchange]) -> Vec<Vec<u8>> {
+    let mut received = Vec::with_capacity(script.len());
+
+    for exchange in script {
+        // Stopping the script as soon as
+        // the client stops talking the protocol
+        let mut buf = vec![0; exchange.expect];
+        if stream.read_exact(&mut buf).await.is_err() {
+            break;
+        }
+        received.push(buf);
+
+        if let Some(pause) = exchange.pause {
+            delay_for(pause).await;
+        }
+
+        if stream.write_all(&exchange.reply).await.is_err() {
+            break;
+        }
+    }
+
+    received
+}
+
+/// Short timeouts suitable for tests
+pub fn timeouts() -> ConnectionTimeouts {
+    ConnectionTimeouts::new(
+        Duration::from_secs(2),
+        Duration::from_secs(2),
+        Duration::from_secs(2),
+    )
+}
+
+/// A successful Socks5 reply
+/// with the bound address 0.0.0.0:0
+pub const SOCKS5_SUCCESS: [u8; 10] = [0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+
+/// A successful Socks4 reply
+/// with the bound address 0.0.0.0:0
+pub const SOCKS4_SUCCESS: [u8; 8] = [0x00, 0x5a, 0, 0, 0, 0, 0, 0];
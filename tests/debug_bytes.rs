@@ -0,0 +1,57 @@
+mod common;
+
+use async_proxy::clients::socks5::{Destination, TcpNoAuth};
+use async_proxy::proxy::ProxyConstructor;
+use common::{timeouts, Exchange, MockServer, SOCKS5_SUCCESS};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+
+/// A logger collecting the
+/// messages of the crate
+struct Collector(Mutex<Vec<String>>);
+
+impl Log for Collector {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Trace
+    }
+
+    fn log(&self, record: &Record) {
+        if record.target().starts_with("async_proxy") {
+            let message = record.args().to_string();
+            self.0.lock().unwrap().push(message);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static COLLECTOR: Collector = Collector(Mutex::new(Vec::new()));
+
+#[tokio::test]
+async fn handshake_bytes_are_dumped_with_the_password_redacted() {
+    log::set_logger(&COLLECTOR).unwrap();
+    log::set_max_level(LevelFilter::Trace);
+
+    // The credentials user:pass
+    let server = MockServer::spawn(vec![
+        Exchange::new(4, &[0x05, 0x02]),
+        Exchange::new(11, &[0x01, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let mut proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    proxy.with_authentication("user", "pass");
+    proxy.connect(server.connect().await).await.unwrap();
+
+    let messages = COLLECTOR.0.lock().unwrap();
+    assert!(messages.contains(&"sent 4 bytes: 05 02 02 00".to_owned()));
+    assert!(messages.contains(&"received 2 bytes: 05 02".to_owned()));
+
+    // The username is dumped, while the password is not
+    assert!(messages.contains(&"sent 11 bytes: 01 04 75 73 65 72 04 ** ** ** **".to_owned()));
+    assert!(messages
+        .iter()
+        .all(|message| !message.contains("70 61 73 73")));
+}
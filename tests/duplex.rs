@@ -0,0 +1,270 @@
+mod common;
+
+use async_proxy::clients::http::{self, HttpConnect};
+use async_proxy::clients::socks5::no_auth::ErrorKind;
+use async_proxy::clients::socks5::{Destination, TcpNoAuth};
+use async_proxy::general::{ConnectionTimeouts, TimeoutPhase};
+use async_proxy::proxy::{relay, ProxyConstructor};
+use common::{timeouts, SOCKS5_SUCCESS};
+use std::io;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+#[tokio::test]
+async fn socks5_is_spoken_over_an_in_memory_duplex() {
+    // The pipe holds a single byte, so that every
+    // read of the client returns a byte at most
+    let (client, mut server) = duplex(1);
+
+    // Playing the proxy server on the other half
+    let handle = tokio::spawn(async move {
+        let mut greeting = [0; 3];
+        server.read_exact(&mut greeting).await.unwrap();
+        server.write_all(&[0x05, 0x00]).await.unwrap();
+
+        let mut request = [0; 10];
+        server.read_exact(&mut request).await.unwrap();
+        server.write_all(&SOCKS5_SUCCESS).await.unwrap();
+        server.write_all(b"hello").await.unwrap();
+
+        request
+    });
+
+    let proxy: TcpNoAuth<DuplexStream> =
+        TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let mut stream = proxy.connect(client).await.unwrap();
+
+    let mut buf = [0; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+
+    let request = handle.await.unwrap();
+    assert_eq!(request, [0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1, 0, 80]);
+}
+
+#[tokio::test]
+async fn socks5_failure_reply_is_scripted_over_a_duplex() {
+    let (client, mut server) = duplex(64);
+
+    tokio::spawn(async move {
+        let mut greeting = [0; 3];
+        server.read_exact(&mut greeting).await.unwrap();
+        server.write_all(&[0x05, 0x00]).await.unwrap();
+
+        let mut request = [0; 10];
+        server.read_exact(&mut request).await.unwrap();
+        let mut reply = SOCKS5_SUCCESS;
+        reply[1] = 0x05;
+        server.write_all(&reply).await.unwrap();
+    });
+
+    let proxy: TcpNoAuth<DuplexStream> =
+        TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let e = proxy.connect(client).await.err().unwrap();
+    assert_eq!(e.reply_code(), Some(0x05));
+    assert!(matches!(e.into_kind(), ErrorKind::ConnectionRefused));
+}
+
+#[tokio::test]
+async fn http_connect_is_spoken_over_an_in_memory_duplex() {
+    let (client, mut server) = duplex(1024);
+
+    let handle = tokio::spawn(async move {
+        let expected = b"CONNECT 127.0.0.1:80 HTTP/1.1\r\nHost: 127.0.0.1:80\r\n\r\n";
+        let mut request = vec![0; expected.len()];
+        server.read_exact(&mut request).await.unwrap();
+        server
+            .write_all(b"HTTP/1.1 200 Connection established\r\n\r\nhello")
+            .await
+            .unwrap();
+
+        request == expected[..]
+    });
+
+    let proxy: HttpConnect<DuplexStream> =
+        HttpConnect::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let mut stream = proxy.connect(client).await.unwrap();
+
+    let mut buf = [0; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+    assert!(handle.await.unwrap());
+}
+
+/// Plays an HTTP proxy server writing `response`
+/// in two parts split at `split`, with a pause in
+/// between, so that they are read separately
+fn spawn_split_response(mut server: DuplexStream, response: &'static [u8], split: usize) {
+    tokio::spawn(async move {
+        let request = b"CONNECT 127.0.0.1:80 HTTP/1.1\r\nHost: 127.0.0.1:80\r\n\r\n";
+        let mut buf = vec![0; request.len()];
+        server.read_exact(&mut buf).await.unwrap();
+
+        server.write_all(&response[..split]).await.unwrap();
+        tokio::time::delay_for(Duration::from_millis(5)).await;
+        server.write_all(&response[split..]).await.unwrap();
+        tokio::time::delay_for(Duration::from_secs(1)).await;
+    });
+}
+
+#[tokio::test]
+async fn http_response_split_at_any_byte_is_read_up_to_the_headers() {
+    let response = b"HTTP/1.1 200 Connection established\r\nVia: 1.1 proxy\r\n\r\nhello";
+    let proxy: HttpConnect<DuplexStream> =
+        HttpConnect::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+
+    // The bytes of the tunnel are never lost, wherever
+    // the response is split (in particular, inside the
+    // terminator or right after it)
+    for split in 1..response.len() {
+        let (client, server) = duplex(256);
+        spawn_split_response(server, response, split);
+
+        let mut stream = proxy.connect(client).await.unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello", "split at {}", split);
+    }
+
+    // Each read returning a single byte,
+    // the tunnel is not read into at all
+    let (client, mut server) = duplex(1);
+    tokio::spawn(async move {
+        let mut request = vec![0; 53];
+        server.read_exact(&mut request).await.unwrap();
+        server.write_all(response).await.unwrap();
+    });
+
+    let stream = proxy.connect(client).await.unwrap();
+    let (mut inner, buffered) = stream.into_inner_with_buffered();
+    assert!(buffered.is_empty());
+
+    let mut buf = [0; 5];
+    inner.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[tokio::test]
+async fn http_failure_with_a_split_body_is_classified() {
+    let response = b"HTTP/1.1 403 Forbidden\r\nContent-Length: 6\r\n\r\ndenied";
+    let proxy: HttpConnect<DuplexStream> =
+        HttpConnect::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+
+    // Split inside the status line, inside
+    // the terminator and inside the body
+    for &split in &[11, 44, 47] {
+        let (client, server) = duplex(256);
+        spawn_split_response(server, response, split);
+
+        let e = proxy.connect(client).await.err().unwrap();
+        assert!(
+            matches!(e, http::ErrorKind::RequestFailed(403)),
+            "split at {}",
+            split
+        );
+    }
+}
+
+#[tokio::test]
+async fn write_timeout_is_told_from_read_timeout() {
+    let timeouts = ConnectionTimeouts::new(
+        Duration::from_secs(2),
+        Duration::from_millis(100),
+        Duration::from_millis(100),
+    );
+    let proxy: TcpNoAuth<DuplexStream> =
+        TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts);
+
+    // The server never reads, so that the
+    // greeting does not fit into the pipe
+    let (client, _server) = duplex(1);
+    let e = proxy.connect(client).await.err().unwrap().into_kind();
+    assert!(matches!(
+        e,
+        ErrorKind::OperationTimeoutReached(TimeoutPhase::Write)
+    ));
+    assert_eq!(e.to_string(), "write timed out (proxy not reading)");
+
+    // The server reads the greeting, but never replies
+    let (client, mut server) = duplex(64);
+    tokio::spawn(async move {
+        let mut greeting = [0; 3];
+        server.read_exact(&mut greeting).await.unwrap();
+        tokio::time::delay_for(Duration::from_secs(1)).await;
+    });
+    let e = proxy.connect(client).await.err().unwrap().into_kind();
+    assert!(matches!(
+        e,
+        ErrorKind::OperationTimeoutReached(TimeoutPhase::Read)
+    ));
+    assert_eq!(e.to_string(), "read timed out (proxy not replying)");
+}
+
+#[tokio::test]
+async fn timeouts_bound_the_tunnel_after_the_handshake() {
+    let timeouts = ConnectionTimeouts::new(
+        Duration::from_secs(2),
+        Duration::from_millis(100),
+        Duration::from_millis(100),
+    );
+    let proxy: TcpNoAuth<DuplexStream> =
+        TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts);
+
+    // The server completes the handshake, after
+    // which it neither sends nor reads anything
+    let (client, mut server) = duplex(16);
+    let handle = tokio::spawn(async move {
+        let mut greeting = [0; 3];
+        server.read_exact(&mut greeting).await.unwrap();
+        server.write_all(&[0x05, 0x00]).await.unwrap();
+
+        let mut request = [0; 10];
+        server.read_exact(&mut request).await.unwrap();
+        server.write_all(&SOCKS5_SUCCESS).await.unwrap();
+        tokio::time::delay_for(Duration::from_secs(1)).await;
+    });
+
+    let mut stream = proxy.connect(client).await.unwrap();
+    assert_eq!(
+        stream.timeouts().read_timeout,
+        Some(Duration::from_millis(100))
+    );
+
+    let mut buf = [0; 16];
+    let e = stream.read_timed(&mut buf).await.unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+
+    // The pipe takes 16 bytes, the
+    // next write finds it full
+    assert_eq!(stream.write_timed(&[0; 16]).await.unwrap(), 16);
+    let e = stream.write_timed(&[0; 16]).await.unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn relay_copies_both_ways_until_both_ends() {
+    let (a, mut client) = duplex(4);
+    let (b, mut destination) = duplex(4);
+    let handle = tokio::spawn(relay(a, b));
+
+    // The end of the client is passed on to the
+    // destination, which keeps replying afterwards
+    client.write_all(b"ping").await.unwrap();
+    client.shutdown().await.unwrap();
+
+    let mut request = Vec::new();
+    destination.read_to_end(&mut request).await.unwrap();
+    assert_eq!(request, b"ping");
+
+    destination.write_all(b"pong pong").await.unwrap();
+    destination.shutdown().await.unwrap();
+
+    let mut reply = Vec::new();
+    client.read_to_end(&mut reply).await.unwrap();
+    assert_eq!(reply, b"pong pong");
+
+    assert_eq!(handle.await.unwrap().unwrap(), (4, 9));
+}
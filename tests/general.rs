@@ -0,0 +1,99 @@
+use async_proxy::general::ConnectionTimeouts;
+use std::time::Duration;
+
+#[test]
+fn timeouts_are_parsed_in_order_with_zero_meaning_none() {
+    // Connecting, read and write timeouts
+    let timeouts: ConnectionTimeouts = "1000:0:3000".parse().unwrap();
+
+    assert_eq!(
+        timeouts.connecting_timeout,
+        Some(Duration::from_millis(1000))
+    );
+    assert_eq!(timeouts.read_timeout, None);
+    assert_eq!(timeouts.write_timeout, Some(Duration::from_millis(3000)));
+}
+
+#[test]
+fn error_kinds_are_categorized() {
+    use async_proxy::clients::{http, socks4, socks5};
+    use async_proxy::general::{ErrorCategory, TimeoutPhase};
+    use std::io;
+
+    // A request refused (or a destination unreachable)
+    // is told from a server which is broken
+    assert_eq!(
+        socks5::no_auth::ErrorKind::HostUnreachable.category(),
+        ErrorCategory::PolicyDenied
+    );
+    assert_eq!(
+        socks5::no_auth::ErrorKind::SocksServerFailure.category(),
+        ErrorCategory::NetworkError
+    );
+    assert_eq!(
+        socks5::no_auth::ErrorKind::WrongProxyType.category(),
+        ErrorCategory::ProtocolError
+    );
+    assert_eq!(
+        socks5::no_auth::ErrorKind::AuthenticationFailed.category(),
+        ErrorCategory::AuthFailure
+    );
+    assert_eq!(
+        socks5::no_auth::ErrorKind::OperationTimeoutReached(TimeoutPhase::Read).category(),
+        ErrorCategory::Timeout
+    );
+
+    assert_eq!(
+        socks4::ErrorKind::RequestDenied.category(),
+        ErrorCategory::PolicyDenied
+    );
+    let unreachable = io::Error::from(io::ErrorKind::ConnectionRefused);
+    assert_eq!(
+        socks4::ErrorKind::ProxyUnreachable(unreachable).category(),
+        ErrorCategory::NetworkError
+    );
+    assert_eq!(
+        socks4::ErrorKind::BadIdent.category(),
+        ErrorCategory::AuthFailure
+    );
+
+    // The HTTP statuses are categorized by their class,
+    // while a gateway one means an unreachable destination
+    for &(status, category) in &[
+        (403, ErrorCategory::PolicyDenied),
+        (502, ErrorCategory::PolicyDenied),
+        (504, ErrorCategory::PolicyDenied),
+        (500, ErrorCategory::NetworkError),
+        (503, ErrorCategory::NetworkError),
+        (302, ErrorCategory::ProtocolError),
+    ] {
+        assert_eq!(http::ErrorKind::RequestFailed(status).category(), category);
+    }
+    assert_eq!(
+        http::ErrorKind::ProxyAuthenticationRequired.category(),
+        ErrorCategory::AuthFailure
+    );
+}
+
+#[test]
+fn timeouts_are_built_by_name_over_the_defaults() {
+    use async_proxy::general::DEFAULT_TIMEOUT;
+
+    let timeouts = ConnectionTimeouts::builder()
+        .connecting(Duration::from_secs(1))
+        .read(Duration::from_secs(3))
+        .build();
+    assert_eq!(timeouts.connecting_timeout, Some(Duration::from_secs(1)));
+    assert_eq!(timeouts.read_timeout, Some(Duration::from_secs(3)));
+    assert_eq!(timeouts.write_timeout, Some(DEFAULT_TIMEOUT));
+
+    let timeouts = ConnectionTimeouts::uniform(Duration::from_secs(8));
+    assert_eq!(timeouts.connecting_timeout, Some(Duration::from_secs(8)));
+    assert_eq!(timeouts.write_timeout, Some(Duration::from_secs(8)));
+    assert_eq!(timeouts.read_timeout, Some(Duration::from_secs(8)));
+
+    let timeouts = ConnectionTimeouts::default();
+    assert_eq!(timeouts.connecting_timeout, Some(Duration::from_secs(10)));
+    assert_eq!(timeouts.write_timeout, Some(Duration::from_secs(10)));
+    assert_eq!(timeouts.read_timeout, Some(Duration::from_secs(10)));
+}
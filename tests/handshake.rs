@@ -0,0 +1,119 @@
+use async_proxy::clients::socks5::handshake::{Socks5Handshake, Step};
+use async_proxy::clients::socks5::no_auth::ErrorKind;
+use async_proxy::clients::socks5::{Command, Destination};
+use async_proxy::proxy::BoundAddr;
+use std::net::Ipv4Addr;
+
+/// Expects the step to be a write
+/// and returns the bytes to be written
+fn written(step: Step) -> Vec<u8> {
+    match step {
+        Step::Write(payload) => payload,
+        step => panic!("expected a write, got {:?}", step),
+    }
+}
+
+#[test]
+fn handshake_with_credentials_is_driven_without_io() {
+    let mut handshake = Socks5Handshake::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80);
+    handshake.with_credentials("user", "pass");
+
+    // The greeting offers both methods, the strongest first
+    assert_eq!(written(handshake.step(&[])), [0x05, 0x02, 0x02, 0x00]);
+    assert!(matches!(handshake.step(&[]), Step::NeedMore(2)));
+
+    // The method selection arrives in parts
+    assert!(matches!(handshake.step(&[0x05]), Step::NeedMore(1)));
+    assert_eq!(
+        written(handshake.step(&[0x02])),
+        b"\x01\x04user\x04pass".to_vec()
+    );
+
+    // The request follows the accepted credentials
+    assert_eq!(
+        written(handshake.step(&[0x01, 0x00])),
+        [0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1, 0, 80]
+    );
+
+    // The reply with a domain name bound address
+    // is asked for exactly as many bytes as it has
+    assert!(matches!(handshake.step(&[]), Step::NeedMore(4)));
+    assert!(matches!(
+        handshake.step(&[0x05, 0x00, 0x00, 0x03]),
+        Step::NeedMore(1)
+    ));
+    assert!(matches!(handshake.step(&[4]), Step::NeedMore(6)));
+    match handshake.step(b"host\x00\x50") {
+        Step::Done { bound, auth_method } => {
+            assert_eq!(bound.addr, BoundAddr::Domain("host".to_owned()));
+            assert_eq!(bound.port, 80);
+            assert_eq!(auth_method, 0x02);
+        }
+        step => panic!("expected the handshake to be done, got {:?}", step),
+    }
+    assert_eq!(handshake.reply_code(), Some(0x00));
+    assert!(handshake.buffered().is_empty());
+}
+
+#[test]
+fn delegated_method_is_left_to_the_caller() {
+    let mut handshake = Socks5Handshake::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80);
+    handshake.with_methods(&[0x80]);
+    handshake.with_command(Command::TcpPortBinding);
+
+    assert_eq!(written(handshake.step(&[])), [0x05, 0x01, 0x80]);
+    assert!(matches!(
+        handshake.step(&[0x05, 0x80]),
+        Step::Authenticate(0x80)
+    ));
+
+    // The destination may be replaced until the request is written
+    handshake.set_destination(Destination::Ipv4Addr(Ipv4Addr::new(192, 0, 2, 7)));
+    assert_eq!(
+        written(handshake.step(&[])),
+        [0x05, 0x02, 0x00, 0x01, 192, 0, 2, 7, 0, 80]
+    );
+
+    // A failure reply keeps its code
+    let reply = [0x05, 0x05, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+    assert!(matches!(
+        handshake.step(&reply),
+        Step::Error(ErrorKind::ConnectionRefused)
+    ));
+    assert_eq!(handshake.reply_code(), Some(0x05));
+}
+
+#[test]
+fn bad_greeting_replies_are_told_apart() {
+    let destination = Destination::Ipv4Addr(Ipv4Addr::LOCALHOST);
+
+    // The server has hung up without a reply
+    let mut handshake = Socks5Handshake::new(destination.clone(), 80);
+    handshake.step(&[]);
+    assert!(matches!(
+        handshake.closed(),
+        ErrorKind::ConnectionClosedByProxy
+    ));
+
+    // The server has hung up in the middle of the reply
+    let mut handshake = Socks5Handshake::new(destination.clone(), 80);
+    handshake.step(&[]);
+    handshake.step(&[0x05]);
+    assert!(matches!(handshake.closed(), ErrorKind::BadBuffer));
+
+    // An HTTP server is detected by the first byte
+    let mut handshake = Socks5Handshake::new(destination.clone(), 80);
+    handshake.step(&[]);
+    assert!(matches!(
+        handshake.step(b"H"),
+        Step::Error(ErrorKind::WrongProxyType)
+    ));
+
+    // The selected method has not been offered
+    let mut handshake = Socks5Handshake::new(destination, 80);
+    handshake.step(&[]);
+    assert!(matches!(
+        handshake.step(&[0x05, 0x02]),
+        Step::Error(ErrorKind::BadBuffer)
+    ));
+}
@@ -0,0 +1,246 @@
+mod common;
+
+use async_proxy::clients::http::connect::HttpConnectStream;
+use async_proxy::clients::http::{ErrorKind, HttpConnect};
+use async_proxy::clients::socks5::{Destination, TcpNoAuth};
+use async_proxy::proxy::ProxyConstructor;
+use bytes::buf::BufExt;
+use bytes::{Buf, BytesMut};
+use common::{timeouts, Exchange, MockServer, SOCKS5_SUCCESS};
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+/// The CONNECT request to 127.0.0.1:80
+const REQUEST: &[u8] = b"CONNECT 127.0.0.1:80 HTTP/1.1\r\nHost: 127.0.0.1:80\r\n\r\n";
+
+#[tokio::test]
+async fn tunnel_is_established_with_basic_authentication() {
+    let request = b"CONNECT 127.0.0.1:80 HTTP/1.1\r\nHost: 127.0.0.1:80\r\n\
+                    Proxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n";
+
+    // The first bytes of the tunnel
+    // follow the response headers
+    let server = MockServer::spawn(vec![
+        Exchange::new(
+            request.len(),
+            b"HTTP/1.1 200 Connection established\r\n\r\nhello",
+        ),
+        Exchange::new(1, &[]),
+    ])
+    .await;
+
+    let mut proxy = HttpConnect::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    proxy.with_authentication("user", "pass");
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+
+    let mut buf = [0; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+
+    drop(stream);
+    assert_eq!(server.received().await[0], &request[..]);
+}
+
+#[tokio::test]
+async fn failure_statuses_are_classified() {
+    let server = MockServer::spawn(vec![Exchange::new(
+        REQUEST.len(),
+        b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n",
+    )])
+    .await;
+
+    let proxy = HttpConnect::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(
+        result,
+        Err(ErrorKind::ProxyAuthenticationRequired)
+    ));
+
+    let server = MockServer::spawn(vec![Exchange::new(
+        REQUEST.len(),
+        b"HTTP/1.0 403 Forbidden\r\n\r\n",
+    )])
+    .await;
+
+    let proxy = HttpConnect::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(result, Err(ErrorKind::RequestFailed(403))));
+}
+
+#[tokio::test]
+async fn reply_of_a_socks_server_is_detected() {
+    // The replies of a Socks4 and a Socks5 proxy server
+    for reply in [&[0x00, 0x5b, 0, 0, 0, 0, 0, 0][..], &[0x05, 0xFF][..]] {
+        let server = MockServer::spawn(vec![Exchange::new(REQUEST.len(), reply)]).await;
+
+        let proxy = HttpConnect::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+        let result = proxy.connect(server.connect().await).await;
+        assert!(matches!(result, Err(ErrorKind::WrongProxyType)));
+    }
+}
+
+#[tokio::test]
+async fn buffers_are_read_and_written_through_the_tunnel() {
+    let server = MockServer::spawn(vec![
+        Exchange::new(REQUEST.len(), b"HTTP/1.1 200 OK\r\n\r\nhello"),
+        Exchange::new(6, b" back"),
+        Exchange::new(1, &[]),
+    ])
+    .await;
+
+    let proxy = HttpConnect::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+
+    // The buffered bytes come first
+    let mut buf = BytesMut::with_capacity(64);
+    stream.read_buf(&mut buf).await.unwrap();
+    assert_eq!(&buf[..], b"hello");
+
+    // Writing a buffer made of two slices
+    let mut chain = BufExt::chain(&b"wor"[..], &b"ld!"[..]);
+    while chain.has_remaining() {
+        stream.write_buf(&mut chain).await.unwrap();
+    }
+
+    buf.clear();
+    while buf.len() < 5 {
+        stream.read_buf(&mut buf).await.unwrap();
+    }
+    assert_eq!(&buf[..], b" back");
+
+    drop(stream);
+    assert_eq!(server.received().await[1], b"world!");
+}
+
+#[tokio::test]
+async fn split_read_half_returns_the_buffered_bytes_first() {
+    let server = MockServer::spawn(vec![
+        Exchange::new(REQUEST.len(), b"HTTP/1.1 200 OK\r\n\r\nhello"),
+        Exchange::new(4, b" back"),
+        Exchange::new(1, &[]),
+    ])
+    .await;
+
+    let proxy = HttpConnect::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+    let (mut read_half, mut write_half) = stream.into_split();
+
+    let writer = tokio::spawn(async move {
+        write_half.write_all(b"ping").await.unwrap();
+        write_half
+    });
+
+    let mut buf = [0; 10];
+    read_half.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello back");
+
+    drop((read_half, writer.await.unwrap()));
+    assert_eq!(server.received().await[1], b"ping");
+}
+
+#[tokio::test]
+async fn initial_data_is_written_after_the_response() {
+    let request = b"GET / HTTP/1.1\r\n\r\n";
+
+    let server = MockServer::spawn(vec![
+        Exchange::new(REQUEST.len(), b"HTTP/1.1 200 OK\r\n\r\n"),
+        Exchange::new(request.len(), &[]),
+    ])
+    .await;
+
+    let proxy = HttpConnect::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    proxy
+        .connect_with_initial_data(server.connect().await, request)
+        .await
+        .unwrap();
+
+    let received = server.received().await;
+    assert_eq!(received[1], request);
+}
+
+#[tokio::test]
+async fn inner_stream_is_returned_with_the_unread_buffered_bytes() {
+    let server = MockServer::spawn(vec![
+        Exchange::new(REQUEST.len(), b"HTTP/1.1 200 OK\r\n\r\nhello"),
+        Exchange::new(4, b" back"),
+    ])
+    .await;
+
+    let proxy = HttpConnect::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+
+    let mut buf = [0; 2];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"he");
+
+    // The rest of the buffered bytes is not lost
+    let (mut inner, buffered) = stream.into_inner_with_buffered();
+    assert_eq!(buffered, b"llo");
+
+    inner.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 5];
+    inner.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b" back");
+}
+
+#[tokio::test]
+async fn lines_are_read_starting_with_the_buffered_bytes() {
+    // The first line of the tunnel follows
+    // the response headers in the same read
+    let server = MockServer::spawn(vec![
+        Exchange::new(REQUEST.len(), b"HTTP/1.1 200 OK\r\n\r\n220 ready\r\n2"),
+        Exchange::new(0, b"21 bye\r\n").after(Duration::from_millis(50)),
+    ])
+    .await;
+
+    let proxy = HttpConnect::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+
+    let mut line = String::new();
+    stream.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "220 ready\r\n");
+
+    // A line split across the buffered
+    // bytes and the stream is read whole
+    line.clear();
+    stream.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "221 bye\r\n");
+}
+
+#[tokio::test]
+async fn socks5_is_spoken_through_the_tunnel() {
+    // The server plays the HTTP proxy server
+    // and the Socks5 one it tunnels to
+    let server = MockServer::spawn(vec![
+        Exchange::new(
+            REQUEST.len(),
+            b"HTTP/1.1 200 Connection established\r\n\r\n",
+        ),
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+        Exchange::new(5, b"hello"),
+    ])
+    .await;
+
+    let http = HttpConnect::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let tunnel = http.connect(server.connect().await).await.unwrap();
+
+    let socks5: TcpNoAuth<HttpConnectStream> = TcpNoAuth::new(
+        Destination::Ipv4Addr(Ipv4Addr::new(192, 0, 2, 7)),
+        443,
+        timeouts(),
+    );
+    let mut stream = socks5.connect(tunnel).await.unwrap();
+
+    stream.write_all(b"hello").await.unwrap();
+    let mut buf = [0; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+
+    let received = server.received().await;
+    assert_eq!(
+        received[2],
+        [0x05, 0x01, 0x00, 0x01, 192, 0, 2, 7, 0x01, 0xBB]
+    );
+}
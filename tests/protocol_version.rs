@@ -0,0 +1,45 @@
+mod common;
+
+use async_proxy::clients::socks5::handshake::{Socks5Handshake, Step};
+use async_proxy::clients::socks5::no_auth::{Error, ErrorKind};
+use async_proxy::clients::socks5::{Destination, TcpNoAuth};
+use async_proxy::proxy::ProxyConstructor;
+use common::{timeouts, Exchange, MockServer};
+use std::net::Ipv4Addr;
+
+#[tokio::test]
+async fn custom_version_is_written_and_expected() {
+    let reply = [0x06, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x06, 0x00]),
+        Exchange::new(10, &reply),
+    ])
+    .await;
+
+    let mut proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    proxy.with_protocol_version(0x06);
+    proxy.connect(server.connect().await).await.unwrap();
+
+    // Both the greeting and the request carry the version
+    let received = server.received().await;
+    assert_eq!(received[0], [0x06, 0x01, 0x00]);
+    assert_eq!(received[1][0], 0x06);
+
+    // A standard reply is rejected then
+    let server = MockServer::spawn(vec![Exchange::new(3, &[0x05, 0x00])]).await;
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(
+        result.map_err(Error::into_kind),
+        Err(ErrorKind::BadBuffer)
+    ));
+}
+
+#[test]
+fn custom_version_of_the_state_machine() {
+    let mut handshake = Socks5Handshake::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80);
+    handshake.with_protocol_version(0x00);
+
+    // The version is not taken for a Socks4 reply
+    assert!(matches!(handshake.step(&[]), Step::Write(greeting) if greeting[0] == 0x00));
+    assert!(matches!(handshake.step(&[0x00, 0x00]), Step::Write(_)));
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use async_proxy::clients::socks5::no_auth::{Error, ErrorKind};
+use async_proxy::clients::socks5::resolve::Resolve;
+use async_proxy::clients::socks5::Destination;
+use async_proxy::proxy::ProxyConstructor;
+use common::{timeouts, Exchange, MockServer};
+use std::net::{IpAddr, Ipv4Addr};
+use tokio::net::TcpStream;
+
+#[tokio::test]
+async fn name_is_resolved_through_the_proxy() {
+    // BND.ADDR 93.184.216.34, BND.PORT is ignored
+    let reply = [0x05, 0x00, 0x00, 0x01, 93, 184, 216, 34, 0, 0];
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(1 + 1 + 1 + 1 + 1 + 11 + 2, &reply),
+    ])
+    .await;
+
+    let resolve: Resolve<TcpStream> =
+        Resolve::new(Destination::hostname("example.com").unwrap(), timeouts());
+    let addr = resolve.connect(server.connect().await).await.unwrap();
+    assert_eq!(addr, IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)));
+
+    // The RESOLVE command (0xF0) with the domain name
+    let received = server.received().await;
+    assert_eq!(received[1][..5], [0x05, 0xF0, 0x00, 0x03, 11]);
+    assert_eq!(&received[1][5..16], b"example.com");
+}
+
+#[tokio::test]
+async fn rejected_command_is_not_supported() {
+    // "Command not supported" (0x07)
+    let reply = [0x05, 0x07, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(1 + 1 + 1 + 1 + 1 + 11 + 2, &reply),
+    ])
+    .await;
+
+    let resolve: Resolve<TcpStream> =
+        Resolve::new(Destination::hostname("example.com").unwrap(), timeouts());
+    let result = resolve.connect(server.connect().await).await;
+    assert!(matches!(
+        result.map_err(Error::into_kind),
+        Err(ErrorKind::NotSupported)
+    ));
+}
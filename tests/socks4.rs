@@ -0,0 +1,480 @@
+mod common;
+
+use async_proxy::clients::socks4::general::StrParsingError;
+use async_proxy::clients::socks4::{ErrorKind, Socks4Bind, Socks4General, Socks4NoIdent};
+use async_proxy::general::TimeoutPhase;
+use async_proxy::proxy::{BoundAddr, ProxyConstructor};
+use common::{timeouts, Exchange, MockServer, SOCKS4_SUCCESS};
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[tokio::test]
+async fn reply_with_bad_version_is_rejected() {
+    // A reply with the "request granted" status byte
+    // in place, but with a version byte that belongs
+    // to no known proxy protocol
+    let mut reply = SOCKS4_SUCCESS;
+    reply[0] = 0x01;
+
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80);
+
+    let server = MockServer::spawn(vec![Exchange::new(9, &reply)]).await;
+    let proxy = Socks4NoIdent::new(dest_addr, timeouts());
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(result, Err(ErrorKind::BadBuffer)));
+
+    let server = MockServer::spawn(vec![Exchange::new(9, &reply)]).await;
+    let proxy = Socks4General::new(dest_addr, Cow::Borrowed(""), timeouts());
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(result, Err(ErrorKind::BadBuffer)));
+}
+
+#[tokio::test]
+async fn reply_of_another_proxy_type_is_detected() {
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80);
+
+    // The replies of a Socks5 and an HTTP proxy server
+    for reply in [&[0x05, 0xFF][..], &b"HTTP/1.1 400 Bad Request\r\n\r\n"[..]] {
+        let server = MockServer::spawn(vec![Exchange::new(9, reply)]).await;
+        let proxy = Socks4NoIdent::new(dest_addr, timeouts());
+        let result = proxy.connect(server.connect().await).await;
+        assert!(matches!(result, Err(ErrorKind::WrongProxyType)));
+    }
+}
+
+#[tokio::test]
+async fn bound_port_and_ip_are_parsed_from_the_reply() {
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80);
+
+    // DSTPORT 8080 and DSTIP 10.0.0.1
+    let mut reply = SOCKS4_SUCCESS;
+    reply[2..4].copy_from_slice(&8080u16.to_be_bytes());
+    reply[4..8].copy_from_slice(&[10, 0, 0, 1]);
+
+    let server = MockServer::spawn(vec![Exchange::new(9, &reply)]).await;
+    let proxy = Socks4NoIdent::new(dest_addr, timeouts());
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+    assert_eq!(stream.bound_port(), 8080);
+    assert_eq!(stream.bound_ip(), Ipv4Addr::new(10, 0, 0, 1));
+
+    let server = MockServer::spawn(vec![Exchange::new(9, &reply)]).await;
+    let proxy = Socks4General::new(dest_addr, Cow::Borrowed(""), timeouts());
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+    assert_eq!(stream.bound_port(), 8080);
+    assert_eq!(stream.bound_ip(), Ipv4Addr::new(10, 0, 0, 1));
+}
+
+#[tokio::test]
+async fn reply_arriving_in_parts_is_read_in_full() {
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80);
+
+    let mut reply = SOCKS4_SUCCESS;
+    reply[2..4].copy_from_slice(&8080u16.to_be_bytes());
+    reply[4..8].copy_from_slice(&[10, 0, 0, 1]);
+    let script = || {
+        vec![
+            Exchange::new(9, &reply[..2]),
+            Exchange::new(0, &reply[2..]).after(Duration::from_millis(50)),
+        ]
+    };
+
+    let server = MockServer::spawn(script()).await;
+    let proxy = Socks4NoIdent::new(dest_addr, timeouts());
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+    assert_eq!(stream.bound_port(), 8080);
+    assert_eq!(stream.bound_ip(), Ipv4Addr::new(10, 0, 0, 1));
+
+    let server = MockServer::spawn(script()).await;
+    let proxy = Socks4General::new(dest_addr, Cow::Borrowed(""), timeouts());
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+    assert_eq!(stream.bound_port(), 8080);
+    assert_eq!(stream.bound_ip(), Ipv4Addr::new(10, 0, 0, 1));
+
+    // The server hangs up before the address is sent
+    let server = MockServer::spawn(vec![Exchange::new(9, &reply[..4])]).await;
+    let proxy = Socks4General::new(dest_addr, Cow::Borrowed(""), timeouts());
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(result, Err(ErrorKind::BadBuffer)));
+}
+
+#[tokio::test]
+async fn nodelay_is_restored_after_the_handshake() {
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80);
+
+    let server = MockServer::spawn(vec![Exchange::new(9, &SOCKS4_SUCCESS)]).await;
+    let proxy = Socks4NoIdent::new(dest_addr, timeouts());
+    let stream = proxy
+        .connect_with_nodelay(server.connect().await)
+        .await
+        .unwrap();
+    assert!(!stream.get_ref().nodelay().unwrap());
+
+    let server = MockServer::spawn(vec![Exchange::new(9, &SOCKS4_SUCCESS)]).await;
+    let stream = server.connect().await;
+    stream.set_nodelay(true).unwrap();
+    let proxy = Socks4General::new(dest_addr, Cow::Borrowed(""), timeouts());
+    let stream = proxy.connect_with_nodelay(stream).await.unwrap();
+    assert!(stream.get_ref().nodelay().unwrap());
+}
+
+#[tokio::test]
+async fn unboxed_connect_establishes_the_tunnel() {
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80);
+
+    let server = MockServer::spawn(vec![Exchange::new(9, &SOCKS4_SUCCESS)]).await;
+    let proxy = Socks4NoIdent::new(dest_addr, timeouts());
+    proxy.connect_unboxed(server.connect().await).await.unwrap();
+
+    let server = MockServer::spawn(vec![Exchange::new(14, &SOCKS4_SUCCESS)]).await;
+    let proxy = Socks4General::new(dest_addr, Cow::Borrowed("alice"), timeouts());
+    proxy.connect_unboxed(server.connect().await).await.unwrap();
+
+    let received = server.received().await;
+    assert_eq!(&received[0][8..], b"alice\0");
+}
+
+#[tokio::test]
+async fn data_sent_along_with_the_reply_reaches_the_caller() {
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80);
+
+    // The reply and the first tunnel data
+    // are written by the server at once
+    let mut coalesced = SOCKS4_SUCCESS.to_vec();
+    coalesced.extend_from_slice(b"payload");
+
+    let server = MockServer::spawn(vec![Exchange::new(9, &coalesced)]).await;
+    let proxy = Socks4NoIdent::new(dest_addr, timeouts());
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+    let mut buf = [0; 7];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"payload");
+
+    let server = MockServer::spawn(vec![Exchange::new(9, &coalesced)]).await;
+    let proxy = Socks4General::new(dest_addr, Cow::Borrowed(""), timeouts());
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+    let mut buf = [0; 7];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"payload");
+}
+
+#[tokio::test]
+async fn banner_sent_along_with_the_reply_is_read() {
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80);
+
+    // A banner-first service speaks right after
+    // the reply, which arrives in the same segment
+    let mut coalesced = SOCKS4_SUCCESS.to_vec();
+    coalesced.extend_from_slice(b"SSH-2.0-OpenSSH_8.9\r\n");
+
+    let server = MockServer::spawn(vec![Exchange::new(9, &coalesced)]).await;
+    let proxy = Socks4NoIdent::new(dest_addr, timeouts());
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+    let banner = stream.read_initial(512).await.unwrap();
+    assert_eq!(banner, b"SSH-2.0-OpenSSH_8.9\r\n");
+
+    let server = MockServer::spawn(vec![Exchange::new(9, &coalesced)]).await;
+    let proxy = Socks4General::new(dest_addr, Cow::Borrowed(""), timeouts());
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+    let banner = stream.read_initial(512).await.unwrap();
+    assert_eq!(banner, b"SSH-2.0-OpenSSH_8.9\r\n");
+}
+
+#[tokio::test]
+async fn inner_stream_is_returned_with_no_buffered_bytes() {
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80);
+
+    let mut coalesced = SOCKS4_SUCCESS.to_vec();
+    coalesced.extend_from_slice(b"payload");
+
+    let server = MockServer::spawn(vec![Exchange::new(9, &coalesced)]).await;
+    let proxy = Socks4NoIdent::new(dest_addr, timeouts());
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+    let (mut inner, buffered) = stream.into_inner_with_buffered();
+    assert!(buffered.is_empty());
+    let mut buf = [0; 7];
+    inner.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"payload");
+
+    let server = MockServer::spawn(vec![Exchange::new(9, &coalesced)]).await;
+    let proxy = Socks4General::new(dest_addr, Cow::Borrowed(""), timeouts());
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+    let (mut inner, buffered) = stream.into_inner_with_buffered();
+    assert!(buffered.is_empty());
+    let mut buf = [0; 7];
+    inner.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"payload");
+}
+
+#[tokio::test]
+async fn ident_is_sent_null_terminated() {
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80);
+
+    let server = MockServer::spawn(vec![Exchange::new(14, &SOCKS4_SUCCESS)]).await;
+    let proxy = Socks4General::new(dest_addr, Cow::Borrowed("alice"), timeouts());
+    proxy.connect(server.connect().await).await.unwrap();
+
+    let received = server.received().await;
+    assert_eq!(&received[0][8..], b"alice\0");
+}
+
+#[tokio::test]
+async fn empty_ident_is_sent_as_without_ident() {
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80);
+
+    // A trailing byte would be read by the second exchange,
+    // which sees the end of the stream instead
+    let script = || vec![Exchange::new(9, &SOCKS4_SUCCESS), Exchange::new(1, &[])];
+
+    let server = MockServer::spawn(script()).await;
+    let proxy = Socks4General::new(dest_addr, Cow::Owned(String::new()), timeouts());
+    proxy.connect(server.connect().await).await.unwrap();
+    let general = server.received().await;
+
+    let server = MockServer::spawn(script()).await;
+    let proxy = Socks4NoIdent::new(dest_addr, timeouts());
+    proxy.connect(server.connect().await).await.unwrap();
+    let no_ident = server.received().await;
+
+    assert_eq!(general, no_ident);
+    assert_eq!(general.len(), 1);
+    assert_eq!(&general[0][8..], b"\0");
+}
+
+#[tokio::test]
+async fn invalid_idents_are_rejected_before_the_request() {
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80);
+
+    // Longer than the default maximum (255 bytes)
+    let server = MockServer::spawn(vec![Exchange::new(1, &[])]).await;
+    let proxy = Socks4General::new(dest_addr, Cow::Owned("a".repeat(256)), timeouts());
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(result, Err(ErrorKind::IdentTooLong)));
+    assert!(server.received().await.iter().all(Vec::is_empty));
+
+    // Longer than the maximum set
+    let server = MockServer::spawn(vec![Exchange::new(1, &[])]).await;
+    let mut proxy = Socks4General::new(dest_addr, Cow::Borrowed("alice"), timeouts());
+    proxy.with_max_ident_len(4);
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(result, Err(ErrorKind::IdentTooLong)));
+    assert!(server.received().await.iter().all(Vec::is_empty));
+
+    // With an interior NULL byte
+    let server = MockServer::spawn(vec![Exchange::new(1, &[])]).await;
+    let proxy = Socks4General::new(dest_addr, Cow::Borrowed("al\0ice"), timeouts());
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(result, Err(ErrorKind::IdentContainsNul)));
+    assert!(server.received().await.iter().all(Vec::is_empty));
+}
+
+#[tokio::test]
+async fn ident_of_raw_bytes_is_sent_as_it_is() {
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80);
+
+    // The ident is not UTF-8
+    let ident: &[u8] = &[0xFF, 0xFE, 0x01];
+    let server = MockServer::spawn(vec![Exchange::new(8 + 3 + 1, &SOCKS4_SUCCESS)]).await;
+    let proxy = Socks4General::with_ident_bytes(dest_addr, Cow::Borrowed(ident), timeouts());
+    assert_eq!(proxy.ident_bytes(), ident);
+    proxy.connect(server.connect().await).await.unwrap();
+
+    let received = server.received().await;
+    assert_eq!(&received[0][8..], b"\xFF\xFE\x01\0");
+
+    // An interior NULL byte is still rejected
+    let server = MockServer::spawn(vec![Exchange::new(1, &[])]).await;
+    let proxy = Socks4General::with_ident_bytes(dest_addr, Cow::Borrowed(b"\xFF\0"), timeouts());
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(result, Err(ErrorKind::IdentContainsNul)));
+}
+
+#[test]
+fn constructors_are_parsed_with_try_from() {
+    let proxy = Socks4NoIdent::try_from("127.0.0.1:80 8000:8000:8000").unwrap();
+    assert_eq!(
+        proxy.dest_addr(),
+        SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80)
+    );
+
+    let proxy = Socks4General::try_from("127.0.0.1:80 alice 8000:8000:8000").unwrap();
+    assert_eq!(proxy.ident(), "alice");
+
+    // The errors of parsing are printable
+    let error = Socks4General::try_from("localhost:80 alice 8000:8000:8000")
+        .err()
+        .unwrap();
+    assert!(matches!(error, StrParsingError::InvalidAddr));
+    assert_eq!(error.to_string(), "invalid destination address");
+}
+
+#[tokio::test]
+async fn establish_dials_the_proxy_server() {
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80);
+
+    let server = MockServer::spawn(vec![Exchange::new(9, &SOCKS4_SUCCESS)]).await;
+    let proxy = Socks4NoIdent::new(dest_addr, timeouts());
+    proxy.establish(server.addr()).await.unwrap();
+    assert_eq!(server.received().await[0][..2], [0x04, 0x01]);
+
+    // A closed port is told apart from
+    // a handshake the server has rejected
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let closed_addr = listener.local_addr().unwrap();
+    drop(listener);
+
+    let result = proxy.establish(closed_addr).await;
+    assert!(matches!(result, Err(ErrorKind::ProxyUnreachable(_))));
+
+    let mut reply = SOCKS4_SUCCESS;
+    reply[1] = 0x5B;
+    let server = MockServer::spawn(vec![Exchange::new(9, &reply)]).await;
+    let result = proxy.establish(server.addr()).await;
+    assert!(matches!(result, Err(ErrorKind::RequestDenied)));
+}
+
+#[tokio::test]
+async fn establish_reaches_an_ipv6_proxy_server() {
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80);
+
+    let server = MockServer::spawn_on("[::1]:0", vec![Exchange::new(9, &SOCKS4_SUCCESS)]).await;
+    assert!(server.addr().is_ipv6());
+
+    let proxy = Socks4NoIdent::new(dest_addr, timeouts());
+    let proxy_addr = format!("[::1]:{}", server.addr().port());
+    proxy.establish(proxy_addr.as_str()).await.unwrap();
+    assert_eq!(server.received().await[0][..2], [0x04, 0x01]);
+}
+
+#[tokio::test]
+async fn bind_request_is_sent_and_the_destination_is_accepted() {
+    // The server listens on 10.0.0.1:5000
+    // and the destination 192.0.2.1:20 connects
+    // once it has learned the bound endpoint
+    let mut listening = SOCKS4_SUCCESS;
+    listening[2..4].copy_from_slice(&5000u16.to_be_bytes());
+    listening[4..8].copy_from_slice(&[10, 0, 0, 1]);
+    let mut connected = SOCKS4_SUCCESS;
+    connected[2..4].copy_from_slice(&20u16.to_be_bytes());
+    connected[4..8].copy_from_slice(&[192, 0, 2, 1]);
+
+    let server = MockServer::spawn(vec![
+        Exchange::new(12, &listening),
+        Exchange::new(0, &connected).after(Duration::from_millis(50)),
+    ])
+    .await;
+
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 1), 20);
+    let proxy = Socks4Bind::new(dest_addr, Cow::Borrowed("ftp"), timeouts());
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+    assert_eq!(stream.bound_endpoint().port, 5000);
+
+    // A specified address is kept as it is
+    let reachable = stream.reachable_bound_endpoint().unwrap();
+    assert_eq!(&reachable, stream.bound_endpoint());
+
+    let peer = stream.accept().await.unwrap();
+    assert_eq!(peer.addr, BoundAddr::Ipv4(Ipv4Addr::new(192, 0, 2, 1)));
+    assert_eq!(peer.port, 20);
+
+    // The second reply has already been consumed
+    assert!(matches!(
+        stream.accept().await,
+        Err(ErrorKind::NotSupported)
+    ));
+
+    // The BIND command (2) along with
+    // the destination and the ident
+    let received = server.received().await;
+    assert_eq!(received[0][..2], [0x04, 0x02]);
+    assert_eq!(received[0][2..8], [0, 20, 192, 0, 2, 1]);
+    assert_eq!(received[0][8..], *b"ftp\0");
+}
+
+#[tokio::test]
+async fn accept_waits_within_the_accept_timeout() {
+    let server = MockServer::spawn(vec![
+        Exchange::new(9, &SOCKS4_SUCCESS),
+        Exchange::new(1, &[]),
+    ])
+    .await;
+
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 20);
+    let mut proxy = Socks4Bind::new(dest_addr, Cow::Borrowed(""), timeouts());
+    assert_eq!(proxy.accept_timeout(), timeouts().read_timeout);
+    proxy.with_accept_timeout(Duration::from_millis(100));
+
+    // The unspecified address is the one of the server
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+    let reachable = stream.reachable_bound_endpoint().unwrap();
+    assert_eq!(reachable.addr, BoundAddr::Ipv4(Ipv4Addr::LOCALHOST));
+
+    assert!(matches!(
+        stream.accept().await,
+        Err(ErrorKind::OperationTimeoutReached(TimeoutPhase::Handshake))
+    ));
+
+    // Part of the reply may have been consumed by
+    // the attempt timed out, so that it is not retried
+    assert!(matches!(
+        stream.accept().await,
+        Err(ErrorKind::NotSupported)
+    ));
+}
+
+#[tokio::test]
+async fn reset_while_accepting_is_recorded() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // The server aborts the connection instead of
+    // sending the second reply (a zero linger makes
+    // closing send an RST)
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = [0; 9];
+        stream.read_exact(&mut buf).await.unwrap();
+        stream.write_all(&SOCKS4_SUCCESS).await.unwrap();
+
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+        stream.set_linger(Some(Duration::from_secs(0))).unwrap();
+    });
+
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 20);
+    let proxy = Socks4Bind::new(dest_addr, Cow::Borrowed(""), timeouts());
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = proxy.connect(stream).await.unwrap();
+
+    match stream.accept().await {
+        Err(ErrorKind::IOError(e)) => assert_eq!(e.kind(), io::ErrorKind::ConnectionReset),
+        result => panic!("expected a reset, got {:?}", result),
+    }
+    assert_eq!(
+        stream.last_error_kind(),
+        Some(io::ErrorKind::ConnectionReset)
+    );
+}
+
+#[tokio::test]
+async fn rejected_inbound_connection_is_not_accepted() {
+    let mut rejected = SOCKS4_SUCCESS;
+    rejected[1] = 0x5b;
+
+    let server = MockServer::spawn(vec![
+        Exchange::new(9, &SOCKS4_SUCCESS),
+        Exchange::new(0, &rejected).after(Duration::from_millis(50)),
+    ])
+    .await;
+
+    let dest_addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 20);
+    let proxy = Socks4Bind::new(dest_addr, Cow::Borrowed(""), timeouts());
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+    assert!(matches!(
+        stream.accept().await,
+        Err(ErrorKind::RequestDenied)
+    ));
+}
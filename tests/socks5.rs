@@ -0,0 +1,1687 @@
+mod common;
+
+use async_proxy::clients::socks5::auth::{AuthMethod, Gssapi, UserPass};
+use async_proxy::clients::socks5::no_auth::{
+    AuthenticationKind, BuildError, Error, ErrorKind, NotSupportedMethod, StrParsingError,
+    DEFAULT_MAX_REPLY_BYTES,
+};
+use async_proxy::clients::socks5::{
+    Command, Destination, DestinationError, Port, ResolutionPolicy, Socks5, TcpNoAuth,
+};
+use async_proxy::destination;
+use async_proxy::general::{
+    ConnectionTimeouts, IOStream, IpPreference, Observer, Resolver, TimeoutPhase,
+};
+use async_proxy::proxy::{
+    connect_via_any, AttemptError, BoundAddr, BoundEndpoint, CancelError, ProxyConstructor,
+};
+use common::{timeouts, Exchange, MockServer, SOCKS5_SUCCESS};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[tokio::test]
+async fn connect_request_to_ipv6_destination() {
+    let addr: Ipv6Addr = "2001:db8::1".parse().unwrap();
+    assert_eq!(Destination::Ipv6Addr(addr).len_as_buffer(), 16 + 1);
+
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(22, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv6Addr(addr), 8080, timeouts());
+    proxy.connect(server.connect().await).await.unwrap();
+
+    // Version, command, reserved byte, address type,
+    // the address and the port in the network byte order
+    let mut expected = vec![0x05, 0x01, 0x00, 0x04];
+    expected.extend_from_slice(&addr.octets());
+    expected.extend_from_slice(&8080u16.to_be_bytes());
+
+    let received = server.received().await;
+    assert_eq!(received[0], [0x05, 0x01, 0x00]);
+    assert_eq!(received[1], expected);
+}
+
+#[tokio::test]
+async fn every_reply_code_is_classified() {
+    for code in 0x00..=0xFFu8 {
+        let mut reply = SOCKS5_SUCCESS;
+        reply[1] = code;
+
+        let server = MockServer::spawn(vec![
+            Exchange::new(3, &[0x05, 0x00]),
+            Exchange::new(10, &reply),
+        ])
+        .await;
+
+        let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+        let result = proxy.connect(server.connect().await).await;
+
+        // The error carries the raw reply code
+        // and the destination of the attempt
+        if let Err(e) = &result {
+            assert_eq!(e.reply_code(), Some(code));
+            assert_eq!(e.destination(), "127.0.0.1");
+            assert_eq!(e.port(), 80);
+        }
+        let result = result.map_err(Error::into_kind);
+
+        // Only 0x00 means success, 0x01-0x08 are the errors
+        // defined by the RFC, anything else is a bad buffer
+        let classified = match code {
+            0x00 => result.is_ok(),
+            0x01 => matches!(result, Err(ErrorKind::SocksServerFailure)),
+            0x02 => matches!(result, Err(ErrorKind::RequestDenied)),
+            0x03 => matches!(result, Err(ErrorKind::NetworkUnreachable)),
+            0x04 => matches!(result, Err(ErrorKind::HostUnreachable)),
+            0x05 => matches!(result, Err(ErrorKind::ConnectionRefused)),
+            0x06 => matches!(result, Err(ErrorKind::TTLExpired)),
+            0x07 => matches!(result, Err(ErrorKind::NotSupported)),
+            0x08 => matches!(result, Err(ErrorKind::DestinationNotSupported)),
+            _ => matches!(result, Err(ErrorKind::BadBuffer)),
+        };
+
+        assert!(
+            classified,
+            "reply code {:#04x} got {:?}",
+            code,
+            result.err()
+        );
+    }
+}
+
+#[tokio::test]
+async fn reply_with_unknown_address_type_is_rejected() {
+    // The reply header names the address type 0x07,
+    // after which the server keeps the connection open
+    // without sending anything, so that reading any
+    // further would only end with the read timeout
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &[0x05, 0x00, 0x00, 0x07]),
+        Exchange::new(1, &[]),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let started = Instant::now();
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(
+        result.map_err(Error::into_kind),
+        Err(ErrorKind::BadBuffer)
+    ));
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn bound_domain_name_is_parsed() {
+    // A reply with the bound domain name
+    // "proxy.test" and the bound port 1080
+    let mut reply = vec![0x05, 0x00, 0x00, 0x03, 10];
+    reply.extend_from_slice(b"proxy.test");
+    reply.extend_from_slice(&1080u16.to_be_bytes());
+
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &reply),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+
+    let bound = stream.bound_endpoint();
+    assert_eq!(bound.addr, BoundAddr::Domain("proxy.test".to_owned()));
+    assert_eq!(bound.port, 1080);
+    assert_eq!(bound.to_string(), "proxy.test:1080");
+}
+
+#[tokio::test]
+async fn cancelled_connect_gives_the_stream_back() {
+    // The server never replies to the greeting,
+    // waiting for more data instead
+    let server = MockServer::spawn(vec![Exchange::new(3, &[]), Exchange::new(1, &[])]).await;
+    let stream = server.connect().await;
+    let local_addr = stream.local_addr().unwrap();
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let cancel = tokio::time::delay_for(Duration::from_millis(50));
+
+    match proxy.connect_with_cancel(stream, cancel).await {
+        Err(CancelError::Cancelled(stream)) => {
+            assert_eq!(stream.local_addr().unwrap(), local_addr)
+        }
+        _ => panic!("the connection attempt has not been cancelled"),
+    }
+}
+
+#[tokio::test]
+async fn stream_debug_prints_the_peer_address() {
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+
+    assert_eq!(
+        format!("{:?}", stream),
+        format!("TcpNoAuthStream {{ peer_addr: Some({}) }}", server.addr())
+    );
+}
+
+#[tokio::test]
+async fn selecting_a_method_not_offered_is_rejected() {
+    // Only "no authentication" is offered,
+    // while the server selects Username/Password
+    let server = MockServer::spawn(vec![Exchange::new(3, &[0x05, 0x02])]).await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(
+        result.map_err(Error::into_kind),
+        Err(ErrorKind::BadBuffer)
+    ));
+
+    // Only Username/Password is offered (by the typed
+    // method), while the server selects "no authentication"
+    let server = MockServer::spawn(vec![Exchange::new(3, &[0x05, 0x00])]).await;
+
+    let proxy: Socks5<UserPass> = Socks5::with_auth_method(
+        Destination::Ipv4Addr(Ipv4Addr::LOCALHOST),
+        80,
+        timeouts(),
+        UserPass::new("user", "pass"),
+    );
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(
+        result.map_err(Error::into_kind),
+        Err(ErrorKind::BadBuffer)
+    ));
+}
+
+#[tokio::test]
+async fn gssapi_selected_by_the_server_is_not_supported() {
+    let server = MockServer::spawn(vec![Exchange::new(3, &[0x05, 0x01])]).await;
+
+    let proxy: Socks5<Gssapi> = Socks5::with_auth_method(
+        Destination::Ipv4Addr(Ipv4Addr::LOCALHOST),
+        80,
+        timeouts(),
+        Gssapi,
+    );
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(
+        result.map_err(Error::into_kind),
+        Err(ErrorKind::Method(NotSupportedMethod::GssAPI))
+    ));
+
+    // Only GSSAPI is offered
+    assert_eq!(server.received().await[0], [0x05, 0x01, 0x01]);
+}
+
+/// Returns the greeting the proxy constructor
+/// sends, which is expected to be as long as
+/// `expected`, to a server accepting no method
+async fn greeting<P>(proxy: P, expected: &[u8]) -> Vec<u8>
+where
+    P: ProxyConstructor<Stream = TcpStream>,
+{
+    let server = MockServer::spawn(vec![Exchange::new(expected.len(), &[0x05, 0xFF])]).await;
+    assert!(proxy.connect(server.connect().await).await.is_err());
+
+    server.received().await.remove(0)
+}
+
+#[tokio::test]
+async fn greeting_bytes_match_each_auth_config() {
+    let destination = Destination::Ipv4Addr(Ipv4Addr::LOCALHOST);
+
+    // No authentication
+    let expected = [0x05, 0x01, 0x00];
+    let proxy = TcpNoAuth::new(destination.clone(), 80, timeouts());
+    assert_eq!(greeting(proxy, &expected).await, expected);
+
+    // Credentials, falling back to no authentication
+    let expected = [0x05, 0x02, 0x02, 0x00];
+    let mut proxy = TcpNoAuth::new(destination.clone(), 80, timeouts());
+    proxy.with_authentication("user", "pass");
+    assert_eq!(greeting(proxy, &expected).await, expected);
+
+    let proxy = TcpNoAuth::<TcpStream>::builder()
+        .destination(destination.clone())
+        .port(80)
+        .timeouts(timeouts())
+        .credentials("user", "pass")
+        .build()
+        .unwrap();
+    assert_eq!(greeting(proxy, &expected).await, expected);
+
+    // Credentials only
+    let expected = [0x05, 0x01, 0x02];
+    let proxy: Socks5<UserPass> =
+        Socks5::with_auth_method(destination, 80, timeouts(), UserPass::new("user", "pass"));
+    assert_eq!(greeting(proxy, &expected).await, expected);
+}
+
+#[tokio::test]
+async fn method_order_reorders_the_offered_methods() {
+    let destination = Destination::Ipv4Addr(Ipv4Addr::LOCALHOST);
+
+    // No authentication offered first
+    let expected = [0x05, 0x02, 0x00, 0x02];
+    let mut proxy = TcpNoAuth::new(destination.clone(), 80, timeouts());
+    proxy.with_authentication("user", "pass");
+    proxy.with_method_order(&[0x00, 0x02]);
+    assert_eq!(proxy.method_order(), &[0x00, 0x02]);
+    assert_eq!(greeting(proxy, &expected).await, expected);
+
+    // The methods not listed come last
+    let proxy = TcpNoAuth::<TcpStream>::builder()
+        .destination(destination.clone())
+        .port(80)
+        .timeouts(timeouts())
+        .credentials("user", "pass")
+        .method_order(&[0x00])
+        .build()
+        .unwrap();
+    assert_eq!(greeting(proxy, &expected).await, expected);
+
+    // A listed method that is not performed is never offered
+    let expected = [0x05, 0x01, 0x00];
+    let mut proxy = TcpNoAuth::new(destination, 80, timeouts());
+    proxy.with_method_order(&[0x02, 0x00]);
+    assert_eq!(greeting(proxy, &expected).await, expected);
+}
+
+#[tokio::test]
+async fn no_acceptable_methods_is_reported() {
+    let server = MockServer::spawn(vec![Exchange::new(3, &[0x05, 0xFF])]).await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let result = proxy.connect(server.connect().await).await;
+
+    // The server has not replied to a connection
+    // request, so that there is no reply code
+    let e = result.unwrap_err();
+    assert_eq!(e.reply_code(), None);
+    assert!(matches!(e.into_kind(), ErrorKind::NoAcceptableAuthMethods));
+}
+
+#[test]
+fn builder_validates_required_fields() {
+    let result = TcpNoAuth::<TcpStream>::builder()
+        .destination(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST))
+        .timeouts(timeouts())
+        .build();
+    assert!(matches!(result, Err(BuildError::MissingPort)));
+
+    let proxy = TcpNoAuth::<TcpStream>::builder()
+        .destination(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST))
+        .port(80)
+        .timeouts(timeouts())
+        .credentials("user", "pass")
+        .resolution(ResolutionPolicy::Local)
+        .build()
+        .unwrap();
+
+    assert_eq!(proxy.port(), 80);
+    assert_eq!(proxy.resolution(), ResolutionPolicy::Local);
+    assert!(matches!(
+        proxy.auth(),
+        AuthenticationKind::UsernamePassword { .. }
+    ));
+}
+
+#[tokio::test]
+async fn constructor_is_created_from_a_socket_addr() {
+    let addr: SocketAddr = "[2001:db8::1]:8080".parse().unwrap();
+    let proxy: TcpNoAuth = TcpNoAuth::to_socket_addr(addr, timeouts());
+    assert_eq!(
+        proxy.destination(),
+        &Destination::Ipv6Addr("2001:db8::1".parse().unwrap())
+    );
+    assert_eq!(proxy.port(), 8080);
+
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let addr: SocketAddr = "127.0.0.1:80".parse().unwrap();
+    let proxy = TcpNoAuth::to_socket_addr(addr, timeouts());
+    proxy.connect(server.connect().await).await.unwrap();
+
+    let received = server.received().await;
+    assert_eq!(received[1], [0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1, 0, 80]);
+}
+
+#[test]
+fn invalid_domain_name_is_rejected() {
+    let result = "not a domain".parse::<Destination>();
+    assert!(matches!(result, Err(DestinationError::InvalidDomainName)));
+}
+
+#[cfg(not(feature = "dns-validation"))]
+#[test]
+fn domain_name_is_checked_by_length_and_characters() {
+    assert!(Destination::hostname("proxied.test").is_ok());
+    assert!(Destination::hostname("_srv.proxied.test.").is_ok());
+
+    let long_label = "a".repeat(64);
+    for name in &["", "a..test", "-a.test", "a-.test", "a b.test", &long_label] {
+        assert_eq!(
+            Destination::hostname(name),
+            Err(DestinationError::InvalidDomainName)
+        );
+    }
+}
+
+#[cfg(feature = "idna")]
+#[test]
+fn internationalized_domain_name_is_converted_to_ascii() {
+    match Destination::hostname("münchen.de") {
+        Ok(Destination::DomainName(name)) => assert_eq!(name, "xn--mnchen-3ya.de"),
+        _ => panic!("the domain name has not been converted"),
+    }
+}
+
+#[tokio::test]
+async fn peeked_data_is_not_consumed() {
+    let mut reply = SOCKS5_SUCCESS.to_vec();
+    reply.extend_from_slice(b"hello");
+
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &reply),
+        Exchange::new(1, &[]),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+
+    let mut buf = [0; 5];
+    let peeked = stream.peek(&mut buf).await.unwrap();
+    assert_eq!(&buf[..peeked], &b"hello"[..peeked]);
+
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[test]
+fn socket_addr_is_split_into_destination_and_port() {
+    let (destination, port) = Destination::from_socket_addr("[2001:db8::1]:8080".parse().unwrap());
+    assert_eq!(
+        destination,
+        Destination::Ipv6Addr("2001:db8::1".parse().unwrap())
+    );
+    assert_eq!(port, 8080);
+}
+
+#[tokio::test]
+async fn reply_of_another_proxy_type_is_detected() {
+    // The replies of a Socks4 and an HTTP proxy server
+    for reply in [
+        &[0x00, 0x5b, 0, 0, 0, 0, 0, 0][..],
+        &b"HTTP/1.1 400 Bad Request\r\n\r\n"[..],
+    ] {
+        let server = MockServer::spawn(vec![Exchange::new(3, reply)]).await;
+
+        let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+        let result = proxy.connect(server.connect().await).await;
+        assert!(matches!(
+            result.map_err(Error::into_kind),
+            Err(ErrorKind::WrongProxyType)
+        ));
+    }
+}
+
+#[tokio::test]
+async fn connection_closed_after_the_greeting_is_detected() {
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+
+    // The server reads the greeting and hangs up
+    let server = MockServer::spawn(vec![Exchange::new(3, &[])]).await;
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(
+        result.map_err(Error::into_kind),
+        Err(ErrorKind::ConnectionClosedByProxy)
+    ));
+
+    let server = MockServer::spawn(vec![Exchange::new(5, &[])]).await;
+    let result = proxy.probe(server.connect().await).await;
+    assert!(matches!(result, Err(ErrorKind::ConnectionClosedByProxy)));
+
+    // A short reply is still a bad one
+    let server = MockServer::spawn(vec![Exchange::new(3, &[0x05])]).await;
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(
+        result.map_err(Error::into_kind),
+        Err(ErrorKind::BadBuffer)
+    ));
+}
+
+/// Records the hooks called
+/// on the connection attempts
+#[derive(Default)]
+struct RecordingObserver(Mutex<Vec<String>>);
+
+impl Observer<ErrorKind> for RecordingObserver {
+    fn on_connect_start(&self) {
+        self.0.lock().unwrap().push("start".to_owned());
+    }
+
+    fn on_connect_success(&self, _elapsed: Duration) {
+        self.0.lock().unwrap().push("success".to_owned());
+    }
+
+    fn on_connect_error(&self, error: &ErrorKind, _elapsed: Duration) {
+        self.0.lock().unwrap().push(format!("error: {}", error));
+    }
+}
+
+#[tokio::test]
+async fn observer_is_told_of_each_connection_attempt() {
+    let observer = Arc::new(RecordingObserver::default());
+    let mut proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    proxy.with_observer(observer.clone());
+
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+    proxy.connect(server.connect().await).await.unwrap();
+
+    let server = MockServer::spawn(vec![Exchange::new(3, &[])]).await;
+    proxy.connect(server.connect().await).await.unwrap_err();
+
+    assert_eq!(
+        *observer.0.lock().unwrap(),
+        [
+            "start",
+            "success",
+            "start",
+            "error: the proxy server has closed the connection",
+        ]
+    );
+}
+
+/// Resolves names from a fixed map only
+struct HostsResolver(HashMap<&'static str, Vec<IpAddr>>);
+
+#[async_trait::async_trait]
+impl Resolver for HostsResolver {
+    async fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        Ok(self.0.get(host).cloned().unwrap_or_default())
+    }
+}
+
+#[tokio::test]
+async fn local_resolution_uses_the_resolver_set() {
+    let addr = Ipv4Addr::new(192, 0, 2, 7);
+    let mut hosts = HashMap::new();
+    hosts.insert("proxied.test", vec![IpAddr::V4(addr)]);
+    let resolver: Arc<dyn Resolver> = Arc::new(HostsResolver(hosts));
+
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::<TcpStream>::builder()
+        .destination(Destination::hostname("proxied.test").unwrap())
+        .port(80)
+        .timeouts(timeouts())
+        .resolution(ResolutionPolicy::Local)
+        .resolver(resolver.clone())
+        .build()
+        .unwrap();
+    proxy.connect(server.connect().await).await.unwrap();
+
+    // The resolved address is sent instead of the name
+    let received = server.received().await;
+    assert_eq!(received[1], [0x05, 0x01, 0x00, 0x01, 192, 0, 2, 7, 0, 80]);
+
+    // A name the resolver does not know is unresolvable
+    let server = MockServer::spawn(vec![Exchange::new(3, &[0x05, 0x00])]).await;
+    let mut proxy = TcpNoAuth::new(
+        Destination::hostname("unknown.test").unwrap(),
+        80,
+        timeouts(),
+    );
+    proxy.with_resolution(ResolutionPolicy::Local);
+    proxy.with_resolver(resolver);
+
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(
+        result.map_err(Error::into_kind),
+        Err(ErrorKind::UnresolvableDestination)
+    ));
+}
+
+/// A resolver that never answers in time
+struct StalledResolver;
+
+#[async_trait::async_trait]
+impl Resolver for StalledResolver {
+    async fn resolve(&self, _host: &str) -> io::Result<Vec<IpAddr>> {
+        tokio::time::delay_for(Duration::from_secs(5)).await;
+        Ok(Vec::new())
+    }
+}
+
+#[tokio::test]
+async fn stalled_resolution_is_bounded_by_the_connecting_timeout() {
+    let server = MockServer::spawn(vec![Exchange::new(3, &[0x05, 0x00])]).await;
+
+    let timeouts = ConnectionTimeouts::new(
+        Duration::from_millis(100),
+        Duration::from_secs(2),
+        Duration::from_secs(2),
+    );
+    let mut proxy = TcpNoAuth::new(Destination::hostname("stalled.test").unwrap(), 80, timeouts);
+    proxy.with_resolution(ResolutionPolicy::Local);
+    proxy.with_resolver(Arc::new(StalledResolver));
+
+    let e = proxy.connect(server.connect().await).await.err().unwrap();
+    let e = e.into_kind();
+    assert!(matches!(
+        e,
+        ErrorKind::OperationTimeoutReached(TimeoutPhase::Connect)
+    ));
+    assert_eq!(e.to_string(), "connect timed out");
+}
+
+#[tokio::test]
+async fn ip_preference_selects_the_resolved_address() {
+    let v4 = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 7));
+    let v6 = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 7));
+    let addrs = [v6, v4];
+
+    assert_eq!(IpPreference::Any.select(&addrs), Some(v6));
+    assert_eq!(IpPreference::Ipv4First.select(&addrs), Some(v4));
+    assert_eq!(IpPreference::Ipv6First.select(&addrs), Some(v6));
+    assert_eq!(IpPreference::Ipv4Only.select(&[v6]), None);
+    assert_eq!(IpPreference::Ipv6First.select(&[v4]), Some(v4));
+
+    let mut hosts = HashMap::new();
+    hosts.insert("dual.test", addrs.to_vec());
+    hosts.insert("v6.test", vec![v6]);
+    let resolver: Arc<dyn Resolver> = Arc::new(HostsResolver(hosts));
+
+    // The IPv4 address is sent, albeit resolved second
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::<TcpStream>::builder()
+        .destination(Destination::hostname("dual.test").unwrap())
+        .port(80)
+        .timeouts(timeouts())
+        .resolution(ResolutionPolicy::Local)
+        .resolver(resolver.clone())
+        .ip_preference(IpPreference::Ipv4First)
+        .build()
+        .unwrap();
+    assert_eq!(proxy.ip_preference(), IpPreference::Ipv4First);
+    proxy.connect(server.connect().await).await.unwrap();
+
+    let received = server.received().await;
+    assert_eq!(received[1], [0x05, 0x01, 0x00, 0x01, 192, 0, 2, 7, 0, 80]);
+
+    // A name with no address of the only version accepted is unresolvable
+    let server = MockServer::spawn(vec![Exchange::new(3, &[0x05, 0x00])]).await;
+    let mut proxy = TcpNoAuth::new(Destination::hostname("v6.test").unwrap(), 80, timeouts());
+    proxy.with_resolution(ResolutionPolicy::Local);
+    proxy.with_resolver(resolver);
+    proxy.with_ip_preference(IpPreference::Ipv4Only);
+
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(
+        result.map_err(Error::into_kind),
+        Err(ErrorKind::UnresolvableDestination)
+    ));
+}
+
+#[tokio::test]
+async fn split_halves_are_used_concurrently() {
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+        Exchange::new(4, b"pong"),
+        Exchange::new(1, &[]),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+    let (mut read_half, mut write_half) = stream.into_split();
+
+    let writer = tokio::spawn(async move {
+        write_half.write_all(b"ping").await.unwrap();
+        write_half
+    });
+
+    let mut buf = [0; 4];
+    read_half.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"pong");
+
+    drop((read_half, writer.await.unwrap()));
+    assert_eq!(server.received().await[2], b"ping");
+}
+
+#[tokio::test]
+async fn stalled_subnegotiation_is_bounded_by_the_auth_timeout() {
+    // The server selects Username/Password,
+    // but never replies to the credentials
+    let server = MockServer::spawn(vec![
+        Exchange::new(4, &[0x05, 0x02]),
+        Exchange::new(1 + 1 + 4 + 1 + 4, &[]),
+        Exchange::new(1, &[]),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::<TcpStream>::builder()
+        .destination(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST))
+        .port(80)
+        .timeouts(timeouts())
+        .credentials("user", "pass")
+        .auth_timeout(Duration::from_millis(100))
+        .build()
+        .unwrap();
+    assert_eq!(proxy.auth_timeout(), Some(Duration::from_millis(100)));
+
+    let started = Instant::now();
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(
+        result.map_err(Error::into_kind),
+        Err(ErrorKind::OperationTimeoutReached(TimeoutPhase::Handshake))
+    ));
+
+    // The read timeout is much longer
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn displayed_destination_is_parsed_back() {
+    let destinations = [
+        Destination::Ipv4Addr(Ipv4Addr::new(192, 0, 2, 1)),
+        Destination::Ipv6Addr("2001:db8::1".parse().unwrap()),
+        Destination::hostname("example.com").unwrap(),
+    ];
+
+    for destination in &destinations {
+        let displayed = destination.to_string();
+        assert_eq!(displayed.parse::<Destination>().as_ref(), Ok(destination));
+    }
+
+    assert_eq!(destinations[1].to_string(), "[2001:db8::1]");
+}
+
+#[test]
+fn destination_literals_are_parsed_at_compile_time() {
+    const PROXIED: Destination = destination!("example.com.");
+    assert_eq!(PROXIED, "example.com.".parse().unwrap());
+
+    // Each literal is parsed into the same
+    // destination as the one parsed at runtime
+    let literals = [
+        (destination!("192.0.2.1"), "192.0.2.1"),
+        (destination!("::"), "::"),
+        (destination!("::1"), "::1"),
+        (destination!("2001:db8::1"), "2001:db8::1"),
+        (destination!("fe80::"), "fe80::"),
+        (destination!("1:2:3:4:5:6:7:8"), "1:2:3:4:5:6:7:8"),
+        (destination!("FFFF:0:0::ab:1"), "FFFF:0:0::ab:1"),
+        (
+            destination!("my-host_1.example.com"),
+            "my-host_1.example.com",
+        ),
+    ];
+
+    for (destination, literal) in &literals {
+        assert_eq!(Ok(destination), literal.parse::<Destination>().as_ref());
+    }
+}
+
+#[test]
+fn destinations_are_used_as_map_keys() {
+    let mut pool = HashMap::new();
+    pool.insert(Destination::hostname("example.com").unwrap(), 1);
+    pool.insert(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 2);
+
+    // A borrowed and an owned name are the same key
+    let borrowed = Destination::DomainName(Cow::Borrowed("example.com"));
+    assert_eq!(pool.get(&borrowed), Some(&1));
+    assert_eq!(
+        pool.get(&Destination::Ipv4Addr(Ipv4Addr::LOCALHOST)),
+        Some(&2)
+    );
+}
+
+#[tokio::test]
+async fn typed_user_pass_method_authenticates() {
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x02]),
+        Exchange::new(1 + 1 + 4 + 1 + 4, &[0x01, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let proxy: Socks5<UserPass> = Socks5::with_auth_method(
+        Destination::Ipv4Addr(Ipv4Addr::LOCALHOST),
+        80,
+        timeouts(),
+        UserPass::new("user", "pass"),
+    );
+    proxy.connect(server.connect().await).await.unwrap();
+
+    let received = server.received().await;
+    assert_eq!(received[0], [0x05, 0x01, 0x02]);
+    assert_eq!(received[1], b"\x01\x04user\x04pass");
+}
+
+/// A private method, exchanging a single
+/// byte as its subnegotiation
+struct Knock;
+
+#[async_trait::async_trait]
+impl AuthMethod for Knock {
+    fn method_id(&self) -> u8 {
+        0x80
+    }
+
+    async fn negotiate<S>(&self, stream: &mut S, _: &ConnectionTimeouts) -> Result<(), ErrorKind>
+    where
+        S: IOStream + Send,
+    {
+        stream.write_all(&[0x2A]).await?;
+
+        let mut reply = [0];
+        stream.read_exact(&mut reply).await?;
+        match reply {
+            [0x00] => Ok(()),
+            _ => Err(ErrorKind::AuthenticationFailed),
+        }
+    }
+}
+
+#[tokio::test]
+async fn custom_method_is_negotiated() {
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x80]),
+        Exchange::new(1, &[0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let proxy: Socks5<Knock> = Socks5::with_auth_method(
+        Destination::Ipv4Addr(Ipv4Addr::LOCALHOST),
+        80,
+        timeouts(),
+        Knock,
+    );
+    proxy.connect(server.connect().await).await.unwrap();
+
+    let received = server.received().await;
+    assert_eq!(received[0], [0x05, 0x01, 0x80]);
+    assert_eq!(received[1], [0x2A]);
+}
+
+#[tokio::test]
+async fn too_long_credentials_are_rejected_before_the_greeting() {
+    let server = MockServer::spawn(vec![Exchange::new(3, &[0x05, 0x02])]).await;
+
+    let username = "u".repeat(256);
+    let proxy: Socks5<UserPass> = Socks5::with_auth_method(
+        Destination::Ipv4Addr(Ipv4Addr::LOCALHOST),
+        80,
+        timeouts(),
+        UserPass::new(username, "pass"),
+    );
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(
+        result.map_err(Error::into_kind),
+        Err(ErrorKind::CredentialsTooLong)
+    ));
+
+    // Nothing has been sent
+    assert!(server.received().await.is_empty());
+}
+
+#[tokio::test]
+async fn subnegotiation_reply_split_in_parts_is_read_whole() {
+    // The version and the status of
+    // the reply arrive separately
+    let server = MockServer::spawn(vec![
+        Exchange::new(4, &[0x05, 0x02]),
+        Exchange::new(1 + 1 + 4 + 1 + 4, &[0x01]),
+        Exchange::new(0, &[0x00]).after(Duration::from_millis(50)),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let mut proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    proxy.with_authentication("user", "pass");
+    proxy.connect(server.connect().await).await.unwrap();
+}
+
+#[tokio::test]
+async fn keepalive_is_set_on_the_underlying_stream() {
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+
+    stream.set_keepalive(Some(Duration::from_secs(30))).unwrap();
+    assert_eq!(
+        stream.get_ref().keepalive().unwrap(),
+        Some(Duration::from_secs(30))
+    );
+
+    stream.set_keepalive(None).unwrap();
+    assert_eq!(stream.get_ref().keepalive().unwrap(), None);
+}
+
+#[tokio::test]
+async fn reset_tunnel_is_told_from_a_closed_one() {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // The server closes the first tunnel cleanly
+    // and aborts the second one (a zero linger
+    // makes closing send an RST)
+    tokio::spawn(async move {
+        for reset in &[false, true] {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 10];
+            stream.read_exact(&mut buf[..3]).await.unwrap();
+            stream.write_all(&[0x05, 0x00]).await.unwrap();
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&SOCKS5_SUCCESS).await.unwrap();
+
+            if *reset {
+                stream.set_linger(Some(Duration::from_secs(0))).unwrap();
+            }
+        }
+    });
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let mut buf = [0; 16];
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = proxy.connect(stream).await.unwrap();
+    assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+    assert_eq!(stream.last_error_kind(), None);
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = proxy.connect(stream).await.unwrap();
+    let e = stream.read(&mut buf).await.unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::ConnectionReset);
+    assert_eq!(
+        stream.last_error_kind(),
+        Some(io::ErrorKind::ConnectionReset)
+    );
+}
+
+#[tokio::test]
+async fn stream_is_shut_down_within_the_timeout() {
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+        Exchange::new(1, &[]),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+
+    stream.set_linger(Some(Duration::from_secs(1))).unwrap();
+    assert_eq!(
+        stream.get_ref().linger().unwrap(),
+        Some(Duration::from_secs(1))
+    );
+
+    stream
+        .shutdown_with_timeout(Duration::from_secs(1))
+        .await
+        .unwrap();
+
+    // The server sees the end of the stream
+    // instead of the byte it expects
+    assert_eq!(server.received().await.len(), 2);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn raw_fd_is_the_one_of_the_underlying_stream() {
+    use std::os::unix::io::AsRawFd;
+
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+
+    assert_eq!(stream.as_raw_fd(), stream.get_ref().as_raw_fd());
+}
+
+#[tokio::test]
+async fn banner_of_the_destination_is_read() {
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+        Exchange::new(0, b"220 smtp.test ESMTP\r\n").after(Duration::from_millis(50)),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+
+    // No more than the maximum is read, and
+    // the rest of the line is read next
+    assert_eq!(stream.read_initial(3).await.unwrap(), b"220");
+    assert_eq!(
+        stream.read_initial(512).await.unwrap(),
+        b" smtp.test ESMTP\r\n"
+    );
+
+    // The server has closed the tunnel
+    assert!(stream.read_initial(512).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn nodelay_is_restored_after_the_handshake() {
+    for &prior in &[false, true] {
+        let server = MockServer::spawn(vec![
+            Exchange::new(3, &[0x05, 0x00]),
+            Exchange::new(10, &SOCKS5_SUCCESS),
+        ])
+        .await;
+
+        let stream = server.connect().await;
+        stream.set_nodelay(prior).unwrap();
+
+        let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+        let stream = proxy.connect_with_nodelay(stream).await.unwrap();
+        assert_eq!(stream.get_ref().nodelay().unwrap(), prior);
+    }
+}
+
+#[tokio::test]
+async fn unboxed_connect_establishes_the_tunnel() {
+    fn assert_send<T: Send>(future: T) -> T {
+        future
+    }
+
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let future = assert_send(proxy.connect_unboxed(server.connect().await));
+    let stream = future.await.unwrap();
+    assert_eq!(stream.bound_endpoint().port, 0);
+
+    let received = server.received().await;
+    assert_eq!(received[1], [0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1, 0, 80]);
+}
+
+#[tokio::test]
+async fn mapped_constructor_yields_the_mapped_stream() {
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+        Exchange::new(0, b"220 ready\r\n").after(Duration::from_millis(50)),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts())
+        .map(|stream| stream.buffered());
+    assert_eq!(proxy.get_ref().port(), 80);
+
+    // The same constructor may be shared among tasks
+    let proxy = Arc::new(proxy);
+    let stream = server.connect().await;
+    let mut stream = tokio::spawn(async move { proxy.connect(stream).await.unwrap() })
+        .await
+        .unwrap();
+
+    let mut line = String::new();
+    stream.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "220 ready\r\n");
+}
+
+#[tokio::test]
+async fn closed_tunnel_is_not_alive() {
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+        Exchange::new(1, &[]),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+    assert!(stream.is_alive());
+
+    // The server closes the connection
+    // as soon as the script is over
+    stream.write_all(&[0]).await.unwrap();
+    server.received().await;
+
+    let mut alive = true;
+    for _ in 0..100 {
+        alive = stream.is_alive();
+        if !alive {
+            break;
+        }
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+    }
+    assert!(!alive);
+}
+
+#[tokio::test]
+async fn initial_data_is_written_after_the_reply() {
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+        Exchange::new(5, b"world"),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let mut stream = proxy
+        .connect_with_initial_data(server.connect().await, b"hello")
+        .await
+        .unwrap();
+
+    let mut buf = [0; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"world");
+
+    let received = server.received().await;
+    assert_eq!(received[2], b"hello");
+}
+
+#[tokio::test]
+async fn selected_auth_method_is_exposed_on_the_stream() {
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+    assert_eq!(stream.auth_method(), 0x00);
+
+    // The credentials user:pass
+    let server = MockServer::spawn(vec![
+        Exchange::new(4, &[0x05, 0x02]),
+        Exchange::new(11, &[0x01, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+    let mut proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    proxy.with_authentication("user", "pass");
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+    assert_eq!(stream.auth_method(), 0x02);
+}
+
+#[tokio::test]
+async fn domain_names_are_encoded_up_to_255_bytes() {
+    // The longest domain name that can be represented
+    let name = "a".repeat(255);
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(1 + 1 + 1 + 1 + 1 + 255 + 2, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::DomainName(name.clone().into()), 80, timeouts());
+    proxy.connect(server.connect().await).await.unwrap();
+
+    let received = server.received().await;
+    assert_eq!(received[1][..5], [0x05, 0x01, 0x00, 0x03, 255]);
+    assert_eq!(&received[1][5..260], name.as_bytes());
+    assert_eq!(received[1][260..], [0, 80]);
+
+    // A longer one is rejected with no request sent
+    let server =
+        MockServer::spawn(vec![Exchange::new(3, &[0x05, 0x00]), Exchange::new(1, &[])]).await;
+
+    let proxy = TcpNoAuth::new(
+        Destination::DomainName("a".repeat(256).into()),
+        80,
+        timeouts(),
+    );
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(
+        result.map_err(Error::into_kind),
+        Err(ErrorKind::DomainNameTooLong)
+    ));
+    assert_eq!(server.received().await.len(), 1);
+}
+
+#[tokio::test]
+async fn credentials_fall_back_to_no_authentication() {
+    // The server does not require authentication
+    let server = MockServer::spawn(vec![
+        Exchange::new(4, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let mut proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    proxy.with_authentication("user", "pass");
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+    assert_eq!(stream.auth_method(), 0x00);
+
+    // Both methods are offered, while
+    // the credentials are never sent
+    let received = server.received().await;
+    assert_eq!(received[0], [0x05, 0x02, 0x02, 0x00]);
+    assert_eq!(received[1], [0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1, 0, 80]);
+}
+
+#[test]
+fn constructor_is_parsed_with_try_from() {
+    let proxy = TcpNoAuth::<TcpStream>::try_from("example.com 443 8000:8000:8000").unwrap();
+    assert_eq!(
+        proxy.destination(),
+        &Destination::hostname("example.com").unwrap()
+    );
+    assert_eq!(proxy.port(), 443);
+
+    // The errors of parsing are printable
+    let error = TcpNoAuth::<TcpStream>::try_from("example.com port 8000:8000:8000")
+        .err()
+        .unwrap();
+    assert!(matches!(error, StrParsingError::InvalidPort));
+    assert_eq!(error.to_string(), "invalid port");
+
+    let error: Box<dyn std::error::Error> =
+        Box::new("example.com".parse::<TcpNoAuth>().err().unwrap());
+    assert_eq!(error.to_string(), "expected `destination port timeouts`");
+}
+
+#[test]
+fn bracketed_and_scoped_ipv6_destinations_are_parsed() {
+    let addr: Ipv6Addr = "2001:db8::1".parse().unwrap();
+    assert_eq!("[2001:db8::1]".parse(), Ok(Destination::Ipv6Addr(addr)));
+
+    // The zone is dropped, since it cannot be sent
+    let scoped: Ipv6Addr = "fe80::1".parse().unwrap();
+    assert_eq!("fe80::1%eth0".parse(), Ok(Destination::Ipv6Addr(scoped)));
+    assert_eq!("[fe80::1%eth0]".parse(), Ok(Destination::Ipv6Addr(scoped)));
+
+    // An empty zone is not a zone
+    assert_eq!(
+        "fe80::1%".parse::<Destination>(),
+        Err(DestinationError::InvalidDomainName)
+    );
+}
+
+#[tokio::test]
+async fn bind_command_is_sent_and_the_peer_is_accepted() {
+    // The server listens on 10.0.0.1:5000
+    // and the peer 192.0.2.1:4000 connects
+    let mut listening = SOCKS5_SUCCESS;
+    listening[4..8].copy_from_slice(&[10, 0, 0, 1]);
+    listening[8..10].copy_from_slice(&5000u16.to_be_bytes());
+    let mut connected = SOCKS5_SUCCESS;
+    connected[4..8].copy_from_slice(&[192, 0, 2, 1]);
+    connected[8..10].copy_from_slice(&4000u16.to_be_bytes());
+
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &listening),
+        Exchange::new(0, &connected),
+    ])
+    .await;
+
+    let mut proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    assert_eq!(proxy.command(), Command::TcpConnectionEstablishment);
+    proxy.with_command(Command::TcpPortBinding);
+
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+    assert_eq!(stream.command(), Command::TcpPortBinding);
+    assert_eq!(stream.bound_endpoint().port, 5000);
+
+    let peer = stream.accept().await.unwrap();
+    assert_eq!(peer.addr, BoundAddr::Ipv4(Ipv4Addr::new(192, 0, 2, 1)));
+    assert_eq!(peer.port, 4000);
+
+    // The second reply has already been consumed
+    assert!(matches!(
+        stream.accept().await,
+        Err(ErrorKind::NotSupported)
+    ));
+
+    // The command of the request
+    let received = server.received().await;
+    assert_eq!(received[1][1], 0x02);
+}
+
+#[tokio::test]
+async fn accept_waits_within_the_accept_timeout() {
+    let timeouts = ConnectionTimeouts::new(
+        Duration::from_secs(2),
+        Duration::from_secs(2),
+        Duration::from_millis(100),
+    );
+    let mut proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts);
+    proxy.with_command(Command::TcpPortBinding);
+    assert_eq!(proxy.accept_timeout(), Some(Duration::from_millis(100)));
+
+    // The peer connects later than the read timeout,
+    // but within the accept timeout
+    proxy.with_accept_timeout(Duration::from_secs(1));
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+        Exchange::new(0, &SOCKS5_SUCCESS).after(Duration::from_millis(300)),
+    ])
+    .await;
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+    assert!(stream.accept().await.is_ok());
+
+    // No peer connects at all
+    proxy.with_accept_timeout(Duration::from_millis(100));
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+        Exchange::new(1, &[]),
+    ])
+    .await;
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+    assert!(matches!(
+        stream.accept().await,
+        Err(ErrorKind::OperationTimeoutReached(TimeoutPhase::Handshake))
+    ));
+
+    // Part of the reply may have been consumed by
+    // the attempt timed out, so that it is not retried
+    assert!(matches!(
+        stream.accept().await,
+        Err(ErrorKind::NotSupported)
+    ));
+}
+
+#[tokio::test]
+async fn unspecified_bound_address_is_substituted_with_the_server_one() {
+    let server_ip = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1));
+    let bound = |addr, port| BoundEndpoint::new(addr, port);
+
+    // Only an unspecified address is substituted
+    let unspecified = bound(BoundAddr::Ipv6(Ipv6Addr::UNSPECIFIED), 5000);
+    assert_eq!(
+        unspecified.substitute_unspecified(server_ip),
+        bound(BoundAddr::Ipv4(Ipv4Addr::new(198, 51, 100, 1)), 5000)
+    );
+    for specified in [
+        bound(BoundAddr::Ipv4(Ipv4Addr::new(192, 0, 2, 1)), 5000),
+        bound(BoundAddr::Domain("relay.example".to_string()), 5000),
+    ] {
+        let substituted = specified.clone().substitute_unspecified(server_ip);
+        assert_eq!(substituted, specified);
+    }
+
+    // The server binds on all of its interfaces
+    let mut listening = SOCKS5_SUCCESS;
+    listening[8..10].copy_from_slice(&5000u16.to_be_bytes());
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &listening),
+        Exchange::new(1, &[]),
+    ])
+    .await;
+
+    let mut proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    proxy.with_command(Command::TcpPortBinding);
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+
+    // The reported endpoint is kept as it is
+    assert_eq!(
+        stream.bound_endpoint().addr,
+        BoundAddr::Ipv4(Ipv4Addr::UNSPECIFIED)
+    );
+    assert_eq!(
+        stream.reachable_bound_endpoint().unwrap(),
+        bound(BoundAddr::Ipv4(Ipv4Addr::LOCALHOST), 5000)
+    );
+}
+
+#[tokio::test]
+async fn accept_is_not_supported_after_connect() {
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let mut stream = proxy.connect(server.connect().await).await.unwrap();
+    assert!(matches!(
+        stream.accept().await,
+        Err(ErrorKind::NotSupported)
+    ));
+}
+
+#[tokio::test]
+async fn inner_stream_is_returned_with_no_buffered_bytes() {
+    // The reply and the first tunnel data
+    // are written by the server at once
+    let mut coalesced = SOCKS5_SUCCESS.to_vec();
+    coalesced.extend_from_slice(b"payload");
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &coalesced),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+    let (mut inner, buffered) = stream.into_inner_with_buffered();
+    assert!(buffered.is_empty());
+
+    let mut buf = [0; 7];
+    inner.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"payload");
+}
+
+#[tokio::test]
+async fn probe_returns_the_method_selected_by_the_server() {
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+
+    // The server requires `Username/Password`
+    let server = MockServer::spawn(vec![Exchange::new(5, &[0x05, 0x02])]).await;
+    let methods = proxy.probe(server.connect().await).await.unwrap();
+    assert_eq!(methods, [0x02]);
+
+    // Nothing but the greeting offering all methods is sent
+    let received = server.received().await;
+    assert_eq!(received, [vec![0x05, 0x03, 0x00, 0x01, 0x02]]);
+
+    // The server accepts none of the methods
+    let server = MockServer::spawn(vec![Exchange::new(5, &[0x05, 0xFF])]).await;
+    let methods = proxy.probe(server.connect().await).await.unwrap();
+    assert!(methods.is_empty());
+
+    // Not a Socks5 server
+    let server = MockServer::spawn(vec![Exchange::new(5, &[0x00, 0x5a])]).await;
+    let result = proxy.probe(server.connect().await).await;
+    assert!(matches!(result, Err(ErrorKind::WrongProxyType)));
+}
+
+#[tokio::test]
+async fn probe_reads_a_selection_split_across_segments() {
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+
+    let server = MockServer::spawn(vec![
+        Exchange::new(5, &[0x05]),
+        Exchange::new(0, &[0x00]).after(Duration::from_millis(50)),
+    ])
+    .await;
+    let methods = proxy.probe(server.connect().await).await.unwrap();
+    assert_eq!(methods, [0x00]);
+}
+
+#[tokio::test]
+async fn destinations_are_attempted_until_one_is_connected_to() {
+    // The first destination is unreachable
+    let host_unreachable = [0x05, 0x04, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+    let first = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &host_unreachable),
+    ])
+    .await;
+    let second = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let destinations = [
+        (Destination::Ipv4Addr(Ipv4Addr::new(10, 0, 0, 1)), 80),
+        (Destination::Ipv4Addr(Ipv4Addr::new(10, 0, 0, 2)), 80),
+    ];
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let mut proxies = vec![first.addr(), second.addr()].into_iter();
+    proxy
+        .connect_to_any(&destinations, || {
+            TcpStream::connect(proxies.next().unwrap())
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(first.received().await[1][4..8], [10, 0, 0, 1]);
+    assert_eq!(second.received().await[1][4..8], [10, 0, 0, 2]);
+
+    // The destination of the constructor is left as it was
+    assert_eq!(
+        proxy.destination(),
+        &Destination::Ipv4Addr(Ipv4Addr::LOCALHOST)
+    );
+
+    // The error of the last attempt is returned
+    // if no destination is connected to
+    let refused = async { Err(io::Error::from(io::ErrorKind::ConnectionRefused)) };
+    let mut refused = Some(refused);
+    let result = proxy
+        .connect_to_any(&destinations[1..], || refused.take().unwrap())
+        .await;
+    let e = result.unwrap_err();
+    assert_eq!(e.destination(), "10.0.0.2");
+    assert!(matches!(e.into_kind(), ErrorKind::IOError(_)));
+
+    // There is nothing to attempt at all
+    let result = proxy.connect_to_any(&[], || async { unreachable!() }).await;
+    assert!(matches!(
+        result.map_err(Error::into_kind),
+        Err(ErrorKind::IOError(_))
+    ));
+
+    // The attempts are dropped in the middle of one,
+    // which leaves the constructor untouched as well
+    let silent = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]).after(Duration::from_secs(5))
+    ])
+    .await;
+    let future = proxy.connect_to_any(&destinations, || TcpStream::connect(silent.addr()));
+    let result = tokio::time::timeout(Duration::from_millis(50), future).await;
+    assert!(result.is_err());
+    assert_eq!(
+        proxy.destination(),
+        &Destination::Ipv4Addr(Ipv4Addr::LOCALHOST)
+    );
+}
+
+#[tokio::test]
+async fn shared_constructor_connects_from_concurrent_tasks() {
+    let proxy = Arc::new(TcpNoAuth::new(
+        Destination::Ipv4Addr(Ipv4Addr::LOCALHOST),
+        80,
+        timeouts(),
+    ));
+
+    let mut tasks = Vec::new();
+    for _ in 0..2 {
+        let server = MockServer::spawn(vec![
+            Exchange::new(3, &[0x05, 0x00]),
+            Exchange::new(10, &SOCKS5_SUCCESS),
+        ])
+        .await;
+        let stream = server.connect().await;
+
+        // No lock is needed around the constructor
+        let proxy = Arc::clone(&proxy);
+        tasks.push(tokio::spawn(async move {
+            proxy.connect(stream).await.map(|_| server)
+        }));
+    }
+
+    for task in tasks {
+        let server = task.await.unwrap().unwrap();
+        assert_eq!(server.received().await[0], [0x05, 0x01, 0x00]);
+    }
+}
+
+#[tokio::test]
+async fn same_constructor_probes_and_then_connects() {
+    let probed = MockServer::spawn(vec![Exchange::new(5, &[0x05, 0x00])]).await;
+    let connected = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let methods = proxy.probe(probed.connect().await).await.unwrap();
+    assert_eq!(methods, [0x00]);
+
+    let stream = proxy.connect(connected.connect().await).await.unwrap();
+    assert_eq!(stream.auth_method(), 0x00);
+}
+
+#[tokio::test]
+async fn buffered_tunnel_reads_lines() {
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+        Exchange::new(0, b"220 ready\r\n221 bye\r\n"),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+    let mut stream = stream.buffered();
+
+    let mut line = String::new();
+    stream.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "220 ready\r\n");
+
+    line.clear();
+    stream.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "221 bye\r\n");
+}
+
+#[tokio::test]
+async fn reply_longer_than_the_maximum_is_rejected() {
+    // A reply with the longest domain name bound address
+    let mut reply = vec![0x05, 0x00, 0x00, 0x03, 255];
+    reply.extend_from_slice(&[b'a'; 255]);
+    reply.extend_from_slice(&[0, 80]);
+
+    // It fits the default maximum
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &reply),
+    ])
+    .await;
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    assert_eq!(proxy.max_reply_bytes(), DEFAULT_MAX_REPLY_BYTES);
+    proxy.connect(server.connect().await).await.unwrap();
+
+    // While it does not fit the maximum set,
+    // which an IPv4 bound address still does
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &reply),
+    ])
+    .await;
+    let mut proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    proxy.with_max_reply_bytes(10);
+    let result = proxy.connect(server.connect().await).await;
+    assert!(matches!(
+        result.map_err(Error::into_kind),
+        Err(ErrorKind::BadBuffer)
+    ));
+
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+    proxy.connect(server.connect().await).await.unwrap();
+}
+
+#[tokio::test]
+async fn proxy_servers_are_attempted_until_a_handshake_succeeds() {
+    // The first proxy server is unreachable
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let closed = listener.local_addr().unwrap();
+    drop(listener);
+
+    // The second one denies the request
+    let denied = [0x05, 0x02, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+    let denying = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &denied),
+    ])
+    .await;
+    let succeeding = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let proxies = [closed, denying.addr(), succeeding.addr()];
+    connect_via_any(&proxies, &proxy, &timeouts())
+        .await
+        .unwrap();
+
+    // The failures of all of them are listed otherwise
+    let e = connect_via_any(&proxies[..1], &proxy, &timeouts())
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(e.attempts.len(), 1);
+    assert_eq!(e.attempts[0].0, closed);
+    assert!(matches!(e.attempts[0].1, AttemptError::Unreachable(_)));
+    assert!(e.to_string().starts_with("all proxy servers failed: "));
+}
+
+#[test]
+fn port_is_typed_or_bare() {
+    let port: Port = "8080".parse().unwrap();
+    assert_eq!(port, Port(8080));
+    assert_eq!(port.to_string(), "8080");
+    assert!("65536".parse::<Port>().is_err());
+
+    // Both a typed port and a bare `u16` are accepted
+    let typed =
+        TcpNoAuth::<TcpStream>::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), port, timeouts());
+    let bare =
+        TcpNoAuth::<TcpStream>::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 8080, timeouts());
+    assert_eq!(typed.port(), bare.port());
+    assert_eq!(u16::from(port), typed.port());
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use async_proxy::clients::socks5::{Destination, TcpNoAuth};
+use async_proxy::clients::tls;
+use async_proxy::proxy::ProxyConstructor;
+use common::{timeouts, Exchange, MockServer, SOCKS5_SUCCESS};
+use std::io;
+use std::net::Ipv4Addr;
+
+#[tokio::test]
+async fn invalid_server_name_is_rejected_before_the_handshake() {
+    let server = MockServer::spawn(vec![Exchange::new(1, &[])]).await;
+
+    let result = tls::connect_through(server.connect().await, "not a host name").await;
+    let error = result.err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+    // Nothing has been sent to the server
+    assert!(server.received().await.iter().all(Vec::is_empty));
+}
+
+#[tokio::test]
+async fn handshake_is_performed_over_the_proxy_stream() {
+    // The destination replies to the ClientHello
+    // (whose record header is 5 bytes long)
+    // with a non-TLS response
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+        Exchange::new(5, b"HTTP/1.1 400 Bad Request\r\n\r\n"),
+    ])
+    .await;
+
+    let proxy = TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 443, timeouts());
+    let stream = proxy.connect(server.connect().await).await.unwrap();
+    assert!(tls::connect_through(stream, "example.com").await.is_err());
+
+    // The ClientHello is a TLS handshake record
+    let received = server.received().await;
+    assert_eq!(received[2][0], 0x16);
+}
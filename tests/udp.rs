@@ -0,0 +1,206 @@
+mod common;
+
+use async_proxy::clients::socks5::no_auth::ErrorKind;
+use async_proxy::clients::socks5::udp::{
+    decode_reply, encode_request, Datagram, Reassembler, UdpAssociate,
+};
+use async_proxy::clients::socks5::Destination;
+use async_proxy::proxy::{BoundAddr, ProxyConstructor};
+use common::{timeouts, Exchange, MockServer, SOCKS5_SUCCESS};
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::net::{TcpStream, UdpSocket};
+
+/// Builds a datagram from 10.0.0.1:53
+/// with the given FRAG byte and payload
+fn datagram(frag: u8, data: &[u8]) -> Vec<u8> {
+    let mut buf = vec![0x00, 0x00, frag, 0x01, 10, 0, 0, 1, 0, 53];
+    buf.extend_from_slice(data);
+    buf
+}
+
+#[test]
+fn fragmented_datagram_is_surfaced() {
+    let buf = datagram(0x01, b"partial");
+    let result = Datagram::parse(&buf).unwrap().unfragmented();
+    assert!(matches!(result, Err(ErrorKind::FragmentedDatagram(0x01))));
+
+    let buf = datagram(0x00, b"whole");
+    let datagram = Datagram::parse(&buf).unwrap().unfragmented().unwrap();
+    assert_eq!(
+        datagram.destination,
+        Destination::Ipv4Addr(Ipv4Addr::new(10, 0, 0, 1))
+    );
+    assert_eq!(datagram.port, 53);
+    assert_eq!(datagram.data, b"whole");
+}
+
+#[test]
+fn fragments_are_reassembled() {
+    let mut reassembler = Reassembler::new();
+
+    let first = datagram(0x01, b"hello ");
+    let last = datagram(0x82, b"world");
+    assert!(reassembler.push(Datagram::parse(&first).unwrap()).is_none());
+
+    let (destination, port, data) = reassembler.push(Datagram::parse(&last).unwrap()).unwrap();
+    assert_eq!(
+        destination,
+        Destination::Ipv4Addr(Ipv4Addr::new(10, 0, 0, 1))
+    );
+    assert_eq!(port, 53);
+    assert_eq!(data, b"hello world");
+
+    // A fragment out of order abandons the sequence
+    let second = datagram(0x02, b"lost");
+    let last = datagram(0x83, b"lost");
+    assert!(reassembler.push(Datagram::parse(&first).unwrap()).is_none());
+    assert!(reassembler.push(Datagram::parse(&last).unwrap()).is_none());
+    assert!(reassembler
+        .push(Datagram::parse(&second).unwrap())
+        .is_none());
+}
+
+#[test]
+fn encoded_request_is_decoded_back() {
+    let destinations = [
+        Destination::Ipv4Addr(Ipv4Addr::new(10, 0, 0, 1)),
+        Destination::Ipv6Addr("2001:db8::1".parse().unwrap()),
+        Destination::hostname("example.com").unwrap(),
+    ];
+
+    for destination in &destinations {
+        let mut buf = Vec::new();
+        encode_request(destination, 53, b"query", &mut buf).unwrap();
+
+        // RSV and FRAG are zeroes
+        assert_eq!(buf[..3], [0x00, 0x00, 0x00]);
+
+        let (decoded, port, data) = decode_reply(&buf).unwrap();
+        assert_eq!(&decoded, destination);
+        assert_eq!(port, 53);
+        assert_eq!(data, b"query");
+    }
+
+    // The address type matches the destination
+    let mut buf = Vec::new();
+    encode_request(&destinations[0], 53, b"query", &mut buf).unwrap();
+    assert_eq!(buf, datagram(0x00, b"query"));
+
+    // A domain name too long to be encoded
+    // leaves nothing in the buffer
+    let mut buf = b"kept".to_vec();
+    let destination = Destination::DomainName("a".repeat(256).into());
+    let result = encode_request(&destination, 53, b"query", &mut buf);
+    assert!(matches!(result, Err(ErrorKind::DomainNameTooLong)));
+    assert_eq!(buf, b"kept");
+
+    // A fragment is not taken for a whole datagram
+    let buf = datagram(0x81, b"partial");
+    let result = decode_reply(&buf);
+    assert!(matches!(result, Err(ErrorKind::FragmentedDatagram(0x81))));
+}
+
+#[tokio::test]
+async fn association_defaults_to_the_wildcard_client_addr() {
+    // The relay is 10.0.0.1:5000
+    let mut reply = SOCKS5_SUCCESS;
+    reply[4..8].copy_from_slice(&[10, 0, 0, 1]);
+    reply[8..10].copy_from_slice(&5000u16.to_be_bytes());
+
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &reply),
+    ])
+    .await;
+
+    let associate = UdpAssociate::<TcpStream>::new(timeouts());
+    let association = associate.connect(server.connect().await).await.unwrap();
+    assert_eq!(
+        association.relay().addr,
+        BoundAddr::Ipv4(Ipv4Addr::new(10, 0, 0, 1))
+    );
+    assert_eq!(association.relay().port, 5000);
+
+    // The UDP ASSOCIATE command with 0.0.0.0:0
+    let received = server.received().await;
+    assert_eq!(received[1], [0x05, 0x03, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+}
+
+#[tokio::test]
+async fn association_is_restricted_to_the_client_addr() {
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &SOCKS5_SUCCESS),
+    ])
+    .await;
+
+    let client_addr: SocketAddr = "192.0.2.1:4000".parse().unwrap();
+    let mut associate = UdpAssociate::<TcpStream>::new(timeouts());
+    associate.with_client_addr(client_addr);
+    assert_eq!(
+        associate.client_addr(),
+        (&Destination::Ipv4Addr(Ipv4Addr::new(192, 0, 2, 1)), 4000)
+    );
+    associate.connect(server.connect().await).await.unwrap();
+
+    let received = server.received().await;
+    assert_eq!(
+        received[1],
+        [0x05, 0x03, 0x00, 0x01, 192, 0, 2, 1, 0x0F, 0xA0]
+    );
+}
+
+#[tokio::test]
+async fn datagrams_are_sent_and_received_through_the_relay() {
+    let mut relay = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let relay_port = relay.local_addr().unwrap().port();
+
+    // The relay address is unspecified, meaning
+    // the address of the proxy server itself
+    let mut reply = SOCKS5_SUCCESS;
+    reply[8..10].copy_from_slice(&relay_port.to_be_bytes());
+
+    let server = MockServer::spawn(vec![
+        Exchange::new(3, &[0x05, 0x00]),
+        Exchange::new(10, &reply),
+    ])
+    .await;
+
+    let associate = UdpAssociate::<TcpStream>::new(timeouts());
+    let association = associate.connect(server.connect().await).await.unwrap();
+    let stream = association
+        .bind("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(stream.relay(), relay.local_addr().unwrap());
+
+    let destination = Destination::Ipv4Addr(Ipv4Addr::new(10, 0, 0, 1));
+    let sent = stream.send_to(b"ping", &destination, 53).await.unwrap();
+    assert_eq!(sent, 4);
+
+    let mut buf = [0; 64];
+    let (len, client_addr) = relay.recv_from(&mut buf).await.unwrap();
+    assert_eq!(buf[..len], datagram(0x00, b"ping")[..]);
+    assert_eq!(client_addr, stream.local_addr().unwrap());
+
+    // A datagram from anywhere but the relay
+    // and a fragment are both dropped
+    let mut stranger = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    stranger
+        .send_to(&datagram(0x00, b"spoofed"), client_addr)
+        .await
+        .unwrap();
+    relay
+        .send_to(&datagram(0x01, b"partial"), client_addr)
+        .await
+        .unwrap();
+    relay
+        .send_to(&datagram(0x00, b"pong"), client_addr)
+        .await
+        .unwrap();
+
+    let (data, from, port) = stream.recv_from().await.unwrap();
+    assert_eq!(data, b"pong");
+    assert_eq!(from, destination);
+    assert_eq!(port, 53);
+}
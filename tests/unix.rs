@@ -0,0 +1,45 @@
+#![cfg(unix)]
+
+mod common;
+
+use async_proxy::clients::socks5::{Destination, TcpNoAuth};
+use async_proxy::proxy::ProxyConstructor;
+use common::{timeouts, SOCKS5_SUCCESS};
+use std::net::Ipv4Addr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+#[tokio::test]
+async fn socks5_is_spoken_over_a_unix_stream() {
+    let (client, mut server) = UnixStream::pair().unwrap();
+
+    // Playing the proxy server on the other end
+    let handle = tokio::spawn(async move {
+        let mut greeting = [0; 3];
+        server.read_exact(&mut greeting).await.unwrap();
+        server.write_all(&[0x05, 0x00]).await.unwrap();
+
+        let mut request = [0; 10];
+        server.read_exact(&mut request).await.unwrap();
+        server.write_all(&SOCKS5_SUCCESS).await.unwrap();
+
+        // Echoing the first bytes of the tunnel
+        let mut tunnel = [0; 5];
+        server.read_exact(&mut tunnel).await.unwrap();
+        server.write_all(&tunnel).await.unwrap();
+
+        request
+    });
+
+    let proxy: TcpNoAuth<UnixStream> =
+        TcpNoAuth::new(Destination::Ipv4Addr(Ipv4Addr::LOCALHOST), 80, timeouts());
+    let mut stream = proxy.connect(client).await.unwrap();
+
+    stream.write_all(b"hello").await.unwrap();
+    let mut buf = [0; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+
+    let request = handle.await.unwrap();
+    assert_eq!(request, [0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1, 0, 80]);
+}